@@ -1,3 +1,5 @@
 fn main() {
-    slint_build::compile("ui/app-window.slint").expect("Slint build failed");
+    // the translations of the @tr strings, see lang/README.md
+    let config = slint_build::CompilerConfiguration::new().with_bundled_translations("lang");
+    slint_build::compile_with_config("ui/app-window.slint", config).expect("Slint build failed");
 }
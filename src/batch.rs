@@ -0,0 +1,571 @@
+//! Headless batch mode: one json label spec per input line, one json result per output line.
+//!
+//! ```text
+//! {"text": "Box 1", "font": "DejaVu Sans", "size": 80, "copies": 2}
+//! {"text": "Box 2", "font": "/usr/share/fonts/truetype/dejavu/DejaVuSans.ttf", "host": "http://192.168.0.20"}
+//! {"text": "Box 3", "font": "DejaVu Sans", "image": "logo.png"}
+//! ```
+//!
+//! `--printers <name>,<name>` shares the lines out to several printer profiles which print
+//! in parallel, see [`run_batch_parallel`].
+//!
+//! With `--report <file>` a summary of all lines is saved after the batch, as csv or as
+//! json depending on the extension, see [`write_report`].
+//!
+//! `--text-file <file>` prints a single label with the text of a file, see [`text_file_spec`].
+//!
+//! A missing `image` of a line is replaced by a placeholder instead of failing the line,
+//! see [`load_batch_image`].
+
+use std::fs;
+use std::io::{self, BufRead, Write};
+use std::path::{Path, PathBuf};
+use std::collections::VecDeque;
+use std::sync::{Arc, Mutex, RwLock};
+use std::thread;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use image::{DynamicImage, GrayImage, Luma};
+use serde::{Deserialize, Serialize};
+
+use crate::elements::{ImageElement, LabelElement, TextElement, compose, compose_dithered};
+use crate::error::LabelError;
+use crate::fonts::FontEntry;
+use crate::printer::{HttpTransport, LabelTransport, check_print_length, transport, verify_head_width};
+use crate::profile::PrinterProfile;
+use crate::render::{Overflow, TextOptions, load_source_image, sanitize_label_text};
+use crate::LABEL_WIDTH;
+
+/// one label of a batch
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct BatchSpec {
+    pub text: String,
+    /// font display name or path of a font file
+    pub font: String,
+    /// font size in pixels
+    pub size: f32,
+    /// if not 0, the size is chosen so this many lines fill the label height
+    pub fit_lines: usize,
+    /// what happens to text wider than the label
+    pub overflow: Overflow,
+    /// printer address, the default printer if missing
+    pub host: Option<String>,
+    /// largest request body the printer accepts, in bytes
+    pub max_request_size: Option<usize>,
+    pub copies: u32,
+    /// image file drawn after the text, a placeholder if it is missing
+    pub image: Option<String>,
+}
+
+impl Default for BatchSpec {
+    fn default() -> Self {
+        BatchSpec { text: String::new(), font: String::new(), size: TextOptions::default().size, fit_lines: 0, overflow: Overflow::default(), host: None, max_request_size: None, copies: 1, image: None }
+    }
+}
+
+/// outcome of one batch line
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct BatchResult {
+    /// line number in the input, starting at 1
+    pub line: usize,
+    /// end of the processing in seconds since 1970-01-01 UTC
+    pub time: u64,
+    /// printer of a parallel batch, see `run_batch_parallel`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub printer: Option<String>,
+    pub ok: bool,
+    /// printed length in pixels
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub length: Option<u32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+/// path of the font given by display name or file path
+pub fn resolve_font(font: &str, fonts: &[FontEntry]) -> Option<String> {
+    if let Some(entry) = fonts.iter().find(|entry| entry.display_name == font) {
+        return Some(entry.path.clone());
+    }
+    Path::new(font).is_file().then(|| font.to_string())
+}
+
+static FALLBACK_IMAGE: RwLock<Option<PathBuf>> = RwLock::new(None);
+
+/// use the image file at `path` for missing batch images, `None` for the built in placeholder
+pub fn set_fallback_image(path: Option<PathBuf>) {
+    *FALLBACK_IMAGE.write().unwrap_or_else(|e| e.into_inner()) = path;
+}
+
+/// built in placeholder for a missing image: a crossed square box of the label height
+pub fn placeholder_image(height: u32) -> DynamicImage {
+    let size = height.max(1);
+    let border = (size / 24).max(1);
+    let img = GrayImage::from_fn(size, size, |x, y| {
+        let edge = x < border || y < border || x >= size - border || y >= size - border;
+        let diagonal = x.abs_diff(y) < border || (x + y + 1).abs_diff(size) < border;
+        Luma([if edge || diagonal { 0 } else { 255 }])
+    });
+    DynamicImage::ImageLuma8(img)
+}
+
+/// the image at `path` for a label of `height` rows. a missing file is logged and replaced
+/// by the image set with `set_fallback_image`, or by `placeholder_image` if that can't be
+/// loaded either, so a large batch keeps running. other errors are returned
+pub fn load_batch_image(path: &str, height: u32) -> Result<DynamicImage, LabelError> {
+    if Path::new(path).exists() {
+        return load_source_image(Path::new(path), height);
+    }
+    let fallback = FALLBACK_IMAGE.read().unwrap_or_else(|e| e.into_inner()).clone();
+    if let Some(fallback) = fallback {
+        match load_source_image(&fallback, height) {
+            Ok(img) => {
+                eprintln!("Image not found: {}, using {}", path, fallback.display());
+                return Ok(img);
+            }
+            Err(e) => eprintln!("Fallback image {} not loaded: {}", fallback.display(), e),
+        }
+    }
+    eprintln!("Image not found: {}, using the placeholder", path);
+    Ok(placeholder_image(height))
+}
+
+/// the printer of a spec, `printer` with the host and limit of the spec if it names them
+fn spec_transport(spec: &BatchSpec, printer: Arc<dyn LabelTransport>) -> Arc<dyn LabelTransport> {
+    if spec.host.is_none() && spec.max_request_size.is_none() {
+        return printer;
+    }
+    let settings = match printer.as_http() {
+        Some(http) => http.clone(),
+        // a limit alone doesn't apply to another kind of printer
+        None if spec.host.is_none() => return printer,
+        None => HttpTransport::default(),
+    };
+    Arc::new(HttpTransport {
+        base_url: spec.host.clone().unwrap_or(settings.base_url),
+        max_request_size: spec.max_request_size.or(settings.max_request_size),
+        ..settings
+    })
+}
+
+/// render the label of a spec for the printer of `profile`, returns the image and the
+/// label length
+pub fn render_spec(spec: &BatchSpec, fonts: &[FontEntry], profile: &PrinterProfile) -> Result<(GrayImage, u32), LabelError> {
+    let (width, height) = (LABEL_WIDTH as u32, profile.head_height);
+    let font_path = resolve_font(&spec.font, fonts).ok_or_else(|| LabelError::Font(format!("Font not found: {}", spec.font)))?;
+    let options = TextOptions { size: spec.size, fit_lines: spec.fit_lines, overflow: spec.overflow, ..TextOptions::default() };
+    let (text, _) = TextElement::new(&spec.text, &font_path)?.with_options(options).fit_to_width(width, height)?;
+    let clip = text.vertical_clip(width, height);
+    if clip.is_clipped() {
+        eprintln!("Text is vertically clipped, {} rows above and {} rows below the label are lost", clip.above, clip.below);
+    }
+    let mut elements: Vec<Box<dyn LabelElement>> = vec![Box::new(text)];
+    Ok(match &spec.image {
+        Some(path) => {
+            elements.push(Box::new(ImageElement::new(load_batch_image(path, height)?, 0, 0)));
+            compose_dithered(&elements, width, height)
+        }
+        None => compose(&elements, width, height),
+    })
+}
+
+/// render a label for the printer of `profile` and upload it, returns the label length.
+/// the printing itself is left to the caller
+pub fn render_and_upload(spec: &BatchSpec, fonts: &[FontEntry], profile: &PrinterProfile) -> Result<u32, LabelError> {
+    render_and_upload_on(spec, fonts, spec_transport(spec, transport()).as_ref(), profile)
+}
+
+/// like `render_and_upload` with the printer `printer`
+fn render_and_upload_on(spec: &BatchSpec, fonts: &[FontEntry], printer: &dyn LabelTransport, profile: &PrinterProfile) -> Result<u32, LabelError> {
+    let (img, used_len) = render_spec(spec, fonts, profile)?;
+    verify_head_width(printer, img.height())?;
+    printer.upload(&profile.pack(&img), &Default::default(), &mut |_| {})?;
+    Ok(used_len)
+}
+
+/// render, upload and print one label spec given as json for the printer of `profile`
+pub fn process_line(line: &str, fonts: &[FontEntry], profile: &PrinterProfile) -> Result<u32, LabelError> {
+    let spec: BatchSpec = serde_json::from_str(line)?;
+    process_spec(&spec, fonts, profile)
+}
+
+/// render, upload and print one label spec for the printer of `profile` with the active transport
+pub fn process_spec(spec: &BatchSpec, fonts: &[FontEntry], profile: &PrinterProfile) -> Result<u32, LabelError> {
+    process_spec_on(spec, fonts, transport(), profile)
+}
+
+/// like `process_spec` with the printer `printer`, a host in the spec still wins
+pub fn process_spec_on(spec: &BatchSpec, fonts: &[FontEntry], printer: Arc<dyn LabelTransport>, profile: &PrinterProfile) -> Result<u32, LabelError> {
+    let printer = spec_transport(spec, printer);
+    let length = render_and_upload_on(spec, fonts, printer.as_ref(), profile)?;
+    check_print_length(length)?;
+    for _ in 0..spec.copies.max(1) {
+        printer.print(length)?;
+    }
+    Ok(length)
+}
+
+/// largest accepted text file in bytes, a label can't show more anyway
+pub const MAX_TEXT_FILE_BYTES: u64 = 16 * 1024;
+
+/// read the label text from a UTF-8 file, line endings are normalized and the trailing
+/// line break is removed
+pub fn read_text_file(path: &Path) -> Result<String, LabelError> {
+    let size = fs::metadata(path)?.len();
+    if size > MAX_TEXT_FILE_BYTES {
+        return Err(LabelError::InvalidInput(format!("{} has {} bytes, at most {} are accepted", path.display(), size, MAX_TEXT_FILE_BYTES)));
+    }
+    let text = String::from_utf8(fs::read(path)?)
+        .map_err(|e| LabelError::InvalidInput(format!("{} is no UTF-8 text, invalid byte at {}", path.display(), e.utf8_error().valid_up_to())))?;
+    Ok(sanitize_label_text(&text).trim_end_matches('\n').to_string())
+}
+
+/// the spec of a label with the text of a file, several lines are fitted to the label height
+pub fn text_file_spec(path: &Path, font: &str) -> Result<BatchSpec, LabelError> {
+    let text = read_text_file(path)?;
+    let lines = text.lines().count();
+    Ok(BatchSpec { text, font: font.to_string(), fit_lines: if lines > 1 { lines } else { 0 }, ..BatchSpec::default() })
+}
+
+/// process every non empty input line for the printer of `profile` and write one json
+/// result per line. returns all results for a report
+pub fn run_batch(input: impl BufRead, mut output: impl Write, fonts: &[FontEntry], profile: &PrinterProfile) -> io::Result<Vec<BatchResult>> {
+    let mut results = Vec::new();
+    for (i, line) in input.lines().enumerate() {
+        let line = line?;
+        if line.trim().is_empty() {
+            continue;
+        }
+        let result = batch_result(i + 1, None, process_line(&line, fonts, profile));
+        writeln!(output, "{}", serde_json::to_string(&result)?)?;
+        output.flush()?;
+        results.push(result);
+    }
+    Ok(results)
+}
+
+/// the result of the input line `line`, timed now
+fn batch_result(line: usize, printer: Option<&str>, outcome: Result<u32, LabelError>) -> BatchResult {
+    let time = SystemTime::now().duration_since(UNIX_EPOCH).map_or(0, |since| since.as_secs());
+    let printer = printer.map(str::to_string);
+    match outcome {
+        Ok(length) => BatchResult { line, time, printer, ok: true, length: Some(length), error: None },
+        Err(e) => BatchResult { line, time, printer, ok: false, length: None, error: Some(e.to_string()) },
+    }
+}
+
+/// labels printed and failed by one printer of a parallel batch
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PrinterSummary {
+    pub name: String,
+    pub printed: usize,
+    pub failed: usize,
+}
+
+/// like `run_batch`, but the lines are shared out to `printers` (profile and transport), one
+/// worker thread per printer takes the next line from a common queue as soon as it is
+/// free. the results are written as they come in and are returned in input order,
+/// together with a summary per printer
+pub fn run_batch_parallel(input: impl BufRead, output: impl Write + Send, fonts: &[FontEntry], printers: &[(PrinterProfile, Arc<dyn LabelTransport>)]) -> io::Result<(Vec<BatchResult>, Vec<PrinterSummary>)> {
+    let mut queue = VecDeque::new();
+    for (i, line) in input.lines().enumerate() {
+        let line = line?;
+        if !line.trim().is_empty() {
+            queue.push_back((i + 1, line));
+        }
+    }
+    let queue = Mutex::new(queue);
+    let output = Mutex::new(output);
+    let results = Mutex::new(Vec::new());
+    let summaries = thread::scope(|scope| {
+        let workers: Vec<_> = printers
+            .iter()
+            .map(|(profile, printer)| {
+                let (queue, output, results) = (&queue, &output, &results);
+                scope.spawn(move || -> io::Result<PrinterSummary> {
+                    let name = &profile.name;
+                    let mut summary = PrinterSummary { name: name.clone(), ..PrinterSummary::default() };
+                    loop {
+                        // the lock is released before the label is processed
+                        let next = queue.lock().unwrap_or_else(|e| e.into_inner()).pop_front();
+                        let Some((line, text)) = next else { break };
+                        let outcome = serde_json::from_str::<BatchSpec>(&text)
+                            .map_err(LabelError::from)
+                            .and_then(|spec| process_spec_on(&spec, fonts, printer.clone(), profile));
+                        let result = batch_result(line, Some(name), outcome);
+                        if result.ok { summary.printed += 1 } else { summary.failed += 1 }
+                        {
+                            let mut output = output.lock().unwrap_or_else(|e| e.into_inner());
+                            writeln!(output, "{}", serde_json::to_string(&result)?)?;
+                            output.flush()?;
+                        }
+                        results.lock().unwrap_or_else(|e| e.into_inner()).push(result);
+                    }
+                    Ok(summary)
+                })
+            })
+            .collect();
+        workers
+            .into_iter()
+            .map(|worker| worker.join().unwrap_or_else(|_| Err(io::Error::other("batch worker panicked"))))
+            .collect::<io::Result<Vec<_>>>()
+    })?;
+    let mut results = results.into_inner().unwrap_or_else(|e| e.into_inner());
+    results.sort_by_key(|result| result.line);
+    Ok((results, summaries))
+}
+
+/// the results as csv with a header line: `line,time,printer,status,length,error`
+pub fn report_csv(results: &[BatchResult]) -> String {
+    let mut csv = String::from("line,time,printer,status,length,error\n");
+    for result in results {
+        let status = if result.ok { "ok" } else { "failed" };
+        let length = result.length.map(|length| length.to_string()).unwrap_or_default();
+        let error = csv_field(result.error.as_deref().unwrap_or(""));
+        let printer = csv_field(result.printer.as_deref().unwrap_or(""));
+        csv.push_str(&format!("{},{},{},{},{},{}\n", result.line, result.time, printer, status, length, error));
+    }
+    csv
+}
+
+/// quote a csv field with a separator, quote or line break
+fn csv_field(value: &str) -> String {
+    if value.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", value.replace('"', "\"\""))
+    } else {
+        value.to_string()
+    }
+}
+
+/// save the results of a batch for auditing, as json array for a `.json` file and as
+/// csv (see `report_csv`) for every other file
+pub fn write_report(results: &[BatchResult], path: &Path) -> Result<(), LabelError> {
+    let json = path.extension().is_some_and(|ext| ext.eq_ignore_ascii_case("json"));
+    let report = if json { serde_json::to_string_pretty(results)? } else { report_csv(results) };
+    fs::write(path, report)?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::printer::tests::TransportGuard;
+    use crate::printer::{set_transport, Endpoints, UploadOutcome};
+    use crate::render::PackingOptions;
+    use std::sync::Mutex;
+    use std::sync::atomic::AtomicBool;
+
+    #[test]
+    fn every_line_gets_a_json_result() {
+        let font = crate::render::tests::TEST_FONT;
+        if !Path::new(font).is_file() {
+            return;
+        }
+        // `run_batch` prints with the active transport
+        let _transport = crate::printer::tests::lock_transport();
+        let (base_url, requests) = crate::printer::tests::mock_printer(|_| (200, "OK".to_string()));
+        let input = format!(
+            "{}\n\n{}\n",
+            serde_json::json!({"text": "Box 1", "font": font, "size": 60, "copies": 2, "host": base_url}),
+            serde_json::json!({"text": "Box 2", "font": "No Such Font", "host": base_url}),
+        );
+        let mut output = Vec::new();
+        run_batch(input.as_bytes(), &mut output, &[], &PrinterProfile::default()).unwrap();
+        let lines: Vec<BatchResult> = String::from_utf8(output).unwrap().lines().map(|line| serde_json::from_str(line).unwrap()).collect();
+        assert_eq!(lines.len(), 2);
+        assert!(lines[0].ok && lines[0].length.is_some_and(|length| length > 0) && lines[0].error.is_none());
+        // the empty line is skipped but counted
+        assert_eq!((lines[1].line, lines[1].ok, lines[1].length), (3, false, None));
+        assert!(lines[1].error.as_ref().unwrap().contains("No Such Font"));
+        let prints = requests.lock().unwrap().iter().filter(|request| request.line.starts_with("GET /print")).count();
+        assert_eq!(prints, 2);
+    }
+
+    /// keeps the sizes of the uploads and the print lengths
+    #[derive(Default)]
+    struct CountingTransport {
+        uploads: Mutex<Vec<usize>>,
+        prints: Mutex<Vec<u32>>,
+    }
+
+    impl LabelTransport for CountingTransport {
+        fn upload(&self, data: &[u8], _cancel: &AtomicBool, _progress: &mut dyn FnMut(usize)) -> Result<UploadOutcome, LabelError> {
+            self.uploads.lock().unwrap().push(data.len());
+            Ok(UploadOutcome::Complete)
+        }
+
+        fn print(&self, length: u32) -> Result<(), LabelError> {
+            self.prints.lock().unwrap().push(length);
+            Ok(())
+        }
+    }
+
+    fn configured_printer() -> HttpTransport {
+        let endpoints = Endpoints { upload: "/api/upload".to_string(), print: "/api/print".to_string(), status: "/api/status".to_string() };
+        HttpTransport { endpoints, max_request_size: Some(512), ..HttpTransport::new("http://10.0.0.1") }
+    }
+
+    #[test]
+    fn a_label_is_rendered_and_packed_for_the_profile() {
+        let font = crate::render::tests::TEST_FONT;
+        if !Path::new(font).is_file() {
+            return;
+        }
+        let profile = PrinterProfile { head_height: 64, packing: PackingOptions { pad_rows: true, lsb_first: true }, ..PrinterProfile::default() };
+        let spec = BatchSpec { text: "Box 1".to_string(), font: font.to_string(), size: 40.0, copies: 2, ..BatchSpec::default() };
+        let (img, length) = render_spec(&spec, &[], &profile).unwrap();
+        assert_eq!(img.dimensions(), (LABEL_WIDTH as u32, 64));
+        let _transport = TransportGuard::new();
+        let printer = Arc::new(CountingTransport::default());
+        set_transport(printer.clone());
+        assert_eq!(process_line(&serde_json::to_string(&spec).unwrap(), &[], &profile).unwrap(), length);
+        // 8 bytes per column
+        assert_eq!(*printer.uploads.lock().unwrap(), vec![LABEL_WIDTH * 8]);
+        assert_eq!(*printer.prints.lock().unwrap(), vec![length, length]);
+    }
+
+    #[test]
+    fn a_host_of_a_line_keeps_the_printer_settings() {
+        let spec = BatchSpec { host: Some("http://10.0.0.2".to_string()), ..BatchSpec::default() };
+        let printer = spec_transport(&spec, Arc::new(configured_printer()));
+        let http = printer.as_http().unwrap();
+        assert_eq!(http, &HttpTransport { base_url: "http://10.0.0.2".to_string(), ..configured_printer() });
+    }
+
+    #[test]
+    fn a_limit_of_a_line_only_changes_the_limit() {
+        let spec = BatchSpec { max_request_size: Some(200), ..BatchSpec::default() };
+        let printer = spec_transport(&spec, Arc::new(configured_printer()));
+        assert_eq!(printer.as_http().unwrap(), &HttpTransport { max_request_size: Some(200), ..configured_printer() });
+        // another kind of printer stays the printer of the line
+        let counting: Arc<dyn LabelTransport> = Arc::new(CountingTransport::default());
+        assert!(Arc::ptr_eq(&spec_transport(&spec, counting.clone()), &counting));
+    }
+
+    #[test]
+    fn a_two_line_text_file_is_rendered_on_two_lines() {
+        let dir = crate::fonts::tests::test_dir("text_file");
+        let path = dir.join("label.txt");
+        fs::write(&path, "Box 12\r\nShelf B\n").unwrap();
+        let font = crate::render::tests::TEST_FONT;
+        let spec = text_file_spec(&path, font).unwrap();
+        assert_eq!((spec.text.as_str(), spec.fit_lines), ("Box 12\nShelf B", 2));
+        if Path::new(font).is_file() {
+            let (img, length) = render_spec(&spec, &[], &PrinterProfile::default()).unwrap();
+            let inked_rows: Vec<u32> = (0..img.height()).filter(|&y| (0..length).any(|x| img.get_pixel(x, y)[0] < 128)).collect();
+            let half = img.height() / 2;
+            assert!(inked_rows.iter().any(|&y| y < half - 8) && inked_rows.iter().any(|&y| y > half + 8), "{:?}", inked_rows);
+        }
+        let latin1 = dir.join("latin1.txt");
+        fs::write(&latin1, b"Gr\xf6\xdfe").unwrap();
+        assert!(matches!(read_text_file(&latin1), Err(LabelError::InvalidInput(msg)) if msg.contains("byte at 2")));
+        let large = dir.join("large.txt");
+        fs::write(&large, vec![b'a'; MAX_TEXT_FILE_BYTES as usize + 1]).unwrap();
+        assert!(matches!(read_text_file(&large), Err(LabelError::InvalidInput(_))));
+    }
+
+    #[test]
+    fn a_mixed_batch_is_reported_per_line() {
+        let font = crate::render::tests::TEST_FONT;
+        if !Path::new(font).is_file() {
+            return;
+        }
+        let input = format!(
+            "{}\nnot json\n{}\n",
+            serde_json::json!({"text": "Box 1", "font": font, "size": 60}),
+            serde_json::json!({"text": "Box 2", "font": "No Such Font"}),
+        );
+        let profile = PrinterProfile { name: "office".to_string(), ..PrinterProfile::default() };
+        let printer: Arc<dyn LabelTransport> = Arc::new(CountingTransport::default());
+        let (results, _) = run_batch_parallel(input.as_bytes(), Vec::new(), &[], &[(profile, printer)]).unwrap();
+        let csv = report_csv(&results);
+        let lines: Vec<&str> = csv.lines().collect();
+        assert_eq!(lines.len(), 4);
+        assert_eq!(lines[0], "line,time,printer,status,length,error");
+        let length = results[0].length.unwrap();
+        assert_eq!(lines[1], format!("1,{},office,ok,{},", results[0].time, length));
+        assert!(lines[2].starts_with(&format!("2,{},office,failed,,", results[1].time)));
+        assert!(lines[3].contains(",failed,,") && lines[3].contains("No Such Font"));
+        assert!(results.iter().all(|result| result.time > 0));
+        // a json report has the same rows
+        let dir = crate::fonts::tests::test_dir("batch_report");
+        write_report(&results, &dir.join("report.json")).unwrap();
+        let saved: Vec<BatchResult> = serde_json::from_str(&fs::read_to_string(dir.join("report.json")).unwrap()).unwrap();
+        assert_eq!(saved, results);
+        write_report(&results, &dir.join("report.csv")).unwrap();
+        assert_eq!(fs::read_to_string(dir.join("report.csv")).unwrap(), csv);
+        assert_eq!(csv_field("a \"b\", c"), "\"a \"\"b\"\", c\"");
+    }
+
+    #[test]
+    fn the_lines_are_shared_out_to_two_printers() {
+        let font = crate::render::tests::TEST_FONT;
+        if !Path::new(font).is_file() {
+            return;
+        }
+        // a print takes a while, so the other printer takes the next lines
+        let slow = |line: &str| {
+            if line.starts_with("GET /print") {
+                thread::sleep(std::time::Duration::from_millis(50));
+            }
+            (200, "OK".to_string())
+        };
+        let (mock_a, requests_a) = crate::printer::tests::mock_printer(slow);
+        let (mock_b, requests_b) = crate::printer::tests::mock_printer(slow);
+        let printers: Vec<(PrinterProfile, Arc<dyn LabelTransport>)> = [("a", mock_a), ("b", mock_b)]
+            .into_iter()
+            .map(|(name, base_url)| (PrinterProfile { name: name.to_string(), ..PrinterProfile::default() }, Arc::new(HttpTransport::new(&base_url)) as Arc<dyn LabelTransport>))
+            .collect();
+        let mut input: String = (1..=8).map(|n| format!("{}\n", serde_json::json!({"text": format!("Box {}", n), "font": font, "size": 40}))).collect();
+        input.push_str("{\"text\": \"Box 9\", \"font\": \"No Such Font\"}\n");
+        let mut output = Vec::new();
+        let (results, summaries) = run_batch_parallel(input.as_bytes(), &mut output, &[], &printers).unwrap();
+        assert_eq!(results.iter().map(|result| result.line).collect::<Vec<_>>(), (1..=9).collect::<Vec<_>>());
+        assert_eq!(String::from_utf8(output).unwrap().lines().count(), 9);
+        let printed: usize = summaries.iter().map(|summary| summary.printed).sum();
+        let failed: usize = summaries.iter().map(|summary| summary.failed).sum();
+        assert_eq!((printed, failed), (8, 1));
+        // both printers got labels, every label was printed by the printer of its result
+        for (summary, requests) in summaries.iter().zip([requests_a, requests_b]) {
+            let prints = requests.lock().unwrap().iter().filter(|request| request.line.starts_with("GET /print")).count();
+            let of_printer = results.iter().filter(|result| result.ok && result.printer.as_deref() == Some(summary.name.as_str())).count();
+            assert!(summary.printed > 0, "{:?}", summaries);
+            assert_eq!((prints, of_printer), (summary.printed, summary.printed));
+        }
+    }
+
+    #[test]
+    fn a_missing_image_is_replaced_by_the_placeholder() {
+        let dir = crate::fonts::tests::test_dir("fallback_image");
+        let missing = dir.join("missing.png");
+        let missing = missing.to_str().unwrap();
+        // the built in placeholder is a crossed box of the label height
+        set_fallback_image(None);
+        let img = load_batch_image(missing, 96).unwrap().to_luma8();
+        assert_eq!(img.dimensions(), (96, 96));
+        assert_eq!((img.get_pixel(0, 50)[0], img.get_pixel(48, 48)[0], img.get_pixel(20, 48)[0]), (0, 0, 255));
+        // a configured image replaces it, an existing image is loaded as it is
+        let fallback = dir.join("fallback.png");
+        GrayImage::from_pixel(10, 20, Luma([128u8])).save(&fallback).unwrap();
+        set_fallback_image(Some(fallback.clone()));
+        assert_eq!(load_batch_image(missing, 96).unwrap().to_luma8(), GrayImage::from_pixel(10, 20, Luma([128u8])));
+        let existing = dir.join("existing.png");
+        GrayImage::from_pixel(30, 5, Luma([0u8])).save(&existing).unwrap();
+        assert_eq!(load_batch_image(existing.to_str().unwrap(), 96).unwrap().to_luma8().dimensions(), (30, 5));
+        // a missing fallback falls back to the placeholder, an unreadable image is still an error
+        set_fallback_image(Some(dir.join("gone.png")));
+        assert_eq!(load_batch_image(missing, 64).unwrap().to_luma8().dimensions(), (64, 64));
+        fs::write(&existing, b"no image").unwrap();
+        assert!(load_batch_image(existing.to_str().unwrap(), 96).is_err());
+        set_fallback_image(None);
+
+        // the row of a batch still renders
+        let font = crate::render::tests::TEST_FONT;
+        if !Path::new(font).is_file() {
+            return;
+        }
+        let spec = BatchSpec { text: "Box".to_string(), font: font.to_string(), size: 40.0, image: Some(missing.to_string()), ..BatchSpec::default() };
+        let (label, _) = render_spec(&spec, &[], &PrinterProfile::default()).unwrap();
+        assert!(label.pixels().any(|pixel| pixel[0] == 0));
+    }
+}
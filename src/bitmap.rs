@@ -0,0 +1,160 @@
+//! Ready made bitmaps of other tools, sent to the printer without any rendering.
+
+use std::fs;
+use std::path::Path;
+
+use image::{GrayImage, Luma, imageops};
+
+use crate::LABEL_WIDTH;
+use crate::error::LabelError;
+use crate::printer::{check_print_length, transport, verify_head_width};
+use crate::render::{PackingOptions, get_bitmap_data_with, unpack_bitmap_data};
+
+/// a bitmap ready for the upload
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PackedBitmap {
+    /// packed label of `LABEL_WIDTH` columns, the columns after the bitmap are blank
+    pub data: Vec<u8>,
+    /// columns of the bitmap, the length to print
+    pub length: u32,
+}
+
+/// load a bitmap for a head of `height` dots. `.bin` and `.raw` files hold already packed
+/// data (continuous, msb first like `get_bitmap_data`) and are repacked with `packing`, every
+/// other file is read as an image (pbm, pgm, png, ...), its pixels darker than 50 % are
+/// printed. the image must be exactly `height` pixels high and at most `LABEL_WIDTH` wide
+pub fn load_bitmap(path: &Path, height: u32, packing: &PackingOptions) -> Result<PackedBitmap, LabelError> {
+    let raw = path.extension().is_some_and(|ext| ext.eq_ignore_ascii_case("bin") || ext.eq_ignore_ascii_case("raw"));
+    if raw {
+        return pack_raw(fs::read(path)?, height, packing);
+    }
+    let img = image::open(path)?.into_luma8();
+    pack_image(&img, height, packing)
+}
+
+/// check the packed bytes of a raw file (continuous, msb first) and repack them onto a
+/// blank label with `packing`
+pub fn pack_raw(data: Vec<u8>, height: u32, packing: &PackingOptions) -> Result<PackedBitmap, LabelError> {
+    let bits = data.len() * 8;
+    if height == 0 || !bits.is_multiple_of(height as usize) {
+        return Err(LabelError::InvalidInput(format!("{} bytes are no whole number of columns with {} dots", data.len(), height)));
+    }
+    let length = bits / height as usize;
+    if length > LABEL_WIDTH {
+        return Err(LabelError::InvalidInput(format!("bitmap has {} columns, at most {} fit on the label", length, LABEL_WIDTH)));
+    }
+    let img = unpack_bitmap_data(&data, height as usize, length, &PackingOptions::default());
+    pack_image(&img, height, packing)
+}
+
+/// check the size of a bitmap image and pack it onto a blank label
+pub fn pack_image(img: &GrayImage, height: u32, packing: &PackingOptions) -> Result<PackedBitmap, LabelError> {
+    let (width, img_height) = img.dimensions();
+    if img_height != height {
+        return Err(LabelError::InvalidInput(format!("bitmap is {} pixels high, the print head has {} dots", img_height, height)));
+    }
+    if width == 0 || width as usize > LABEL_WIDTH {
+        return Err(LabelError::InvalidInput(format!("bitmap has {} columns, 1 to {} fit on the label", width, LABEL_WIDTH)));
+    }
+    let mut label = GrayImage::from_pixel(LABEL_WIDTH as u32, height, Luma([255]));
+    imageops::replace(&mut label, img, 0, 0);
+    Ok(PackedBitmap { data: get_bitmap_data_with(&label, height as usize, LABEL_WIDTH, packing), length: width })
+}
+
+/// upload a bitmap file with the active transport and print it `copies` times
+pub fn print_bitmap_file(path: &Path, height: u32, packing: &PackingOptions, copies: u32) -> Result<u32, LabelError> {
+    let bitmap = load_bitmap(path, height, packing)?;
+    check_print_length(bitmap.length)?;
+    let transport = transport();
+    verify_head_width(transport.as_ref(), height)?;
+    transport.upload(&bitmap.data, &Default::default(), &mut |_| {})?;
+    for _ in 0..copies.max(1) {
+        transport.print(bitmap.length)?;
+    }
+    Ok(bitmap.length)
+}
+
+/// bytes per line of `hex_preview`
+const HEX_LINE_BYTES: usize = 16;
+
+/// hex dump of the first and the last `edge` bytes of packed data for firmware debugging,
+/// 16 bytes per line after their offset. short data is shown completely
+pub fn hex_preview(data: &[u8], edge: usize) -> String {
+    let mut lines = Vec::new();
+    if data.len() <= 2 * edge {
+        hex_lines(&mut lines, 0, data);
+    } else {
+        hex_lines(&mut lines, 0, &data[..edge]);
+        let tail = data.len() - edge;
+        lines.push(format!("... {} bytes ...", tail - edge));
+        hex_lines(&mut lines, tail, &data[tail..]);
+    }
+    lines.join("\n")
+}
+
+/// the dump lines of `bytes`, which start at offset `start`
+fn hex_lines(lines: &mut Vec<String>, start: usize, bytes: &[u8]) {
+    for (i, line) in bytes.chunks(HEX_LINE_BYTES).enumerate() {
+        let hex: Vec<String> = line.iter().map(|b| format!("{:02x}", b)).collect();
+        lines.push(format!("{:06x}: {}", start + i * HEX_LINE_BYTES, hex.join(" ")));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::render::get_bitmap_data;
+
+    /// a diagonal line of `width` columns
+    fn diagonal(width: u32, height: u32) -> GrayImage {
+        GrayImage::from_fn(width, height, |x, y| Luma([if x % height == y { 0 } else { 255 }]))
+    }
+
+    #[test]
+    fn raw_data_is_repacked_like_an_image() {
+        let img = diagonal(30, 12);
+        let raw = get_bitmap_data(&img, 12, 30);
+        for packing in [PackingOptions::default(), PackingOptions { pad_rows: true, lsb_first: true }] {
+            let packed = pack_raw(raw.clone(), 12, &packing).unwrap();
+            assert_eq!(packed, pack_image(&img, 12, &packing).unwrap());
+            assert_eq!(packed.length, 30);
+        }
+    }
+
+    #[test]
+    fn raw_data_of_partial_columns_is_refused() {
+        assert!(matches!(pack_raw(vec![0; 5], 12, &PackingOptions::default()), Err(LabelError::InvalidInput(_))));
+        assert!(matches!(pack_raw(vec![0; 3], 0, &PackingOptions::default()), Err(LabelError::InvalidInput(_))));
+    }
+
+    #[test]
+    fn a_small_pbm_is_loaded_and_checked_against_the_head() {
+        let dir = crate::fonts::tests::test_dir("pbm");
+        let path = dir.join("label.pbm");
+        fs::write(&path, "P1\n4 3\n1 0 0 1\n0 1 1 0\n1 0 0 1\n").unwrap();
+        let rows = ["1001", "0110", "1001"];
+        let expected = GrayImage::from_fn(4, 3, |x, y| Luma([if rows[y as usize].as_bytes()[x as usize] == b'1' { 0 } else { 255 }]));
+        let packing = PackingOptions::default();
+        let bitmap = load_bitmap(&path, 3, &packing).unwrap();
+        assert_eq!(bitmap.length, 4);
+        assert_eq!(bitmap, pack_image(&expected, 3, &packing).unwrap());
+        // the pixels come back from the packed label
+        let label = unpack_bitmap_data(&bitmap.data, 3, LABEL_WIDTH, &packing);
+        assert_eq!(imageops::crop_imm(&label, 0, 0, 4, 3).to_image(), expected);
+        assert!(label.enumerate_pixels().all(|(x, _, pixel)| x < 4 || pixel[0] == 255));
+        assert!(matches!(load_bitmap(&path, 12, &packing), Err(LabelError::InvalidInput(_))));
+    }
+
+    #[test]
+    fn the_hex_preview_shows_offsets_and_both_ends() {
+        let data: Vec<u8> = (0..20).collect();
+        assert_eq!(hex_preview(&data, 10), "000000: 00 01 02 03 04 05 06 07 08 09 0a 0b 0c 0d 0e 0f\n000010: 10 11 12 13");
+        assert_eq!(hex_preview(&data, 3), "000000: 00 01 02\n... 14 bytes ...\n000011: 11 12 13");
+        let long: Vec<u8> = vec![0xab; 1000];
+        let lines: Vec<String> = hex_preview(&long, 20).lines().map(str::to_string).collect();
+        assert_eq!(lines.len(), 5);
+        assert_eq!(lines[2], "... 960 bytes ...");
+        assert!(lines[3].starts_with("0003d4: ab"));
+        assert_eq!(hex_preview(&[], 8), "");
+    }
+}
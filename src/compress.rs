@@ -0,0 +1,116 @@
+//! Run length compression of the packed bitmap for firmwares with a compressed raster
+//! mode. Labels with much white space upload a lot faster this way.
+
+use serde::{Deserialize, Serialize};
+
+use crate::error::LabelError;
+
+/// compression of the packed bitmap before the upload
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Compression {
+    /// the packed bytes as they are
+    #[default]
+    None,
+    /// PackBits as in tiff, see `pack_bits`
+    PackBits,
+}
+
+impl Compression {
+    /// name in the query of the print command, `None` without compression
+    pub fn name(self) -> Option<&'static str> {
+        match self {
+            Compression::None => None,
+            Compression::PackBits => Some("packbits"),
+        }
+    }
+
+    /// the bytes to upload for `data`
+    pub fn apply(self, data: &[u8]) -> Vec<u8> {
+        match self {
+            Compression::None => data.to_vec(),
+            Compression::PackBits => pack_bits(data),
+        }
+    }
+}
+
+/// PackBits compression: a header byte `n` of 0..=127 is followed by `n + 1` literal
+/// bytes, a header of -1..=-127 (as i8) by one byte which is repeated `1 - n` times
+pub fn pack_bits(data: &[u8]) -> Vec<u8> {
+    let mut out = Vec::with_capacity(data.len() / 2);
+    let mut pos = 0;
+    while pos < data.len() {
+        let run = data[pos..].iter().take(128).take_while(|&&b| b == data[pos]).count();
+        if run >= 2 {
+            out.push((1 - run as i32) as i8 as u8);
+            out.push(data[pos]);
+            pos += run;
+            continue;
+        }
+        // literal bytes up to the next run of at least 3, a run of 2 costs as much as a literal
+        let start = pos;
+        while pos < data.len() && pos - start < 128 {
+            if pos + 2 < data.len() && data[pos] == data[pos + 1] && data[pos] == data[pos + 2] {
+                break;
+            }
+            pos += 1;
+        }
+        out.push((pos - start - 1) as u8);
+        out.extend_from_slice(&data[start..pos]);
+    }
+    out
+}
+
+/// the bytes of a `pack_bits` stream
+pub fn unpack_bits(data: &[u8]) -> Result<Vec<u8>, LabelError> {
+    let mut out = Vec::with_capacity(data.len() * 2);
+    let mut pos = 0;
+    while pos < data.len() {
+        let header = data[pos] as i8;
+        pos += 1;
+        match header {
+            0..=127 => {
+                let count = header as usize + 1;
+                let literal = data.get(pos..pos + count).ok_or_else(|| LabelError::InvalidInput(format!("PackBits literal of {} bytes is cut off", count)))?;
+                out.extend_from_slice(literal);
+                pos += count;
+            }
+            -128 => {}
+            _ => {
+                let byte = *data.get(pos).ok_or_else(|| LabelError::InvalidInput("PackBits run is cut off".to_string()))?;
+                out.extend(std::iter::repeat_n(byte, (1 - header as i32) as usize));
+                pos += 1;
+            }
+        }
+    }
+    Ok(out)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn packbits_round_trips_to_the_original_bytes() {
+        let sparse: Vec<u8> = (0..2000).map(|i| if i % 300 < 5 { 0x3c } else { 0 }).collect();
+        let noise: Vec<u8> = (0..700u32).map(|i| (i.wrapping_mul(2654435761) >> 13) as u8).collect();
+        let runs_of_two: Vec<u8> = (0..300).map(|i| (i / 2) as u8).collect();
+        for data in [Vec::new(), vec![7], vec![7, 7], sparse.clone(), noise, runs_of_two, vec![0xff; 129]] {
+            let packed = pack_bits(&data);
+            assert_eq!(unpack_bits(&packed).unwrap(), data);
+            assert_eq!(Compression::PackBits.apply(&data), packed);
+        }
+        // white space shrinks a lot, a run is at most 128 bytes
+        assert!(pack_bits(&sparse).len() < sparse.len() / 20);
+        assert_eq!(pack_bits(&[0xff; 129]), vec![0x81, 0xff, 0x00, 0xff]);
+        assert_eq!(Compression::None.apply(&sparse), sparse);
+    }
+
+    #[test]
+    fn a_cut_off_stream_is_an_error() {
+        assert!(matches!(unpack_bits(&[0x02, 1, 2]), Err(LabelError::InvalidInput(_))));
+        assert!(matches!(unpack_bits(&[0xfe]), Err(LabelError::InvalidInput(_))));
+        // -128 is skipped
+        assert_eq!(unpack_bits(&[0x80, 0x00, 9]).unwrap(), vec![9]);
+    }
+}
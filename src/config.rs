@@ -0,0 +1,381 @@
+//! User settings, persisted as json in the config directory of the OS.
+
+use std::fs;
+use std::path::PathBuf;
+use std::time::Duration;
+
+use serde::{Deserialize, Serialize};
+
+use crate::elements::{DateStamp, ElementKind, TicketCounter};
+use crate::error::LabelError;
+use crate::export::OverwritePolicy;
+use crate::profile::PrinterProfile;
+use crate::render::{Overflow, TEXT_BASELINE};
+use crate::transform::RegistrationMark;
+
+/// settings which survive a restart of the app
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Config {
+    /// ask for a confirmation with a summary before printing
+    pub confirm_print: bool,
+    /// size and position of the main window when it was closed
+    pub window: Option<WindowGeometry>,
+    /// language tag ("en", "de", ... or "system") for the font names in the dropdown
+    pub font_name_language: String,
+    /// language of the user interface ("en", "de", ...), "system" for the locale of the OS
+    pub ui_language: String,
+    /// color scheme of the window: "system", "light" or "dark"
+    pub ui_theme: String,
+    /// blank columns fed after every print for tearing off the label, 0 = none
+    pub feed_after_print: u32,
+    /// element offsets are snapped to a grid of this many pixels, shown over the preview. 0 = off
+    pub grid_size: u32,
+    /// show the exact printed bits in the preview instead of the smooth anti-aliased text
+    pub exact_preview: bool,
+    /// show the preview in the physical size of the label instead of one pixel per dot
+    pub true_size_preview: bool,
+    /// measured pixels per inch of the monitor for the true size preview, the nominal
+    /// resolution of the os is used if missing
+    pub screen_dpi: Option<f32>,
+    /// blank columns in front of every label, 0 = none
+    pub lead_in: u32,
+    /// the label length is rounded up to a multiple of this many columns, 0 = no rounding
+    pub length_multiple: u32,
+    /// largest request body the printer firmware accepts in bytes, no limit if missing
+    pub max_request_size: Option<usize>,
+    /// seconds the font scan may take before it stops with the fonts found so far, 0 = no limit
+    pub font_scan_seconds: u64,
+    /// display names of the fonts listed first
+    pub favorite_fonts: Vec<String>,
+    /// display name of the font selected on start, the first font if missing
+    pub default_font: Option<String>,
+    /// the printers to choose from, a default profile is used if empty
+    pub profiles: Vec<PrinterProfile>,
+    /// name of the selected profile, the first one if missing or unknown
+    pub active_profile: Option<String>,
+    /// ticket number printed after the text
+    pub counter: TicketCounter,
+    /// date and time of the label creation
+    pub date_stamp: DateStamp,
+    /// mark for the gap sensor of die-cut labels
+    pub registration_mark: RegistrationMark,
+    /// pngs are saved here with generated names instead of asking for a file, asks if missing
+    pub export_folder: Option<PathBuf>,
+    /// replaces missing images of batch labels, a crossed box if missing
+    pub fallback_image: Option<PathBuf>,
+    /// what an export to an existing file name does
+    pub overwrite_policy: OverwritePolicy,
+    /// glyph coverage above which a text pixel is printed, see `TextOptions::threshold`
+    pub glyph_threshold: f32,
+    /// what happens to text wider than the label
+    pub text_overflow: Overflow,
+    /// baseline of unfitted text in pixels from the top
+    pub text_baseline: f32,
+    /// render a test text on start and report an empty result
+    pub self_test: bool,
+    /// show the panel for sending raw requests to the printer
+    pub developer_mode: bool,
+    /// labels and tape printed since the statistics were reset
+    pub lifetime_stats: PrintStats,
+    /// labels and tape printed since the app was started, not saved
+    #[serde(skip)]
+    pub session_stats: PrintStats,
+    /// left to right order of the label elements, see `ElementKind::complete_order`
+    pub element_order: Vec<ElementKind>,
+    /// the label is this many head heights tall and printed band by band, see `print_bands`
+    pub label_bands: u32,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Config { confirm_print: true, window: None, font_name_language: "en".to_string(), ui_language: "system".to_string(), ui_theme: "system".to_string(), feed_after_print: 0, length_multiple: 0, lead_in: 0, label_bands: 1, exact_preview: false, true_size_preview: false, screen_dpi: None, grid_size: 0, max_request_size: None, font_scan_seconds: 10, favorite_fonts: Vec::new(), default_font: None, profiles: Vec::new(), active_profile: None, counter: TicketCounter::default(), date_stamp: DateStamp::default(), registration_mark: RegistrationMark::default(), export_folder: None, fallback_image: None, overwrite_policy: OverwritePolicy::default(), glyph_threshold: 0.5, text_overflow: Overflow::default(), text_baseline: TEXT_BASELINE, self_test: false, developer_mode: false, lifetime_stats: PrintStats::default(), session_stats: PrintStats::default(), element_order: ElementKind::ALL.to_vec() }
+    }
+}
+
+/// window position and size in physical pixels
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct WindowGeometry {
+    pub x: i32,
+    pub y: i32,
+    pub width: u32,
+    pub height: u32,
+}
+
+impl WindowGeometry {
+    /// smallest restored window size
+    const MIN_WIDTH: u32 = 200;
+    const MIN_HEIGHT: u32 = 100;
+
+    /// keep the window reachable on the monitors `screens`, the primary one first. the window
+    /// stays on the monitor showing most of it and is shrunk and moved to fit on it, a window
+    /// off all monitors (e.g. on a removed one) goes back to the origin of the primary monitor.
+    /// without known monitors only a position left of or above the origin is reset. a
+    /// collapsed window gets a usable size again
+    pub fn clamped(&self, screens: &[WindowGeometry]) -> WindowGeometry {
+        let (width, height) = (self.width.max(Self::MIN_WIDTH), self.height.max(Self::MIN_HEIGHT));
+        let Some(primary) = screens.first() else {
+            return WindowGeometry { x: self.x.max(0), y: self.y.max(0), width, height };
+        };
+        let screen = screens.iter().filter(|screen| self.overlap(screen) > 0).max_by_key(|screen| self.overlap(screen));
+        let (screen, (x, y)) = match screen {
+            Some(screen) => (screen, (self.x, self.y)),
+            None => (primary, (primary.x, primary.y)),
+        };
+        let (width, height) = (width.min(screen.width), height.min(screen.height));
+        WindowGeometry {
+            x: x.clamp(screen.x, screen.x + (screen.width - width) as i32),
+            y: y.clamp(screen.y, screen.y + (screen.height - height) as i32),
+            width,
+            height,
+        }
+    }
+
+    /// area in pixels both rectangles cover
+    fn overlap(&self, other: &WindowGeometry) -> u64 {
+        let span = |start: i32, len: u32, other_start: i32, other_len: u32| {
+            let end = (start as i64 + len as i64).min(other_start as i64 + other_len as i64);
+            (end - (start as i64).max(other_start as i64)).max(0) as u64
+        };
+        span(self.x, self.width, other.x, other.width) * span(self.y, self.height, other.y, other.height)
+    }
+}
+
+/// printed labels and the length of tape they used
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct PrintStats {
+    pub labels: u64,
+    pub tape_mm: f64,
+}
+
+impl PrintStats {
+    /// count `copies` labels of `mm` millimeters each
+    pub fn record(&mut self, copies: u32, mm: f32) {
+        self.labels += copies as u64;
+        self.tape_mm += copies as f64 * mm as f64;
+    }
+}
+
+impl Config {
+    /// location of the config file, `None` if the OS has no config directory
+    pub fn path() -> Option<PathBuf> {
+        dirs_next::config_dir().map(|dir| dir.join("label_drawer").join("config.json"))
+    }
+
+    /// load the config, falls back to the defaults if there is none or it is broken
+    pub fn load() -> Config {
+        let Some(path) = Config::path() else {
+            return Config::default();
+        };
+        match fs::read_to_string(&path) {
+            Ok(json) => serde_json::from_str(&json).unwrap_or_else(|e| {
+                eprintln!("Config {} ignored: {}", path.display(), e);
+                Config::default()
+            }),
+            Err(_) => Config::default(),
+        }
+    }
+
+    /// the selected printer profile. without profiles the default profile with the
+    /// `max_request_size` of the config is used
+    pub fn profile(&self) -> PrinterProfile {
+        let selected = self.active_profile.as_deref().and_then(|name| self.profiles.iter().find(|p| p.name == name));
+        match selected.or(self.profiles.first()) {
+            Some(profile) => profile.clone(),
+            None => PrinterProfile { max_request_size: self.max_request_size, ..PrinterProfile::default() },
+        }
+    }
+
+    /// select the profile called `name`, returns false if there is none
+    pub fn select_profile(&mut self, name: &str) -> bool {
+        if !self.profiles.iter().any(|p| p.name == name) {
+            return false;
+        }
+        self.active_profile = Some(name.to_string());
+        true
+    }
+
+    /// change the host of the selected profile, e.g. after a rediscovery. without
+    /// profiles the default profile is stored with the new host
+    pub fn set_profile_host(&mut self, host: &str) {
+        self.selected_profile_mut().host = host.to_string();
+    }
+
+    /// change the print density of the selected profile, `None` keeps the setting of the printer
+    pub fn set_profile_density(&mut self, density: Option<u8>) {
+        self.selected_profile_mut().density = density;
+    }
+
+    /// switch the selected profile between one and two colors
+    pub fn set_profile_two_color(&mut self, two_color: bool) {
+        self.selected_profile_mut().two_color = two_color;
+    }
+
+    /// the selected profile for a change, without profiles the default profile is stored first
+    fn selected_profile_mut(&mut self) -> &mut PrinterProfile {
+        if self.profiles.is_empty() {
+            self.profiles.push(self.profile());
+        }
+        let name = self.profile().name;
+        let index = self.profiles.iter().position(|p| p.name == name).unwrap_or(0);
+        &mut self.profiles[index]
+    }
+
+    /// add a profile made from `preset` for the host of the selected printer and select it.
+    /// the profile is named like the preset, with a number if the name is taken.
+    /// returns the name of the new profile
+    pub fn add_profile_from_preset(&mut self, preset: &PrinterProfile) -> String {
+        if self.profiles.is_empty() {
+            self.profiles.push(self.profile());
+        }
+        let name = (1..)
+            .map(|n| if n == 1 { preset.name.clone() } else { format!("{} {}", preset.name, n) })
+            .find(|name| !self.profiles.iter().any(|p| &p.name == name))
+            .expect("a free profile name");
+        let host = self.profile().host;
+        self.profiles.push(PrinterProfile::from_preset(preset, &name, &host));
+        self.active_profile = Some(name.clone());
+        name
+    }
+
+    /// add the font to the favorites or remove it, returns if it is a favorite now
+    pub fn toggle_favorite_font(&mut self, name: &str) -> bool {
+        if let Some(pos) = self.favorite_fonts.iter().position(|favorite| favorite == name) {
+            self.favorite_fonts.remove(pos);
+            false
+        } else {
+            self.favorite_fonts.push(name.to_string());
+            true
+        }
+    }
+
+    /// count printed labels of `length` dots in the session and the lifetime statistics,
+    /// the tape is measured at the resolution of the active profile
+    pub fn record_prints(&mut self, copies: u32, length: u32) {
+        let mm = self.profile().px_to_mm(length);
+        self.session_stats.record(copies, mm);
+        self.lifetime_stats.record(copies, mm);
+    }
+
+    /// names of the profiles for the selection, the default profile if none is configured
+    pub fn profile_names(&self) -> Vec<String> {
+        if self.profiles.is_empty() {
+            return vec![PrinterProfile::default().name];
+        }
+        self.profiles.iter().map(|p| p.name.clone()).collect()
+    }
+
+    /// time limit of the font scan, `None` for no limit
+    pub fn font_scan_budget(&self) -> Option<Duration> {
+        (self.font_scan_seconds > 0).then(|| Duration::from_secs(self.font_scan_seconds))
+    }
+
+    /// write the config, creating the directory if needed
+    pub fn save(&self) -> Result<(), LabelError> {
+        let path = Config::path().ok_or_else(|| LabelError::InvalidInput("No config directory available".to_string()))?;
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir)?;
+        }
+        fs::write(&path, serde_json::to_string_pretty(self)?)?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SCREENS: [WindowGeometry; 2] = [WindowGeometry { x: 0, y: 0, width: 1920, height: 1080 }, WindowGeometry { x: 1920, y: 0, width: 1280, height: 1024 }];
+
+    #[test]
+    fn a_window_off_all_screens_goes_back_to_the_primary_screen() {
+        let window = WindowGeometry { x: 5000, y: 200, width: 800, height: 600 };
+        assert_eq!(window.clamped(&SCREENS), WindowGeometry { x: 0, y: 0, width: 800, height: 600 });
+    }
+
+    #[test]
+    fn a_window_is_moved_and_shrunk_onto_its_screen() {
+        let window = WindowGeometry { x: 2800, y: 900, width: 800, height: 600 };
+        assert_eq!(window.clamped(&SCREENS), WindowGeometry { x: 2400, y: 424, width: 800, height: 600 });
+        let window = WindowGeometry { x: 1900, y: -50, width: 2000, height: 1200 };
+        assert_eq!(window.clamped(&SCREENS), WindowGeometry { x: 1920, y: 0, width: 1280, height: 1024 });
+    }
+
+    #[test]
+    fn without_screens_only_a_negative_position_is_reset() {
+        let window = WindowGeometry { x: -300, y: 5000, width: 10, height: 10 };
+        assert_eq!(window.clamped(&[]), WindowGeometry { x: 0, y: 5000, width: 200, height: 100 });
+    }
+
+    #[test]
+    fn profiles_are_loaded_and_selected() {
+        let json = r#"{
+            "profiles": [
+                {"name": "office", "host": "http://10.0.0.5", "margin_start": 12},
+                {"name": "shop", "host": "http://10.0.0.6", "head_height": 128, "dpi": 300, "margin_end": 20, "endpoints": {"upload": "/up", "print": "/go", "status": "/state"}}
+            ],
+            "active_profile": "shop"
+        }"#;
+        let mut config: Config = serde_json::from_str(json).unwrap();
+        let shop = config.profile();
+        assert_eq!((shop.name.as_str(), shop.head_height, shop.dpi, shop.margin_end), ("shop", 128, 300, 20));
+        // the profile's settings go to its printer
+        let printer = shop.transport();
+        assert_eq!((printer.base_url.as_str(), printer.endpoints.print.as_str()), ("http://10.0.0.6", "/go"));
+        assert!(config.select_profile("office"));
+        let office = config.profile();
+        assert_eq!((office.host.as_str(), office.margin_start, office.head_height), ("http://10.0.0.5", 12, crate::LABEL_HEIGHT as u32));
+        assert!(!config.select_profile("garage"));
+        assert_eq!(config.profile().name, "office");
+    }
+
+    #[test]
+    fn a_change_without_profiles_stores_the_default_profile() {
+        let mut config = Config { max_request_size: Some(300), ..Config::default() };
+        assert_eq!(config.profile(), PrinterProfile { max_request_size: Some(300), ..PrinterProfile::default() });
+        assert_eq!(config.profile_names(), vec!["default".to_string()]);
+        config.set_profile_host("http://10.0.0.9");
+        assert_eq!(config.profiles.len(), 1);
+        assert_eq!(config.profile().host, "http://10.0.0.9");
+        assert_eq!(config.profile().max_request_size, Some(300));
+    }
+
+    #[test]
+    fn applying_a_preset_fills_the_printer_settings() {
+        let preset = crate::profile::preset("ESC/POS 80 mm (http bridge)").unwrap();
+        let mut config = Config { profiles: vec![PrinterProfile { host: "http://10.0.0.9".to_string(), ..PrinterProfile::default() }], ..Config::default() };
+        assert_eq!(config.add_profile_from_preset(&preset), "ESC/POS 80 mm (http bridge)");
+        let profile = config.profile();
+        assert_eq!((profile.head_height, profile.dpi, profile.margin_end), (576, 203, 40));
+        assert_eq!(profile.endpoints.upload, "/upload");
+        assert!(profile.packing.pad_rows && !profile.packing.lsb_first);
+        assert_eq!(profile.upload_format.encoding, crate::printer::BodyEncoding::Base64);
+        // the printer stays the selected one
+        assert_eq!(profile.host, "http://10.0.0.9");
+        // a second profile of the same preset gets a number
+        assert_eq!(config.add_profile_from_preset(&preset), "ESC/POS 80 mm (http bridge) 2");
+        assert_eq!(config.profiles.len(), 3);
+        assert!(crate::profile::presets().iter().all(|preset| preset.head_height > 0 && preset.dpi > 0));
+    }
+
+    #[test]
+    fn print_statistics_add_up_and_only_the_lifetime_is_saved() {
+        let mut config = Config::default();
+        config.add_profile_from_preset(&crate::profile::preset("ESC/POS 80 mm (http bridge)").unwrap());
+        for (copies, length) in [(2, 203), (1, 406)] {
+            config.record_prints(copies, length);
+        }
+        assert_eq!(config.session_stats.labels, 3);
+        // 812 dots at 203 dpi are 4 inches
+        assert!((config.session_stats.tape_mm - 101.6).abs() < 0.01, "{}", config.session_stats.tape_mm);
+        assert_eq!(config.lifetime_stats, config.session_stats);
+        let restored: Config = serde_json::from_str(&serde_json::to_string(&config).unwrap()).unwrap();
+        assert_eq!(restored.lifetime_stats, config.lifetime_stats);
+        assert_eq!(restored.session_stats, PrintStats::default());
+        // a later session continues the total
+        let mut lifetime = restored.lifetime_stats;
+        lifetime.record(1, 10.0);
+        assert_eq!(lifetime.labels, 4);
+    }
+}
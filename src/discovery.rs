@@ -0,0 +1,84 @@
+//! Connection test and rediscovery of a printer whose address changed, e.g. by DHCP.
+
+use std::net::{Ipv4Addr, SocketAddr, TcpStream};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::thread;
+use std::time::Duration;
+
+use reqwest::Url;
+use reqwest::blocking::Client;
+
+use crate::printer::Endpoints;
+
+/// time to wait for the connection test
+const TEST_TIMEOUT: Duration = Duration::from_secs(2);
+/// time to wait for every address of the subnet scan
+const PROBE_TIMEOUT: Duration = Duration::from_millis(300);
+/// addresses probed at the same time
+const SCAN_THREADS: usize = 32;
+
+/// true if the printer at `base_url` answers any http request
+pub fn is_reachable(base_url: &str) -> bool {
+    let Ok(client) = Client::builder().timeout(TEST_TIMEOUT).build() else {
+        return false;
+    };
+    client.get(base_url).send().is_ok()
+}
+
+/// true if there is a label printer at `base_url`, i.e. its status endpoint answers
+fn is_printer(client: &Client, base_url: &str, endpoints: &Endpoints) -> bool {
+    client
+        .get(format!("{}{}", base_url, endpoints.status))
+        .send()
+        .map(|response| response.status().is_success())
+        .unwrap_or(false)
+}
+
+/// look for the printer in the /24 subnet of the ipv4 address of `base_url`.
+/// only addresses with an open port get the status request. returns the base url
+/// of the first printer found, `None` if nothing answers or `base_url` has no ipv4 address
+pub fn scan_subnet(base_url: &str, endpoints: &Endpoints) -> Option<String> {
+    let url = Url::parse(base_url).ok()?;
+    let ip: Ipv4Addr = url.host_str()?.parse().ok()?;
+    let port = url.port_or_known_default().unwrap_or(80);
+    let [a, b, c, own] = ip.octets();
+    eprintln!("Printer at {} unreachable, scanning {}.{}.{}.0/24", base_url, a, b, c);
+    let client = Client::builder().timeout(PROBE_TIMEOUT * 3).build().ok()?;
+
+    let next = AtomicUsize::new(1);
+    let found = thread::scope(|scope| {
+        let workers: Vec<_> = (0..SCAN_THREADS)
+            .map(|_| {
+                scope.spawn(|| {
+                    loop {
+                        let last = next.fetch_add(1, Ordering::SeqCst);
+                        if last >= 255 {
+                            return None;
+                        }
+                        if last == own as usize {
+                            continue;
+                        }
+                        let candidate = Ipv4Addr::new(a, b, c, last as u8);
+                        if TcpStream::connect_timeout(&SocketAddr::from((candidate, port)), PROBE_TIMEOUT).is_err() {
+                            continue;
+                        }
+                        let mut candidate_url = url.clone();
+                        candidate_url.set_host(Some(&candidate.to_string())).ok()?;
+                        let candidate_url = candidate_url.as_str().trim_end_matches('/').to_string();
+                        if is_printer(&client, &candidate_url, endpoints) {
+                            // stop the other workers
+                            next.store(255, Ordering::SeqCst);
+                            return Some(candidate_url);
+                        }
+                    }
+                })
+            })
+            .collect();
+        workers.into_iter().filter_map(|worker| worker.join().ok().flatten()).next()
+    });
+    match &found {
+        Some(found) => eprintln!("Printer found at {}", found),
+        None => eprintln!("No printer found in {}.{}.{}.0/24", a, b, c),
+    }
+    found
+}
@@ -0,0 +1,917 @@
+//! Label elements which are composed left to right into one label.
+
+use std::fs;
+
+use chrono::format::{Item, StrftimeItems};
+use chrono::{DateTime, FixedOffset, Local, Utc};
+use image::{DynamicImage, ImageBuffer, Luma};
+use qrcode::{Color, QrCode};
+use rusttype::Font;
+use serde::{Deserialize, Serialize};
+
+use crate::LABEL_WIDTH;
+use crate::error::LabelError;
+use crate::render::{AUTO_LEVELS_CLIP, Letterbox, Levels, Overflow, TEXT_START_X, TextOptions, apply_levels, fit_text_to_width, auto_levels_for, binarize_with, darken_image, despeckle, Dither, draw_text_cached, place_image, prepare_gray_image, resize_letterbox, ResizeFilter, RED_INK, VerticalClip, vertical_clip, Orientation, best_orientation};
+
+/// something that can be drawn onto the label canvas
+pub trait LabelElement {
+    /// draw the element onto `canvas` starting at column `x_offset`.
+    /// returns the width the element used
+    fn render(&self, canvas: &mut ImageBuffer<Luma<u8>, Vec<u8>>, x_offset: u32) -> u32;
+
+    /// like `render`, but gray tones (anti-aliased edges, photos) are kept for a dithering
+    /// of the whole label, see `compose_dithered`. black and white elements keep `render`
+    fn render_gray(&self, canvas: &mut ImageBuffer<Luma<u8>, Vec<u8>>, x_offset: u32) -> u32 {
+        self.render(canvas, x_offset)
+    }
+}
+
+/// draw all elements next to each other onto a white `width` x `height` canvas.
+/// returns the canvas and the used length
+pub fn compose(elements: &[Box<dyn LabelElement>], width: u32, height: u32) -> (ImageBuffer<Luma<u8>, Vec<u8>>, u32) {
+    let mut canvas = ImageBuffer::from_pixel(width, height, Luma([255u8]));
+    let mut x_offset = 0;
+    for element in elements {
+        x_offset += element.render(&mut canvas, x_offset);
+    }
+    (canvas, x_offset.min(width))
+}
+
+/// like `compose`, but the elements are drawn in grayscale and the label is dithered once
+/// at the end, so text and images next to or over each other get the same look
+pub fn compose_dithered(elements: &[Box<dyn LabelElement>], width: u32, height: u32) -> (ImageBuffer<Luma<u8>, Vec<u8>>, u32) {
+    compose_dithered_with(elements, width, height, Dither::default())
+}
+
+/// like `compose_dithered` with the error diffusion `method`
+pub fn compose_dithered_with(elements: &[Box<dyn LabelElement>], width: u32, height: u32, method: Dither) -> (ImageBuffer<Luma<u8>, Vec<u8>>, u32) {
+    let (mut canvas, used_len) = compose_gray(elements, width, height);
+    binarize_with(&mut canvas, method);
+    (canvas, used_len)
+}
+
+/// the grayscale stage of `compose_dithered`
+pub fn compose_gray(elements: &[Box<dyn LabelElement>], width: u32, height: u32) -> (ImageBuffer<Luma<u8>, Vec<u8>>, u32) {
+    let mut canvas = ImageBuffer::from_pixel(width, height, Luma([255u8]));
+    let mut x_offset = 0;
+    for element in elements {
+        x_offset += element.render_gray(&mut canvas, x_offset);
+    }
+    (canvas, x_offset.min(width))
+}
+
+/// ink of an element on a two-color printer
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum InkColor {
+    #[default]
+    Black,
+    Red,
+}
+
+/// like `compose` for a two-color printer, `colors` gives the ink of each element, black
+/// where it is missing. every element is drawn alone into the plane of its ink, black is
+/// drawn over red. with `dithered` gray tones are dithered per plane. the red pixels of
+/// the result have the value `RED_INK`, see `split_planes`
+pub fn compose_two_color(elements: &[Box<dyn LabelElement>], colors: &[InkColor], width: u32, height: u32, dithered: bool) -> (ImageBuffer<Luma<u8>, Vec<u8>>, u32) {
+    let mut black = ImageBuffer::from_pixel(width, height, Luma([255u8]));
+    let mut red = ImageBuffer::from_pixel(width, height, Luma([255u8]));
+    let mut x_offset = 0;
+    for (i, element) in elements.iter().enumerate() {
+        let plane = match colors.get(i).copied().unwrap_or_default() {
+            InkColor::Black => &mut black,
+            InkColor::Red => &mut red,
+        };
+        x_offset += if dithered { element.render_gray(plane, x_offset) } else { element.render(plane, x_offset) };
+    }
+    if dithered {
+        binarize_with(&mut black, Dither::default());
+        binarize_with(&mut red, Dither::default());
+    }
+    for (x, y, pixel) in black.enumerate_pixels_mut() {
+        if pixel[0] >= 128 && red.get_pixel(x, y)[0] < 128 {
+            *pixel = Luma([RED_INK]);
+        }
+    }
+    (black, x_offset.min(width))
+}
+
+/// another element with inverted tones, e.g. white text on black or the negative of a
+/// photo. only the columns of the element are inverted, the rest of the label stays
+pub struct InvertedElement {
+    inner: Box<dyn LabelElement>,
+}
+
+impl InvertedElement {
+    pub fn new(inner: Box<dyn LabelElement>) -> Self {
+        InvertedElement { inner }
+    }
+
+    /// draw the inner element alone, invert its columns and copy them onto `canvas`
+    fn render_with(&self, canvas: &mut ImageBuffer<Luma<u8>, Vec<u8>>, x_offset: u32, gray: bool) -> u32 {
+        let (width, height) = canvas.dimensions();
+        let mut alone = ImageBuffer::from_pixel(width, height, Luma([255u8]));
+        let used_len = if gray { self.inner.render_gray(&mut alone, x_offset) } else { self.inner.render(&mut alone, x_offset) };
+        let end = x_offset.saturating_add(used_len).min(width);
+        for x in x_offset.min(end)..end {
+            for y in 0..height {
+                canvas.put_pixel(x, y, Luma([255 - alone.get_pixel(x, y)[0]]));
+            }
+        }
+        used_len
+    }
+}
+
+impl LabelElement for InvertedElement {
+    fn render(&self, canvas: &mut ImageBuffer<Luma<u8>, Vec<u8>>, x_offset: u32) -> u32 {
+        self.render_with(canvas, x_offset, false)
+    }
+
+    fn render_gray(&self, canvas: &mut ImageBuffer<Luma<u8>, Vec<u8>>, x_offset: u32) -> u32 {
+        self.render_with(canvas, x_offset, true)
+    }
+}
+
+/// text cut out of a background: the pixels of the mask element stay blank, the rest of
+/// the background prints. without a background the columns of the mask are filled black
+pub struct CutOutElement {
+    mask: Box<dyn LabelElement>,
+    background: Option<Box<dyn LabelElement>>,
+}
+
+impl CutOutElement {
+    pub fn new(mask: Box<dyn LabelElement>, background: Option<Box<dyn LabelElement>>) -> Self {
+        CutOutElement { mask, background }
+    }
+
+    /// draw mask and background alone and copy the background, lightened by the mask, onto `canvas`
+    fn render_with(&self, canvas: &mut ImageBuffer<Luma<u8>, Vec<u8>>, x_offset: u32, gray: bool) -> u32 {
+        let (width, height) = canvas.dimensions();
+        let draw = |element: &dyn LabelElement, target: &mut ImageBuffer<Luma<u8>, Vec<u8>>| {
+            if gray { element.render_gray(target, x_offset) } else { element.render(target, x_offset) }
+        };
+        let mut mask = ImageBuffer::from_pixel(width, height, Luma([255u8]));
+        let mask_len = draw(self.mask.as_ref(), &mut mask);
+        let (background, background_len) = match &self.background {
+            Some(element) => {
+                let mut background = ImageBuffer::from_pixel(width, height, Luma([255u8]));
+                let used_len = draw(element.as_ref(), &mut background);
+                (background, used_len)
+            }
+            None => (ImageBuffer::from_pixel(width, height, Luma([0u8])), mask_len),
+        };
+        let used_len = mask_len.max(background_len);
+        let end = x_offset.saturating_add(used_len).min(width);
+        for x in x_offset.min(end)..end {
+            for y in 0..height {
+                let tone = background.get_pixel(x, y)[0].max(255 - mask.get_pixel(x, y)[0]);
+                canvas.put_pixel(x, y, Luma([tone]));
+            }
+        }
+        used_len
+    }
+}
+
+impl LabelElement for CutOutElement {
+    fn render(&self, canvas: &mut ImageBuffer<Luma<u8>, Vec<u8>>, x_offset: u32) -> u32 {
+        self.render_with(canvas, x_offset, false)
+    }
+
+    fn render_gray(&self, canvas: &mut ImageBuffer<Luma<u8>, Vec<u8>>, x_offset: u32) -> u32 {
+        self.render_with(canvas, x_offset, true)
+    }
+}
+
+/// the elements of a label made in the app, composed in the order chosen by the user
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ElementKind {
+    Text,
+    Counter,
+    Image,
+    Date,
+}
+
+impl ElementKind {
+    pub const ALL: [ElementKind; 4] = [ElementKind::Text, ElementKind::Counter, ElementKind::Image, ElementKind::Date];
+
+    /// shown in the element order list
+    pub fn name(self) -> &'static str {
+        match self {
+            ElementKind::Text => "Text",
+            ElementKind::Counter => "Ticket number",
+            ElementKind::Image => "Image",
+            ElementKind::Date => "Date",
+        }
+    }
+
+    /// `order` without duplicates, missing kinds are appended in the default order
+    pub fn complete_order(order: &[ElementKind]) -> Vec<ElementKind> {
+        let mut complete: Vec<ElementKind> = Vec::new();
+        for kind in order.iter().chain(ElementKind::ALL.iter()) {
+            if !complete.contains(kind) {
+                complete.push(*kind);
+            }
+        }
+        complete
+    }
+}
+
+/// round an element offset to the nearest multiple of `grid`, halves away from 0.
+/// 0 and 1 keep the offset
+pub fn snap_to_grid(offset: i32, grid: u32) -> i32 {
+    if grid <= 1 {
+        return offset;
+    }
+    let grid = grid.min(i32::MAX as u32) as i64;
+    let offset = offset as i64;
+    let snapped = (offset.abs() + grid / 2) / grid * grid * offset.signum();
+    snapped.clamp(i32::MIN as i64, i32::MAX as i64) as i32
+}
+
+/// move the item at `from` to position `to` (clamped to the list), the items in between
+/// shift by one. returns false if nothing moved
+pub fn move_element<T>(items: &mut Vec<T>, from: usize, to: usize) -> bool {
+    if from >= items.len() {
+        return false;
+    }
+    let to = to.min(items.len() - 1);
+    if from == to {
+        return false;
+    }
+    let item = items.remove(from);
+    items.insert(to, item);
+    true
+}
+
+/// text rendered with a TrueType font
+pub struct TextElement {
+    text: String,
+    font: Font<'static>,
+    /// key of the glyphs in the glyph cache
+    font_path: String,
+    options: TextOptions,
+}
+
+impl TextElement {
+    pub fn new(text: &str, font_path: &str) -> Result<Self, LabelError> {
+        let font_data = fs::read(font_path)?;
+        let font = Font::try_from_vec(font_data).ok_or_else(|| LabelError::Font(format!("Invalid font file: {}", font_path)))?;
+        Ok(TextElement { text: text.to_string(), font, font_path: font_path.to_string(), options: TextOptions::default() })
+    }
+
+    pub fn with_options(mut self, options: TextOptions) -> Self {
+        self.options = options;
+        self
+    }
+
+    /// apply the overflow policy of the options for a label of `width` x `height`.
+    /// returns the policy if it changed the text, see `fit_text_to_width`
+    pub fn fit_to_width(mut self, width: u32, height: u32) -> Result<(Self, Option<Overflow>), LabelError> {
+        let fitted = fit_text_to_width(&self.font, &self.text, width as f32 - TEXT_START_X, height as f32, &self.options)?;
+        self.text = fitted.text;
+        self.options = fitted.options;
+        Ok((self, fitted.applied))
+    }
+
+    /// the text as it is drawn
+    pub fn text(&self) -> &str {
+        &self.text
+    }
+
+    pub fn options(&self) -> &TextOptions {
+        &self.options
+    }
+
+    /// rows of the text cut off at the top and bottom of a label of `width` x `height`
+    pub fn vertical_clip(&self, width: u32, height: u32) -> VerticalClip {
+        vertical_clip(&self.font, &self.text, TEXT_START_X, width as f32, height, &self.options)
+    }
+}
+
+impl LabelElement for TextElement {
+    fn render(&self, canvas: &mut ImageBuffer<Luma<u8>, Vec<u8>>, x_offset: u32) -> u32 {
+        let used_len = draw_text_cached(canvas, &self.font, &self.font_path, &self.text, x_offset as f32 + TEXT_START_X, &self.options, false);
+        (used_len as u32).saturating_sub(x_offset)
+    }
+
+    fn render_gray(&self, canvas: &mut ImageBuffer<Luma<u8>, Vec<u8>>, x_offset: u32) -> u32 {
+        let used_len = draw_text_cached(canvas, &self.font, &self.font_path, &self.text, x_offset as f32 + TEXT_START_X, &self.options, true);
+        (used_len as u32).saturating_sub(x_offset)
+    }
+}
+
+/// auto incrementing ticket number, persisted in the config
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct TicketCounter {
+    pub enabled: bool,
+    /// number of the next printed label
+    pub next: u64,
+    /// value set by a reset
+    pub start: u64,
+    /// text around the number, `{n}` is replaced by the number
+    pub format: String,
+    /// minimal number of digits, filled up with zeros
+    pub padding: usize,
+}
+
+impl Default for TicketCounter {
+    fn default() -> Self {
+        TicketCounter { enabled: false, next: 1, start: 1, format: "{n}".to_string(), padding: 0 }
+    }
+}
+
+impl TicketCounter {
+    /// text for the next number, e.g. "No. 0042" for the format "No. {n}" and padding 4
+    pub fn text(&self) -> String {
+        let number = format!("{:0width$}", self.next, width = self.padding);
+        if self.format.contains("{n}") {
+            self.format.replace("{n}", &number)
+        } else {
+            format!("{}{}", self.format, number)
+        }
+    }
+
+    /// count a printed label
+    pub fn advance(&mut self) {
+        self.next = self.next.saturating_add(1);
+    }
+
+    /// start again at `start`
+    pub fn reset(&mut self) {
+        self.next = self.start;
+    }
+}
+
+/// date and time of the label creation, persisted in the config
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct DateStamp {
+    pub enabled: bool,
+    /// strftime format, e.g. "%Y-%m-%d %H:%M"
+    pub format: String,
+    /// "local", "utc" or a fixed offset like "+02:00"
+    pub timezone: String,
+}
+
+impl Default for DateStamp {
+    fn default() -> Self {
+        DateStamp { enabled: false, format: "%Y-%m-%d".to_string(), timezone: "local".to_string() }
+    }
+}
+
+impl DateStamp {
+    /// `instant` in the format and timezone of the stamp
+    pub fn text_at(&self, instant: DateTime<Utc>) -> Result<String, LabelError> {
+        let items: Vec<Item> = StrftimeItems::new(&self.format).collect();
+        // chrono panics when an invalid format is displayed
+        if items.contains(&Item::Error) {
+            return Err(LabelError::InvalidInput(format!("Invalid date format '{}'", self.format)));
+        }
+        let text = match self.timezone.trim() {
+            "" | "local" => instant.with_timezone(&Local).format_with_items(items.iter()).to_string(),
+            zone if zone.eq_ignore_ascii_case("utc") => instant.format_with_items(items.iter()).to_string(),
+            zone => {
+                let offset: FixedOffset = zone.parse().map_err(|_| LabelError::InvalidInput(format!("Unknown timezone '{}', use local, utc or an offset like +02:00", zone)))?;
+                instant.with_timezone(&offset).format_with_items(items.iter()).to_string()
+            }
+        };
+        Ok(text)
+    }
+
+    /// the current time as text
+    pub fn text(&self) -> Result<String, LabelError> {
+        self.text_at(Utc::now())
+    }
+}
+
+/// the time of its creation in the format of a `DateStamp`, drawn like a text
+pub struct DateElement {
+    text: TextElement,
+}
+
+impl DateElement {
+    pub fn new(stamp: &DateStamp, font_path: &str) -> Result<Self, LabelError> {
+        Ok(DateElement { text: TextElement::new(&stamp.text()?, font_path)? })
+    }
+
+    pub fn with_options(mut self, options: TextOptions) -> Self {
+        self.text = self.text.with_options(options);
+        self
+    }
+}
+
+impl LabelElement for DateElement {
+    fn render(&self, canvas: &mut ImageBuffer<Luma<u8>, Vec<u8>>, x_offset: u32) -> u32 {
+        self.text.render(canvas, x_offset)
+    }
+
+    fn render_gray(&self, canvas: &mut ImageBuffer<Luma<u8>, Vec<u8>>, x_offset: u32) -> u32 {
+        self.text.render_gray(canvas, x_offset)
+    }
+}
+
+/// the next number of a `TicketCounter`, drawn like a text
+pub struct CounterElement {
+    text: TextElement,
+}
+
+impl CounterElement {
+    pub fn new(counter: &TicketCounter, font_path: &str) -> Result<Self, LabelError> {
+        Ok(CounterElement { text: TextElement::new(&counter.text(), font_path)? })
+    }
+
+    pub fn with_options(mut self, options: TextOptions) -> Self {
+        self.text = self.text.with_options(options);
+        self
+    }
+}
+
+impl LabelElement for CounterElement {
+    fn render(&self, canvas: &mut ImageBuffer<Luma<u8>, Vec<u8>>, x_offset: u32) -> u32 {
+        self.text.render(canvas, x_offset)
+    }
+
+    fn render_gray(&self, canvas: &mut ImageBuffer<Luma<u8>, Vec<u8>>, x_offset: u32) -> u32 {
+        self.text.render_gray(canvas, x_offset)
+    }
+}
+
+/// image scaled to the label height and dithered, moved by an extra offset
+pub struct ImageElement {
+    image: DynamicImage,
+    x_offset: i32,
+    y_offset: i32,
+    levels: Levels,
+    letterbox: Option<Letterbox>,
+    auto_contrast: bool,
+    filter: ResizeFilter,
+    dither: Dither,
+    orientation: Orientation,
+    despeckle: bool,
+}
+
+impl ImageElement {
+    pub fn new(image: DynamicImage, x_offset: i32, y_offset: i32) -> Self {
+        ImageElement { image, x_offset, y_offset, levels: Levels::default(), letterbox: None, auto_contrast: false, filter: ResizeFilter::default(), dither: Dither::default(), orientation: Orientation::default(), despeckle: false }
+    }
+
+    /// remove isolated dots after the dithering when it is rendered alone, see `despeckle`
+    pub fn with_despeckle(mut self, despeckle: bool) -> Self {
+        self.despeckle = despeckle;
+        self
+    }
+
+    /// turn the image on the tape, `Orientation::Auto` chooses the shorter label
+    pub fn with_orientation(mut self, orientation: Orientation) -> Self {
+        self.orientation = orientation;
+        self
+    }
+
+    /// true if the image is turned on a label of `height` rows, `Orientation::Auto` fits it
+    /// into the letterbox or the longest label
+    pub fn is_rotated(&self, height: u32) -> bool {
+        match self.orientation {
+            Orientation::Auto => {
+                let length = self.letterbox.map_or(LABEL_WIDTH as u32, |letterbox| letterbox.width);
+                best_orientation(self.image.width(), self.image.height(), height, length) == Orientation::Rotated
+            }
+            orientation => orientation == Orientation::Rotated,
+        }
+    }
+
+    /// scale the image with `filter` instead of lanczos
+    pub fn with_filter(mut self, filter: ResizeFilter) -> Self {
+        self.filter = filter;
+        self
+    }
+
+    /// dither the image with `dither` when it is rendered alone, see `render`
+    pub fn with_dither(mut self, dither: Dither) -> Self {
+        self.dither = dither;
+        self
+    }
+
+    /// correct the tones before dithering
+    pub fn with_levels(mut self, levels: Levels) -> Self {
+        self.levels = levels;
+        self
+    }
+
+    /// fit the image into a box of the label height instead of scaling it to the height
+    pub fn with_letterbox(mut self, letterbox: Option<Letterbox>) -> Self {
+        self.letterbox = letterbox;
+        self
+    }
+
+    /// stretch the tones to the full range before the levels, see `auto_levels_for`
+    pub fn with_auto_contrast(mut self, auto_contrast: bool) -> Self {
+        self.auto_contrast = auto_contrast;
+        self
+    }
+
+    /// the image scaled to `height` with all tone corrections, as it is dithered
+    pub fn gray(&self, height: u32) -> ImageBuffer<Luma<u8>, Vec<u8>> {
+        let rotated;
+        let image = if self.is_rotated(height) {
+            rotated = self.image.rotate270();
+            &rotated
+        } else {
+            &self.image
+        };
+        let mut gray = match self.letterbox {
+            Some(letterbox) => resize_letterbox(image, letterbox.width, height, letterbox.fill, self.filter),
+            None => prepare_gray_image(image, height, &Levels::default(), self.filter),
+        };
+        if self.auto_contrast {
+            // from the source, the bars of a letterbox would count as tones of the image
+            apply_levels(&mut gray, &auto_levels_for(&self.image.to_luma8(), AUTO_LEVELS_CLIP));
+        }
+        apply_levels(&mut gray, &self.levels);
+        gray
+    }
+}
+
+impl LabelElement for ImageElement {
+    fn render(&self, canvas: &mut ImageBuffer<Luma<u8>, Vec<u8>>, x_offset: u32) -> u32 {
+        let mut dithered = self.gray(canvas.height());
+        binarize_with(&mut dithered, self.dither);
+        if self.despeckle {
+            despeckle(&mut dithered);
+        }
+        let used_len = place_image(canvas, &dithered, x_offset as i32 + self.x_offset, self.y_offset);
+        used_len.saturating_sub(x_offset)
+    }
+
+    fn render_gray(&self, canvas: &mut ImageBuffer<Luma<u8>, Vec<u8>>, x_offset: u32) -> u32 {
+        let gray = self.gray(canvas.height());
+        let used_len = darken_image(canvas, &gray, x_offset as i32 + self.x_offset, self.y_offset);
+        used_len.saturating_sub(x_offset)
+    }
+}
+
+/// Code 39 patterns for the 9 elements (bar, space, bar, ...), a set bit is a wide element
+const CODE39: [(char, u16); 44] = [
+    ('0', 0x034), ('1', 0x121), ('2', 0x061), ('3', 0x160), ('4', 0x031),
+    ('5', 0x130), ('6', 0x070), ('7', 0x025), ('8', 0x124), ('9', 0x064),
+    ('A', 0x109), ('B', 0x049), ('C', 0x148), ('D', 0x019), ('E', 0x118),
+    ('F', 0x058), ('G', 0x00D), ('H', 0x10C), ('I', 0x04C), ('J', 0x01C),
+    ('K', 0x103), ('L', 0x043), ('M', 0x142), ('N', 0x013), ('O', 0x112),
+    ('P', 0x052), ('Q', 0x007), ('R', 0x106), ('S', 0x046), ('T', 0x016),
+    ('U', 0x181), ('V', 0x0C1), ('W', 0x1C0), ('X', 0x091), ('Y', 0x190),
+    ('Z', 0x0D0), ('-', 0x085), ('.', 0x184), (' ', 0x0C4), ('$', 0x0A8),
+    ('/', 0x0A2), ('+', 0x08A), ('%', 0x02A), ('*', 0x094),
+];
+
+/// blank space left and right of a barcode, scanners need it to find the start and end
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum QuietZone {
+    /// in modules, the narrow bar of a barcode or the square of a qr code
+    Modules(u32),
+    /// in pixels
+    Pixels(u32),
+}
+
+impl QuietZone {
+    /// width in pixels for modules of `module_size` pixels
+    pub fn pixels(self, module_size: u32) -> u32 {
+        match self {
+            QuietZone::Modules(modules) => modules * module_size,
+            QuietZone::Pixels(pixels) => pixels,
+        }
+    }
+}
+
+/// warning for a quiet zone narrower than `minimum` modules of `module_size` pixels
+fn quiet_zone_warning(quiet_zone: QuietZone, module_size: u32, minimum: u32, symbology: &str) -> Option<String> {
+    let pixels = quiet_zone.pixels(module_size);
+    (pixels < minimum * module_size).then(|| format!("The quiet zone of {} px is below the minimum of {} px for {}, scanners may not read it", pixels, minimum * module_size, symbology))
+}
+
+/// quiet zone in narrow modules recommended for Code 39
+pub const CODE39_QUIET_ZONE: u32 = 10;
+
+/// Code 39 barcode over the full label height
+pub struct BarcodeElement {
+    patterns: Vec<u16>,
+    module_width: u32,
+    quiet_zone: QuietZone,
+}
+
+impl BarcodeElement {
+    /// `data` is upper cased, `module_width` is the width of a narrow bar in pixels
+    pub fn new(data: &str, module_width: u32) -> Result<Self, LabelError> {
+        let mut patterns = Vec::new();
+        // start and stop character is '*'
+        for c in format!("*{}*", data.to_ascii_uppercase()).chars() {
+            let Some((_, pattern)) = CODE39.iter().find(|(ch, _)| *ch == c) else {
+                return Err(LabelError::InvalidInput(format!("Character '{}' not supported by Code 39", c)));
+            };
+            patterns.push(*pattern);
+        }
+        Ok(BarcodeElement { patterns, module_width: module_width.max(1), quiet_zone: QuietZone::Modules(CODE39_QUIET_ZONE) })
+    }
+
+    /// blank space on both sides instead of `CODE39_QUIET_ZONE` modules, a smaller one is logged
+    pub fn with_quiet_zone(mut self, quiet_zone: QuietZone) -> Self {
+        self.quiet_zone = quiet_zone;
+        if let Some(warning) = self.quiet_zone_warning() {
+            eprintln!("{}", warning);
+        }
+        self
+    }
+
+    /// a message if the quiet zone is below `CODE39_QUIET_ZONE` modules
+    pub fn quiet_zone_warning(&self) -> Option<String> {
+        quiet_zone_warning(self.quiet_zone, self.module_width, CODE39_QUIET_ZONE, "Code 39")
+    }
+}
+
+impl LabelElement for BarcodeElement {
+    fn render(&self, canvas: &mut ImageBuffer<Luma<u8>, Vec<u8>>, x_offset: u32) -> u32 {
+        let (width, height) = canvas.dimensions();
+        let quiet_zone = self.quiet_zone.pixels(self.module_width);
+        let mut x = x_offset + quiet_zone;
+        for (n, pattern) in self.patterns.iter().enumerate() {
+            for i in 0..9 {
+                let wide = pattern & (1 << (8 - i)) != 0;
+                let element_width = if wide { 3 * self.module_width } else { self.module_width };
+                // even elements are bars
+                if i % 2 == 0 {
+                    for px in x..(x + element_width).min(width) {
+                        for py in 0..height {
+                            canvas.put_pixel(px, py, Luma([0u8]));
+                        }
+                    }
+                }
+                x += element_width;
+            }
+            // narrow gap between characters
+            if n + 1 < self.patterns.len() {
+                x += self.module_width;
+            }
+        }
+        x + quiet_zone - x_offset
+    }
+}
+
+/// quiet zone in modules required around a QR code
+pub const QR_QUIET_ZONE: u32 = 4;
+
+/// QR code scaled to the largest integer module size which fits the label height
+/// together with the quiet zone above and below
+pub struct QrElement {
+    code: QrCode,
+    quiet_zone: QuietZone,
+}
+
+impl QrElement {
+    pub fn new(data: &str) -> Result<Self, LabelError> {
+        Ok(QrElement { code: QrCode::new(data.as_bytes()).map_err(|e| LabelError::InvalidInput(e.to_string()))?, quiet_zone: QuietZone::Modules(QR_QUIET_ZONE) })
+    }
+
+    /// blank space around the code instead of `QR_QUIET_ZONE` modules. the module size depends
+    /// on the label height, so the caller checks a smaller zone with `quiet_zone_warning`
+    pub fn with_quiet_zone(mut self, quiet_zone: QuietZone) -> Self {
+        self.quiet_zone = quiet_zone;
+        self
+    }
+
+    /// pixels of one module on a label of `height` rows
+    pub fn module_size(&self, height: u32) -> u32 {
+        let modules = self.code.width() as u32;
+        let size = match self.quiet_zone {
+            QuietZone::Modules(quiet) => height / (modules + 2 * quiet),
+            QuietZone::Pixels(quiet) => height.saturating_sub(2 * quiet) / modules,
+        };
+        size.max(1)
+    }
+
+    /// a message if the quiet zone on a label of `height` rows is below `QR_QUIET_ZONE` modules
+    pub fn quiet_zone_warning(&self, height: u32) -> Option<String> {
+        quiet_zone_warning(self.quiet_zone, self.module_size(height), QR_QUIET_ZONE, "QR codes")
+    }
+}
+
+impl LabelElement for QrElement {
+    fn render(&self, canvas: &mut ImageBuffer<Luma<u8>, Vec<u8>>, x_offset: u32) -> u32 {
+        let (width, height) = canvas.dimensions();
+        let modules = self.code.width() as u32;
+        let module_size = self.module_size(height);
+        let quiet_zone = self.quiet_zone.pixels(module_size);
+        let y_offset = height.saturating_sub(modules * module_size) / 2;
+        for (i, color) in self.code.to_colors().into_iter().enumerate() {
+            if color != Color::Dark {
+                continue;
+            }
+            let mx = i as u32 % modules;
+            let my = i as u32 / modules;
+            for dy in 0..module_size {
+                for dx in 0..module_size {
+                    let px = x_offset + quiet_zone + mx * module_size + dx;
+                    let py = y_offset + my * module_size + dy;
+                    if px < width && py < height {
+                        canvas.put_pixel(px, py, Luma([0u8]));
+                    }
+                }
+            }
+        }
+        modules * module_size + 2 * quiet_zone
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::render::tests::TEST_FONT;
+
+    /// the columns of `img` with any black pixel
+    fn inked_columns(img: &ImageBuffer<Luma<u8>, Vec<u8>>) -> Vec<u32> {
+        (0..img.width()).filter(|&x| (0..img.height()).any(|y| img.get_pixel(x, y)[0] == 0)).collect()
+    }
+
+    #[test]
+    fn text_and_barcode_are_composed_side_by_side() {
+        let Ok(text) = TextElement::new("AB", TEST_FONT) else { return };
+        let (text_only, text_len) = compose(&[Box::new(TextElement::new("AB", TEST_FONT).unwrap()) as Box<dyn LabelElement>], 1000, 96);
+        let barcode_len = compose(&[Box::new(BarcodeElement::new("AB", 2).unwrap()) as Box<dyn LabelElement>], 1000, 96).1;
+        let elements: Vec<Box<dyn LabelElement>> = vec![Box::new(text), Box::new(BarcodeElement::new("AB", 2).unwrap())];
+        let (label, used_len) = compose(&elements, 1000, 96);
+        assert_eq!(used_len, text_len + barcode_len);
+        // the text is unchanged, the barcode starts behind it after its quiet zone
+        assert!(*inked_columns(&text_only).last().unwrap() < text_len);
+        let columns = inked_columns(&label);
+        let first_bar = *columns.iter().find(|&&x| x >= text_len).unwrap();
+        assert_eq!(first_bar, text_len + CODE39_QUIET_ZONE * 2);
+        assert!((0..96).all(|y| label.get_pixel(first_bar, y)[0] == 0));
+    }
+
+    #[test]
+    fn the_ticket_number_counts_and_survives_a_restart() {
+        let mut counter = TicketCounter { enabled: true, next: 41, start: 7, format: "No. {n}".to_string(), padding: 4 };
+        assert_eq!(counter.text(), "No. 0041");
+        counter.advance();
+        assert_eq!(counter.text(), "No. 0042");
+        // the saved config starts with the next number
+        let config = crate::Config { counter: counter.clone(), ..crate::Config::default() };
+        let restored: crate::Config = serde_json::from_str(&serde_json::to_string(&config).unwrap()).unwrap();
+        assert_eq!(restored.counter, counter);
+        counter.reset();
+        assert_eq!(counter.next, 7);
+        assert_eq!(TicketCounter { format: "#".to_string(), ..TicketCounter::default() }.text(), "#1");
+        let mut last = TicketCounter { next: u64::MAX, ..TicketCounter::default() };
+        last.advance();
+        assert_eq!(last.next, u64::MAX);
+    }
+
+    #[test]
+    fn text_over_an_image_is_dithered_once() {
+        let Ok(text) = TextElement::new("I", TEST_FONT) else { return };
+        let (_, text_len) = compose(&[Box::new(TextElement::new("I", TEST_FONT).unwrap()) as Box<dyn LabelElement>], 300, 96);
+        // a mid gray image from the start of the label, under the text
+        let image = ImageElement::new(DynamicImage::ImageLuma8(ImageBuffer::from_pixel(200, 96, Luma([128u8]))), -(text_len as i32), 0);
+        let elements: Vec<Box<dyn LabelElement>> = vec![Box::new(text), Box::new(image)];
+        let (gray, used_len) = compose_gray(&elements, 300, 96);
+        assert_eq!(used_len, 200);
+        // the anti-aliased edges of the text stay gray until the end
+        assert!(gray.pixels().any(|pixel| pixel[0] > 0 && pixel[0] < 128));
+        assert_eq!(*gray.get_pixel(190, 50), Luma([128]));
+        let (label, _) = compose_dithered(&elements, 300, 96);
+        assert!(label.pixels().all(|pixel| pixel[0] == 0 || pixel[0] == 255));
+        let black_share = |columns: std::ops::Range<u32>| {
+            let pixels = columns.len() as f32 * 96.0;
+            columns.flat_map(|x| (0..96).map(move |y| (x, y))).filter(|&(x, y)| label.get_pixel(x, y)[0] == 0).count() as f32 / pixels
+        };
+        let background = black_share(text_len + 20..200);
+        assert!((0.4..0.6).contains(&background), "{}", background);
+        assert!(black_share(0..text_len) > background);
+        assert_eq!(black_share(200..300), 0.0);
+    }
+
+    #[test]
+    fn a_dragged_element_moves_and_the_others_shift() {
+        let mut order = ElementKind::ALL.to_vec();
+        assert!(move_element(&mut order, 0, 2));
+        assert_eq!(order, vec![ElementKind::Counter, ElementKind::Image, ElementKind::Text, ElementKind::Date]);
+        assert!(move_element(&mut order, 3, 0));
+        assert_eq!(order, vec![ElementKind::Date, ElementKind::Counter, ElementKind::Image, ElementKind::Text]);
+        // past the end is the last place, out of the list or in place nothing moves
+        assert!(move_element(&mut order, 0, 10));
+        assert_eq!(order.last(), Some(&ElementKind::Date));
+        assert!(!move_element(&mut order, 4, 0));
+        assert!(!move_element(&mut order, 1, 1));
+        assert_eq!(ElementKind::complete_order(&[ElementKind::Image, ElementKind::Image]), vec![ElementKind::Image, ElementKind::Text, ElementKind::Counter, ElementKind::Date]);
+    }
+
+    #[test]
+    fn offsets_snap_to_the_nearest_grid_line() {
+        assert_eq!(snap_to_grid(13, 8), 16);
+        assert_eq!(snap_to_grid(11, 8), 8);
+        // halves go away from 0, on both sides
+        assert_eq!(snap_to_grid(4, 8), 8);
+        assert_eq!(snap_to_grid(-4, 8), -8);
+        assert_eq!(snap_to_grid(-11, 8), -8);
+        assert_eq!((snap_to_grid(13, 0), snap_to_grid(13, 1)), (13, 13));
+        assert_eq!(snap_to_grid(i32::MAX, 10), i32::MAX);
+        assert_eq!(snap_to_grid(i32::MIN, 16), i32::MIN);
+    }
+
+    #[test]
+    fn an_inverted_image_next_to_normal_text() {
+        let Ok(text) = TextElement::new("AB", TEST_FONT) else { return };
+        let (text_only, text_len) = compose(&[Box::new(TextElement::new("AB", TEST_FONT).unwrap()) as Box<dyn LabelElement>], 400, 96);
+        // black in the left half
+        let image = ImageBuffer::from_fn(64, 96, |x, _| Luma([if x < 32 { 0u8 } else { 255 }]));
+        let inverted = InvertedElement::new(Box::new(ImageElement::new(DynamicImage::ImageLuma8(image), 0, 0)));
+        let elements: Vec<Box<dyn LabelElement>> = vec![Box::new(text), Box::new(inverted)];
+        let (label, used_len) = compose(&elements, 400, 96);
+        assert_eq!(used_len, text_len + 64);
+        // the text keeps its tones, the image is its negative
+        assert!((0..text_len).all(|x| (0..96).all(|y| label.get_pixel(x, y) == text_only.get_pixel(x, y))));
+        assert!((0..96).all(|y| label.get_pixel(text_len + 10, y)[0] == 255 && label.get_pixel(text_len + 50, y)[0] == 0));
+        // behind the element nothing is inverted
+        assert!((text_len + 64..400).all(|x| label.get_pixel(x, 40)[0] == 255));
+    }
+
+    #[test]
+    fn cut_out_text_is_blank_and_the_background_prints() {
+        let Ok(text) = TextElement::new("HO", TEST_FONT) else { return };
+        let (text_only, text_len) = compose(&[Box::new(TextElement::new("HO", TEST_FONT).unwrap()) as Box<dyn LabelElement>], 400, 96);
+        let glyph_pixels = text_only.pixels().filter(|pixel| pixel[0] == 0).count();
+        assert!(glyph_pixels > 0);
+        // a black fill of the text columns
+        let (filled, used_len) = compose(&[Box::new(CutOutElement::new(Box::new(text), None)) as Box<dyn LabelElement>], 400, 96);
+        assert_eq!(used_len, text_len);
+        for (x, y, pixel) in filled.enumerate_pixels() {
+            let expected = if x >= text_len || text_only.get_pixel(x, y)[0] == 0 { 255 } else { 0 };
+            assert_eq!(pixel[0], expected, "{} {}", x, y);
+        }
+        // a black image behind it, wider than the text
+        let background = ImageElement::new(DynamicImage::ImageLuma8(ImageBuffer::from_pixel(300, 96, Luma([0u8]))), 0, 0);
+        let cut_out = CutOutElement::new(Box::new(TextElement::new("HO", TEST_FONT).unwrap()), Some(Box::new(background)));
+        let (label, used_len) = compose(&[Box::new(cut_out) as Box<dyn LabelElement>], 400, 96);
+        assert_eq!(used_len, 300);
+        assert_eq!(label.pixels().filter(|pixel| pixel[0] == 255).count(), glyph_pixels + 100 * 96);
+    }
+
+    #[test]
+    fn the_quiet_zone_is_blank_and_below_the_minimum_warned_about() {
+        // "*A*" is 3 characters of 15 modules and 2 gaps, 94 pixels with a module of 2
+        let barcode = BarcodeElement::new("a", 2).unwrap();
+        assert_eq!(barcode.quiet_zone_warning(), None);
+        let mut canvas = ImageBuffer::from_pixel(200, 8, Luma([255u8]));
+        assert_eq!(barcode.render(&mut canvas, 0), 20 + 94 + 20);
+        let columns = inked_columns(&canvas);
+        assert_eq!((columns[0], *columns.last().unwrap()), (20, 20 + 94 - 1));
+        // runs of bars and spaces: '*', the gap and 'A'
+        let mut runs = Vec::new();
+        for x in 20..20 + 94 {
+            let black = canvas.get_pixel(x, 0)[0] == 0;
+            match runs.last_mut() {
+                Some((color, len)) if *color == black => *len += 1,
+                _ => runs.push((black, 1)),
+            }
+        }
+        let widths: Vec<u32> = runs.iter().map(|(_, len)| *len).collect();
+        assert_eq!(widths[..19], [2, 6, 2, 2, 6, 2, 6, 2, 2, 2, 6, 2, 2, 2, 2, 6, 2, 2, 6]);
+        assert!(runs[0].0 && runs.last().unwrap().0);
+
+        // a zone in pixels replaces the one in modules, a narrow one is warned about
+        let narrow = BarcodeElement::new("A", 2).unwrap().with_quiet_zone(QuietZone::Pixels(5));
+        assert!(narrow.quiet_zone_warning().unwrap().contains("below the minimum of 20 px"));
+        let mut canvas = ImageBuffer::from_pixel(200, 8, Luma([255u8]));
+        assert_eq!(narrow.render(&mut canvas, 10), 5 + 94 + 5);
+        assert_eq!(inked_columns(&canvas)[0], 15);
+        assert_eq!(BarcodeElement::new("A", 2).unwrap().with_quiet_zone(QuietZone::Pixels(20)).quiet_zone_warning(), None);
+        assert!(BarcodeElement::new("a_b", 2).is_err());
+
+        // a qr code keeps its 4 modules on both sides
+        let qr = QrElement::new("label").unwrap();
+        let mut canvas = ImageBuffer::from_pixel(200, 96, Luma([255u8]));
+        let used = qr.render(&mut canvas, 0);
+        let module = qr.module_size(96);
+        let columns = inked_columns(&canvas);
+        assert_eq!(columns[0], 4 * module);
+        assert_eq!(*columns.last().unwrap(), used - 4 * module - 1);
+        assert!(qr.with_quiet_zone(QuietZone::Modules(1)).quiet_zone_warning(96).is_some());
+    }
+
+    #[test]
+    fn a_fixed_instant_is_formatted_in_the_timezone_of_the_stamp() {
+        use chrono::TimeZone;
+        let instant = Utc.with_ymd_and_hms(2024, 12, 31, 23, 30, 5).unwrap();
+        let stamp = |format: &str, timezone: &str| DateStamp { enabled: true, format: format.to_string(), timezone: timezone.to_string() };
+        assert_eq!(stamp("%Y-%m-%d %H:%M:%S", "utc").text_at(instant).unwrap(), "2024-12-31 23:30:05");
+        assert_eq!(stamp("%d.%m.%y", "UTC").text_at(instant).unwrap(), "31.12.24");
+        // a fixed offset moves into the next year, a negative one stays
+        assert_eq!(stamp("%Y-%m-%d %H:%M", "+02:00").text_at(instant).unwrap(), "2025-01-01 01:30");
+        assert_eq!(stamp("%H:%M %z", "-05:00").text_at(instant).unwrap(), "18:30 -0500");
+        assert_eq!(stamp("%Y", "local").text_at(instant).unwrap(), instant.with_timezone(&Local).format("%Y").to_string());
+        assert_eq!(DateStamp::default().text_at(instant).unwrap(), instant.with_timezone(&Local).format("%Y-%m-%d").to_string());
+        // a broken format or zone is an error instead of a panic
+        assert!(matches!(stamp("%Y-%", "utc").text_at(instant), Err(LabelError::InvalidInput(_))));
+        assert!(matches!(stamp("%Y", "Mars/Olympus").text_at(instant), Err(LabelError::InvalidInput(_))));
+    }
+}
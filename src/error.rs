@@ -0,0 +1,120 @@
+//! Error type of the library.
+
+use std::error::Error;
+use std::fmt;
+use std::io;
+
+/// everything that can go wrong while building, storing or printing a label
+#[derive(Debug)]
+pub enum LabelError {
+    /// reading or writing a file failed
+    Io(io::Error),
+    /// a font could not be found or is not a usable font file
+    Font(String),
+    /// an image could not be decoded or encoded
+    Image(image::ImageError),
+    /// a label definition, config or batch line is not valid json
+    Json(serde_json::Error),
+    /// the printer could not be reached
+    Network(reqwest::Error),
+    /// the printer answered with an error status
+    Status { status: u16 },
+    /// the printer refused the upload chunk starting at byte `index`
+    UploadFailed { index: usize, status: u16 },
+    /// the print head of the printer has `head` dots, the label is `height` pixels high
+    HeadMismatch { head: u32, height: u32 },
+    /// the content can't be encoded, e.g. characters missing in a barcode
+    InvalidInput(String),
+    /// error of a custom `LabelTransport`
+    Transport(String),
+}
+
+impl fmt::Display for LabelError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            LabelError::Io(e) => write!(f, "{}", e),
+            LabelError::Font(msg) => write!(f, "{}", msg),
+            LabelError::Image(e) => write!(f, "{}", e),
+            LabelError::Json(e) => write!(f, "{}", e),
+            LabelError::Network(e) => write!(f, "{}", e),
+            LabelError::Status { status } => write!(f, "Printer answered with status {}", status),
+            LabelError::UploadFailed { index, status } => write!(f, "Upload failed at index {} with status {}", index, status),
+            LabelError::HeadMismatch { head, height } => write!(f, "The print head has {} dots, but the label is {} px high", head, height),
+            LabelError::InvalidInput(msg) => write!(f, "{}", msg),
+            LabelError::Transport(msg) => write!(f, "{}", msg),
+        }
+    }
+}
+
+impl Error for LabelError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            LabelError::Io(e) => Some(e),
+            LabelError::Image(e) => Some(e),
+            LabelError::Json(e) => Some(e),
+            LabelError::Network(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl From<io::Error> for LabelError {
+    fn from(e: io::Error) -> Self {
+        LabelError::Io(e)
+    }
+}
+
+impl From<image::ImageError> for LabelError {
+    fn from(e: image::ImageError) -> Self {
+        LabelError::Image(e)
+    }
+}
+
+impl From<serde_json::Error> for LabelError {
+    fn from(e: serde_json::Error) -> Self {
+        LabelError::Json(e)
+    }
+}
+
+impl From<reqwest::Error> for LabelError {
+    fn from(e: reqwest::Error) -> Self {
+        LabelError::Network(e)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// the message and whether the error has a source
+    fn shown(e: LabelError) -> (String, bool) {
+        let has_source = e.source().is_some();
+        (e.to_string(), has_source)
+    }
+
+    #[test]
+    fn errors_convert_and_keep_their_cause() {
+        let io = LabelError::from(io::Error::new(io::ErrorKind::NotFound, "no such file"));
+        assert!(matches!(io, LabelError::Io(ref e) if e.kind() == io::ErrorKind::NotFound));
+        assert_eq!(shown(io), ("no such file".to_string(), true));
+        let image = LabelError::from(image::load_from_memory(b"no image").unwrap_err());
+        assert!(matches!(image, LabelError::Image(_)));
+        assert!(shown(image).1);
+        let json = LabelError::from(serde_json::from_str::<u32>("{").unwrap_err());
+        assert!(matches!(json, LabelError::Json(_)));
+        assert!(shown(json).1);
+        let network = LabelError::from(reqwest::blocking::Client::new().get("no url").send().unwrap_err());
+        assert!(matches!(network, LabelError::Network(_)));
+        assert!(shown(network).1);
+    }
+
+    #[test]
+    fn printer_errors_name_their_details() {
+        assert_eq!(shown(LabelError::Status { status: 503 }), ("Printer answered with status 503".to_string(), false));
+        assert_eq!(shown(LabelError::UploadFailed { index: 960, status: 413 }).0, "Upload failed at index 960 with status 413");
+        assert_eq!(shown(LabelError::HeadMismatch { head: 128, height: 96 }).0, "The print head has 128 dots, but the label is 96 px high");
+        assert_eq!(shown(LabelError::Font("Invalid font file: a.ttf".to_string())), ("Invalid font file: a.ttf".to_string(), false));
+        assert_eq!(shown(LabelError::InvalidInput("bad".to_string())).0, "bad");
+        assert_eq!(shown(LabelError::Transport("offline".to_string())).0, "offline");
+    }
+}
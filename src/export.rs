@@ -0,0 +1,377 @@
+//! Export of the label bitmap into image files for other tools.
+
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+use image::{ImageBuffer, Luma, Rgba, RgbaImage, imageops};
+use serde::{Deserialize, Serialize};
+
+use crate::error::LabelError;
+use crate::render::{PackingOptions, get_bitmap_data_with, unpack_bitmap_data};
+
+/// what the blank (not printed) pixels become in an export
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Background {
+    White,
+    Transparent,
+}
+
+/// convert the first `width` columns into rgba, printed pixels become opaque black
+pub fn to_rgba(img: &ImageBuffer<Luma<u8>, Vec<u8>>, width: u32, background: Background) -> RgbaImage {
+    let width = width.clamp(1, img.width());
+    let cropped = imageops::crop_imm(img, 0, 0, width, img.height()).to_image();
+    let blank = match background {
+        Background::White => Rgba([255, 255, 255, 255]),
+        Background::Transparent => Rgba([0, 0, 0, 0]),
+    };
+    ImageBuffer::from_fn(width, img.height(), |x, y| {
+        let Luma([val]) = *cropped.get_pixel(x, y);
+        if val < 128 { Rgba([0, 0, 0, 255]) } else { blank }
+    })
+}
+
+/// save the first `width` columns of the label as png
+pub fn export_png(img: &ImageBuffer<Luma<u8>, Vec<u8>>, width: u32, path: &Path, background: Background) -> Result<(), LabelError> {
+    to_rgba(img, width, background).save_with_format(path, image::ImageFormat::Png)?;
+    Ok(())
+}
+
+/// `dpi` in the pixels per meter of a bmp header
+fn bmp_pixels_per_meter(dpi: u32) -> u32 {
+    (dpi as f32 / 0.0254).round() as u32
+}
+
+/// a 1 bit bmp of the first `width` columns with the resolution `dpi`: palette entry 0 is
+/// black and 1 white, the rows are stored bottom-up and padded to 4 bytes
+pub fn encode_bmp(img: &ImageBuffer<Luma<u8>, Vec<u8>>, width: u32, dpi: u32) -> Vec<u8> {
+    let width = width.clamp(1, img.width().max(1));
+    let height = img.height();
+    let row_bytes = (width as usize).div_ceil(32) * 4;
+    let offset = 14 + 40 + 2 * 4;
+    let file_size = offset + row_bytes * height as usize;
+    let mut bmp = Vec::with_capacity(file_size);
+    // file header
+    bmp.extend_from_slice(b"BM");
+    bmp.extend_from_slice(&(file_size as u32).to_le_bytes());
+    bmp.extend_from_slice(&[0; 4]);
+    bmp.extend_from_slice(&(offset as u32).to_le_bytes());
+    // BITMAPINFOHEADER, a positive height means bottom-up
+    bmp.extend_from_slice(&40u32.to_le_bytes());
+    bmp.extend_from_slice(&(width as i32).to_le_bytes());
+    bmp.extend_from_slice(&(height as i32).to_le_bytes());
+    bmp.extend_from_slice(&1u16.to_le_bytes());
+    bmp.extend_from_slice(&1u16.to_le_bytes());
+    bmp.extend_from_slice(&0u32.to_le_bytes());
+    bmp.extend_from_slice(&((row_bytes * height as usize) as u32).to_le_bytes());
+    let pixels_per_meter = bmp_pixels_per_meter(dpi);
+    bmp.extend_from_slice(&pixels_per_meter.to_le_bytes());
+    bmp.extend_from_slice(&pixels_per_meter.to_le_bytes());
+    bmp.extend_from_slice(&2u32.to_le_bytes());
+    bmp.extend_from_slice(&2u32.to_le_bytes());
+    // palette: blue, green, red, reserved
+    bmp.extend_from_slice(&[0, 0, 0, 0, 255, 255, 255, 0]);
+    for y in (0..height).rev() {
+        let mut row = vec![0u8; row_bytes];
+        for x in 0..width {
+            if img.get_pixel(x, y)[0] >= 128 {
+                row[x as usize / 8] |= 0x80 >> (x % 8);
+            }
+        }
+        bmp.extend_from_slice(&row);
+    }
+    bmp
+}
+
+/// the bmp of packed label data as sent to the printer (see `get_bitmap_data_with`),
+/// `length` columns of the `width` x `height` label are kept, see `encode_bmp`
+pub fn bmp_from_packed(data: &[u8], height: u32, width: u32, length: u32, packing: &PackingOptions, dpi: u32) -> Vec<u8> {
+    let img = unpack_bitmap_data(data, height as usize, width as usize, packing);
+    encode_bmp(&img, length, dpi)
+}
+
+/// save the first `width` columns of the label as 1 bit bmp with the resolution `dpi`, made
+/// from the packed data so it shows exactly the bits of the printer
+pub fn export_bmp(img: &ImageBuffer<Luma<u8>, Vec<u8>>, width: u32, dpi: u32, path: &Path, packing: &PackingOptions) -> Result<(), LabelError> {
+    let (img_width, height) = img.dimensions();
+    let data = get_bitmap_data_with(img, height as usize, img_width as usize, packing);
+    fs::write(path, bmp_from_packed(&data, height, img_width, width, packing, dpi))?;
+    Ok(())
+}
+
+/// page size in points (1/72 inch) of a label with `width` x `height` dots printed at `dpi`
+pub fn pdf_page_size(width: u32, height: u32, dpi: u32) -> (f32, f32) {
+    let points_per_dot = 72.0 / dpi.max(1) as f32;
+    (width as f32 * points_per_dot, height as f32 * points_per_dot)
+}
+
+/// a single page pdf of the first `width` columns, the page has the physical size of the
+/// label at `dpi`. the bitmap is embedded uncompressed as 1 bit gray image, so an office
+/// printer reproduces the dots without smoothing
+pub fn encode_pdf(img: &ImageBuffer<Luma<u8>, Vec<u8>>, width: u32, dpi: u32) -> Vec<u8> {
+    let width = width.clamp(1, img.width().max(1));
+    let height = img.height();
+    let (page_width, page_height) = pdf_page_size(width, height, dpi);
+    // rows top-down, padded to whole bytes, a set bit is white in DeviceGray
+    let row_bytes = (width as usize).div_ceil(8);
+    let mut pixels = vec![0u8; row_bytes * height as usize];
+    for y in 0..height {
+        for x in 0..width {
+            if img.get_pixel(x, y)[0] >= 128 {
+                pixels[y as usize * row_bytes + x as usize / 8] |= 0x80 >> (x % 8);
+            }
+        }
+    }
+    let contents = format!("q {:.2} 0 0 {:.2} 0 0 cm /Im0 Do Q\n", page_width, page_height);
+
+    let mut pdf = b"%PDF-1.4\n%\xe2\xe3\xcf\xd3\n".to_vec();
+    let mut offsets = Vec::new();
+    let mut object = |pdf: &mut Vec<u8>, head: String, stream: Option<&[u8]>| {
+        offsets.push(pdf.len());
+        pdf.extend_from_slice(format!("{} 0 obj\n{}\n", offsets.len(), head).as_bytes());
+        if let Some(stream) = stream {
+            pdf.extend_from_slice(b"stream\n");
+            pdf.extend_from_slice(stream);
+            pdf.extend_from_slice(b"\nendstream\n");
+        }
+        pdf.extend_from_slice(b"endobj\n");
+    };
+    object(&mut pdf, "<< /Type /Catalog /Pages 2 0 R >>".to_string(), None);
+    object(&mut pdf, "<< /Type /Pages /Kids [3 0 R] /Count 1 >>".to_string(), None);
+    object(
+        &mut pdf,
+        format!("<< /Type /Page /Parent 2 0 R /MediaBox [0 0 {:.2} {:.2}] /Resources << /XObject << /Im0 4 0 R >> >> /Contents 5 0 R >>", page_width, page_height),
+        None,
+    );
+    object(
+        &mut pdf,
+        format!("<< /Type /XObject /Subtype /Image /Width {} /Height {} /ColorSpace /DeviceGray /BitsPerComponent 1 /Interpolate false /Length {} >>", width, height, pixels.len()),
+        Some(&pixels),
+    );
+    object(&mut pdf, format!("<< /Length {} >>", contents.len()), Some(contents.as_bytes()));
+
+    let xref = pdf.len();
+    pdf.extend_from_slice(format!("xref\n0 {}\n0000000000 65535 f \n", offsets.len() + 1).as_bytes());
+    for offset in &offsets {
+        pdf.extend_from_slice(format!("{:010} 00000 n \n", offset).as_bytes());
+    }
+    pdf.extend_from_slice(format!("trailer\n<< /Size {} /Root 1 0 R >>\nstartxref\n{}\n%%EOF\n", offsets.len() + 1, xref).as_bytes());
+    pdf
+}
+
+/// save the first `width` columns of the label as pdf in the size of the label at `dpi`
+pub fn export_pdf(img: &ImageBuffer<Luma<u8>, Vec<u8>>, width: u32, dpi: u32, path: &Path) -> Result<(), LabelError> {
+    fs::write(path, encode_pdf(img, width, dpi))?;
+    Ok(())
+}
+
+/// first `<stem>-0001.<ext>`, `<stem>-0002.<ext>`, ... in `dir` which does not exist yet, so
+/// repeated exports never overwrite each other. an empty `ext` gives names without a dot
+pub fn next_export_path(dir: &Path, stem: &str, ext: &str) -> PathBuf {
+    (1u32..)
+        .map(|n| match ext {
+            "" => dir.join(format!("{}-{:04}", stem, n)),
+            _ => dir.join(format!("{}-{:04}.{}", stem, n, ext)),
+        })
+        .find(|path| !path.exists())
+        .expect("a free file name")
+}
+
+/// what an export to a chosen file name does if the file exists
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum OverwritePolicy {
+    /// ask before the file is replaced
+    #[default]
+    Prompt,
+    /// replace it without asking
+    Overwrite,
+    /// write to the next free name, see `next_export_path`
+    Rename,
+}
+
+impl OverwritePolicy {
+    pub const ALL: [OverwritePolicy; 3] = [OverwritePolicy::Prompt, OverwritePolicy::Overwrite, OverwritePolicy::Rename];
+
+    /// name in the ui and the config
+    pub fn name(self) -> &'static str {
+        match self {
+            OverwritePolicy::Prompt => "prompt",
+            OverwritePolicy::Overwrite => "overwrite",
+            OverwritePolicy::Rename => "rename",
+        }
+    }
+
+    /// the policy with the `name`, the default for an unknown one
+    pub fn from_name(name: &str) -> OverwritePolicy {
+        OverwritePolicy::ALL.into_iter().find(|policy| policy.name() == name).unwrap_or_default()
+    }
+}
+
+/// where an export to a chosen file goes, see `export_destination`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ExportDestination {
+    /// the file is free or may be replaced
+    Write(PathBuf),
+    /// the file exists, the user has to confirm replacing it
+    Confirm(PathBuf),
+}
+
+/// apply `policy` to the file name chosen for an export
+pub fn export_destination(path: &Path, policy: OverwritePolicy) -> ExportDestination {
+    if !path.exists() {
+        return ExportDestination::Write(path.to_path_buf());
+    }
+    match policy {
+        OverwritePolicy::Prompt => ExportDestination::Confirm(path.to_path_buf()),
+        OverwritePolicy::Overwrite => ExportDestination::Write(path.to_path_buf()),
+        OverwritePolicy::Rename => {
+            let dir = path.parent().unwrap_or(Path::new(""));
+            let stem = path.file_stem().map(|stem| stem.to_string_lossy()).unwrap_or_default();
+            let ext = path.extension().map(|ext| ext.to_string_lossy()).unwrap_or_default();
+            ExportDestination::Write(next_export_path(dir, &stem, &ext))
+        }
+    }
+}
+
+/// save the label as png under the next free name in `dir`, the folder is created if missing
+pub fn export_png_to_folder(img: &ImageBuffer<Luma<u8>, Vec<u8>>, width: u32, dir: &Path, background: Background) -> Result<PathBuf, LabelError> {
+    fs::create_dir_all(dir)?;
+    let path = next_export_path(dir, "label", "png");
+    export_png(img, width, &path, background)?;
+    Ok(path)
+}
+
+/// save the label as bmp under the next free name in `dir`, the folder is created if missing
+pub fn export_bmp_to_folder(img: &ImageBuffer<Luma<u8>, Vec<u8>>, width: u32, dpi: u32, dir: &Path, packing: &PackingOptions) -> Result<PathBuf, LabelError> {
+    fs::create_dir_all(dir)?;
+    let path = next_export_path(dir, "label", "bmp");
+    export_bmp(img, width, dpi, &path, packing)?;
+    Ok(path)
+}
+
+/// save the label as pdf under the next free name in `dir`, the folder is created if missing
+pub fn export_pdf_to_folder(img: &ImageBuffer<Luma<u8>, Vec<u8>>, width: u32, dpi: u32, dir: &Path) -> Result<PathBuf, LabelError> {
+    fs::create_dir_all(dir)?;
+    let path = next_export_path(dir, "label", "pdf");
+    export_pdf(img, width, dpi, &path)?;
+    Ok(path)
+}
+
+/// show the folder in the file manager of the OS
+pub fn open_folder(dir: &Path) -> Result<(), LabelError> {
+    fs::create_dir_all(dir)?;
+    let program = if cfg!(target_os = "windows") {
+        "explorer"
+    } else if cfg!(target_os = "macos") {
+        "open"
+    } else {
+        "xdg-open"
+    };
+    Command::new(program).arg(dir).spawn()?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn the_bmp_header_has_the_resolution_of_the_printer() {
+        let img = ImageBuffer::from_pixel(16, 8, Luma([0u8]));
+        let bmp = encode_bmp(&img, 16, 300);
+        let pixels_per_meter = |at: usize| u32::from_le_bytes(bmp[at..at + 4].try_into().unwrap());
+        assert_eq!((pixels_per_meter(38), pixels_per_meter(42)), (11811, 11811));
+        assert_eq!(pixels_per_meter(38), bmp_pixels_per_meter(300));
+        assert_eq!(bmp_pixels_per_meter(200), 7874);
+    }
+
+    #[test]
+    fn a_transparent_export_has_no_alpha_on_blank_pixels() {
+        let img = ImageBuffer::from_fn(10, 4, |x, _| Luma([if x < 3 { 0u8 } else { 255 }]));
+        let rgba = to_rgba(&img, 6, Background::Transparent);
+        assert_eq!(rgba.dimensions(), (6, 4));
+        assert_eq!(*rgba.get_pixel(1, 2), Rgba([0, 0, 0, 255]));
+        assert_eq!(rgba.get_pixel(4, 2)[3], 0);
+        assert_eq!(*to_rgba(&img, 6, Background::White).get_pixel(4, 2), Rgba([255, 255, 255, 255]));
+        // the saved file keeps the alpha
+        let path = crate::fonts::tests::test_dir("transparent_png").join("label.png");
+        export_png(&img, 6, &path, Background::Transparent).unwrap();
+        let saved = image::open(&path).unwrap().to_rgba8();
+        assert_eq!(saved, rgba);
+    }
+
+    #[test]
+    fn exports_to_a_folder_never_overwrite() {
+        let dir = crate::fonts::tests::test_dir("export_folder");
+        assert_eq!(next_export_path(&dir, "label", "png"), dir.join("label-0001.png"));
+        let img = ImageBuffer::from_pixel(8, 4, Luma([0u8]));
+        let first = export_png_to_folder(&img, 8, &dir.join("new"), Background::White).unwrap();
+        let second = export_png_to_folder(&img, 8, &dir.join("new"), Background::White).unwrap();
+        assert_eq!(first, dir.join("new").join("label-0001.png"));
+        assert_eq!(second, dir.join("new").join("label-0002.png"));
+        // a gap is filled before higher numbers
+        fs::remove_file(&first).unwrap();
+        assert_eq!(next_export_path(&dir.join("new"), "label", "png"), first);
+        assert_eq!(next_export_path(&dir, "label", ""), dir.join("label-0001"));
+    }
+
+    #[test]
+    fn a_bmp_reopens_with_the_same_pixels() {
+        let img = ImageBuffer::from_fn(50, 9, |x, y| Luma([if (x + 2 * y) % 5 == 0 { 0u8 } else { 255 }]));
+        let bmp = encode_bmp(&img, 37, 200);
+        let u32_at = |at: usize| u32::from_le_bytes(bmp[at..at + 4].try_into().unwrap());
+        let u16_at = |at: usize| u16::from_le_bytes(bmp[at..at + 2].try_into().unwrap());
+        assert_eq!(&bmp[..2], b"BM");
+        // 37 columns are padded to 8 bytes per row
+        assert_eq!((u32_at(2) as usize, bmp.len(), u32_at(10)), (62 + 8 * 9, 62 + 8 * 9, 62));
+        assert_eq!((u32_at(18), u32_at(22), u16_at(26), u16_at(28)), (37, 9, 1, 1));
+        let expected = imageops::crop_imm(&img, 0, 0, 37, 9).to_image();
+        assert_eq!(image::load_from_memory(&bmp).unwrap().to_luma8(), expected);
+        // the file is made from the packed bits
+        let path = crate::fonts::tests::test_dir("bmp").join("label.bmp");
+        export_bmp(&img, 37, 200, &path, &PackingOptions { pad_rows: true, lsb_first: true }).unwrap();
+        assert_eq!(image::open(&path).unwrap().to_luma8(), expected);
+    }
+
+    #[test]
+    fn the_pdf_page_has_the_size_of_the_label() {
+        // 400 x 96 dots at 200 dpi are 2 x 0.48 inches
+        assert_eq!(pdf_page_size(400, 96, 200), (144.0, 34.56));
+        let img = ImageBuffer::from_pixel(500, 96, Luma([0u8]));
+        let pdf = encode_pdf(&img, 400, 200);
+        let text = String::from_utf8_lossy(&pdf);
+        assert!(text.starts_with("%PDF-1.4"));
+        assert!(text.contains("/MediaBox [0 0 144.00 34.56]"));
+        assert!(text.contains("/Width 400 /Height 96"));
+        // the image stream has 50 bytes per row
+        assert!(text.contains(&format!("/Length {} >>", 50 * 96)));
+        // the cross reference table points at the objects, offsets are in bytes
+        let startxref: usize = text.trim_end().lines().rev().nth(1).unwrap().parse().unwrap();
+        assert!(pdf[startxref..].starts_with(b"xref"));
+        let table = String::from_utf8(pdf[startxref..].to_vec()).unwrap();
+        for (n, line) in table.lines().skip(3).take(5).enumerate() {
+            let offset: usize = line[..10].parse().unwrap();
+            assert!(pdf[offset..].starts_with(format!("{} 0 obj", n + 1).as_bytes()), "{}", line);
+        }
+    }
+
+    #[test]
+    fn an_existing_file_is_detected_before_the_export() {
+        let dir = crate::fonts::tests::test_dir("overwrite");
+        let path = dir.join("box.png");
+        // a new file is written with every policy
+        for policy in OverwritePolicy::ALL {
+            assert_eq!(export_destination(&path, policy), ExportDestination::Write(path.clone()));
+        }
+        fs::write(&path, b"earlier export").unwrap();
+        assert_eq!(export_destination(&path, OverwritePolicy::Prompt), ExportDestination::Confirm(path.clone()));
+        assert_eq!(export_destination(&path, OverwritePolicy::Overwrite), ExportDestination::Write(path.clone()));
+        assert_eq!(export_destination(&path, OverwritePolicy::Rename), ExportDestination::Write(dir.join("box-0001.png")));
+        // the existing file is untouched by the check
+        assert_eq!(fs::read(&path).unwrap(), b"earlier export");
+        let bin = dir.join("label");
+        fs::write(&bin, b"").unwrap();
+        assert_eq!(export_destination(&bin, OverwritePolicy::Rename), ExportDestination::Write(dir.join("label-0001")));
+    }
+}
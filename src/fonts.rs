@@ -0,0 +1,591 @@
+//! Discovery of the installed TrueType fonts.
+
+use std::collections::{HashMap, HashSet};
+use std::collections::hash_map::DefaultHasher;
+use std::env;
+use std::fs::{self, File};
+use std::hash::{Hash, Hasher};
+use std::io::Read;
+use std::path::{Path, PathBuf};
+use std::sync::{LazyLock, Mutex};
+use std::time::{Duration, Instant, SystemTime};
+
+use ttf_parser::{Face, PlatformId};
+use unicode_normalization::{UnicodeNormalization, char::is_combining_mark};
+
+use crate::error::LabelError;
+
+/// deepest folder level below a font directory that is scanned
+pub const MAX_DIR_DEPTH: usize = 16;
+
+/// a usable font: the name shown to the user and the file it was loaded from
+#[derive(Debug, Clone)]
+pub struct FontEntry {
+    pub display_name: String,
+    pub path: String,
+}
+
+/// helper function: find all subdirectories of a directory
+pub fn find_subdirs_recursively(dir: &Path) -> Vec<PathBuf> {
+    let mut subdirs = Vec::new();
+    let mut visited = HashSet::new();
+    if let Ok(canonical) = dir.canonicalize() {
+        visited.insert(canonical);
+    }
+    collect_subdirs(dir, 0, &mut visited, &mut subdirs);
+    subdirs
+}
+
+/// add the subdirectories of `dir` to `subdirs`. directories are tracked by their
+/// canonical path in `visited`, so symlink loops and directories reachable over
+/// several links are only added once. below `MAX_DIR_DEPTH` levels the recursion stops
+fn collect_subdirs(dir: &Path, depth: usize, visited: &mut HashSet<PathBuf>, subdirs: &mut Vec<PathBuf>) {
+    if depth >= MAX_DIR_DEPTH {
+        eprintln!("Skip subfolders of {}: deeper than {} levels", dir.display(), MAX_DIR_DEPTH);
+        return;
+    }
+    if let Ok(entries) = fs::read_dir(dir) {
+        for entry in entries.flatten() {
+            let path = entry.path();
+            if path.is_dir()
+                && let Ok(canonical) = path.canonicalize()
+                && visited.insert(canonical)
+            {
+                subdirs.push(path.clone());
+                // add recursiv subdirs
+                collect_subdirs(&path, depth + 1, visited, subdirs);
+            }
+        }
+    }
+}
+
+/// get list of common font directories for the current OS
+pub fn get_system_font_dirs() -> Vec<PathBuf> {
+    let mut dirs = Vec::new();
+
+    match std::env::consts::OS {
+        // ---------------- Windows ----------------
+        "windows" => {
+            dirs.push(PathBuf::from("C:\\Windows\\Fonts"));
+        }
+
+        // ---------------- macOS ----------------
+        "macos" => {
+            dirs.push(PathBuf::from("/System/Library/Fonts"));
+            dirs.push(PathBuf::from("/System/Library/Fonts/Supplemental"));
+            if let Some(home) = dirs_next::home_dir() {
+                dirs.push(home.join("Library/Fonts"));
+            }
+        }
+
+        // ---------------- Linux & BSD ----------------
+        "linux" | "freebsd" | "openbsd" | "netbsd" => {
+            dirs.push(PathBuf::from("/usr/share/fonts"));
+            dirs.push(PathBuf::from("/usr/local/share/fonts"));
+            if let Some(home) = dirs_next::home_dir() {
+                dirs.push(home.join(".fonts"));
+                dirs.push(home.join(".local/share/fonts"));
+            }
+        }
+
+        // ---------------- Fallback ----------------
+        other => {
+            eprintln!("unknown OS: {} – no font dirs estimated", other);
+        }
+    }
+
+    // one visited set for all roots, e.g. Fonts/Supplemental is also below Fonts
+    let mut all_dirs = Vec::new();
+    let mut visited = HashSet::new();
+    for dir in &dirs {
+        if let Ok(canonical) = dir.canonicalize()
+            && !visited.insert(canonical)
+        {
+            continue;
+        }
+        all_dirs.push(dir.clone());
+        collect_subdirs(dir, 0, &mut visited, &mut all_dirs);
+    }
+
+    all_dirs
+}
+
+/// result of a font scan
+#[derive(Debug, Clone, Default)]
+pub struct FontScan {
+    /// found fonts, sorted by display name
+    pub entries: Vec<FontEntry>,
+    /// number of directories which could be read
+    pub readable_dirs: usize,
+    /// directories which could not be read and the reason
+    pub skipped_dirs: Vec<(PathBuf, String)>,
+    /// font files which were skipped without parsing because the same file or an
+    /// identical copy was already scanned
+    pub duplicate_files: usize,
+    /// the time budget ran out, the entries are only the fonts found until then
+    pub truncated: bool,
+}
+
+/// device and inode of a file, the same for hard links and for paths through symlinks
+#[cfg(unix)]
+fn file_id(path: &Path) -> Option<(u64, u64)> {
+    use std::os::unix::fs::MetadataExt;
+    fs::metadata(path).ok().map(|m| (m.dev(), m.ino()))
+}
+
+#[cfg(not(unix))]
+fn file_id(_path: &Path) -> Option<(u64, u64)> {
+    None
+}
+
+/// hash of the file content to recognize copies of a font in different folders
+fn content_hash(data: &[u8]) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    data.hash(&mut hasher);
+    hasher.finish()
+}
+
+/// what a scan learned about a font file, reused by later scans while the size and the
+/// modification time of the file stay the same
+#[derive(Debug, Clone, PartialEq)]
+struct ScannedFile {
+    size: u64,
+    modified: Option<SystemTime>,
+    hash: u64,
+    /// per preferred language: `None` for a file which is no font, else the display name
+    /// from the name table if it has one
+    names: HashMap<String, Option<Option<String>>>,
+}
+
+/// font files of the previous scans by path
+static SCANNED_FILES: LazyLock<Mutex<HashMap<PathBuf, ScannedFile>>> = LazyLock::new(|| Mutex::new(HashMap::new()));
+
+/// hash and display name of the font file at `path`. the file is only read and parsed
+/// if it changed since the last scan or the name in `preferred` is not known yet
+fn scan_file(path: &Path, preferred: &str) -> Option<ScannedFile> {
+    let metadata = fs::metadata(path).ok()?;
+    let (size, modified) = (metadata.len(), metadata.modified().ok());
+    let mut files = SCANNED_FILES.lock().unwrap_or_else(|e| e.into_inner());
+    let cached = files.get(path).filter(|file| file.size == size && file.modified == modified);
+    if let Some(file) = cached
+        && file.names.contains_key(preferred)
+    {
+        return Some(file.clone());
+    }
+    let mut data = Vec::new();
+    File::open(path).ok()?.read_to_end(&mut data).ok()?;
+    let hash = content_hash(&data);
+    let name = Face::parse(&data, 0).ok().map(|face| font_display_name(&face, preferred));
+    let mut file = match cached {
+        Some(file) if file.hash == hash => file.clone(),
+        _ => ScannedFile { size, modified, hash, names: HashMap::new() },
+    };
+    file.names.insert(preferred.to_string(), name);
+    files.insert(path.to_path_buf(), file.clone());
+    Some(file)
+}
+
+/// locale of the environment like "de_DE.UTF-8", empty if none is set
+pub fn environment_locale() -> String {
+    ["LC_ALL", "LC_MESSAGES", "LANG"]
+        .iter()
+        .find_map(|var| env::var(var).ok().filter(|v| !v.is_empty()))
+        .unwrap_or_default()
+}
+
+/// primary language of the windows name table for a tag like "en", "de_DE.UTF-8" or
+/// "system" (the locale of the environment)
+fn primary_language_id(tag: &str) -> u16 {
+    let tag = if tag == "system" {
+        environment_locale()
+    } else {
+        tag.to_string()
+    };
+    match tag.get(..2).unwrap_or_default().to_ascii_lowercase().as_str() {
+        "zh" => 0x04,
+        "de" => 0x07,
+        "es" => 0x0A,
+        "fr" => 0x0C,
+        "it" => 0x10,
+        "ja" => 0x11,
+        "ko" => 0x12,
+        "nl" => 0x13,
+        "pl" => 0x15,
+        "pt" => 0x16,
+        "ru" => 0x19,
+        _ => ENGLISH,
+    }
+}
+
+/// primary language id of English in the windows name table
+const ENGLISH: u16 = 0x09;
+
+/// full name of the font from the name table. a name in the preferred language wins,
+/// then an English one, then any readable one
+pub fn font_display_name(face: &Face, preferred: &str) -> Option<String> {
+    let preferred = primary_language_id(preferred);
+    face.names()
+        .into_iter()
+        .filter(|n| n.name_id == ttf_parser::name_id::FULL_NAME)
+        .filter_map(|n| {
+            let (name, language) = match n.platform_id {
+                PlatformId::Windows => (n.to_string()?, n.language_id & 0x3FF),
+                // mac roman, only the ascii part is decoded
+                PlatformId::Macintosh if n.encoding_id == 0 && n.name.is_ascii() => {
+                    let language = if n.language_id == 0 { ENGLISH } else { 0 };
+                    (String::from_utf8_lossy(n.name).into_owned(), language)
+                }
+                _ => (n.to_string()?, 0),
+            };
+            let rank = if language == preferred { 0 } else if language == ENGLISH { 1 } else { 2 };
+            Some((rank, name))
+        })
+        .filter(|(_, name)| !name.trim().is_empty())
+        .min_by_key(|(rank, _)| *rank)
+        .map(|(_, name)| name)
+}
+
+/// scan the given directories for `.ttf` files, deduplicated by font name.
+/// files seen before (same inode or same content) are skipped before parsing, files
+/// unchanged since an earlier scan aren't read again.
+/// `preferred` is the language tag for the display names, see `font_display_name`.
+/// with a `budget` the scan stops when the time is used up and keeps what it found
+pub fn scan_font_dirs(font_dirs: &[PathBuf], preferred: &str, budget: Option<Duration>) -> FontScan {
+    let deadline = budget.map(|budget| Instant::now() + budget);
+    let out_of_time = || deadline.is_some_and(|deadline| Instant::now() >= deadline);
+    let mut scan = FontScan::default();
+    let mut font_entries: Vec<FontEntry> = Vec::new();
+    let mut seen_fonts = HashSet::new();
+    let mut seen_files = HashSet::new();
+    let mut seen_contents = HashSet::new();
+    'dirs: for font_dir in font_dirs {
+        if out_of_time() {
+            scan.truncated = true;
+            break;
+        }
+        eprintln!("Scan folder: {}", font_dir.display());
+        let entries = match fs::read_dir(font_dir) {
+            Ok(entries) => entries,
+            Err(e) => {
+                eprintln!("Skip folder {}: {}", font_dir.display(), e);
+                // a missing default folder is normal, only unreadable ones are reported
+                if e.kind() != std::io::ErrorKind::NotFound {
+                    scan.skipped_dirs.push((font_dir.clone(), e.to_string()));
+                }
+                continue;
+            }
+        };
+        scan.readable_dirs += 1;
+        for entry in entries.flatten() {
+            let path = entry.path();
+            if let Some(ext) = path.extension().and_then(|e| e.to_str())
+                && ext.eq_ignore_ascii_case("ttf")
+            {
+                if out_of_time() {
+                    scan.truncated = true;
+                    break 'dirs;
+                }
+                // the same file reached a second time, e.g. through a link
+                if let Some(id) = file_id(&path)
+                    && !seen_files.insert(id)
+                {
+                    scan.duplicate_files += 1;
+                    continue;
+                }
+                let Some(scanned) = scan_file(&path, preferred) else {
+                    continue;
+                };
+                // an identical copy in another folder
+                if !seen_contents.insert(scanned.hash) {
+                    scan.duplicate_files += 1;
+                    continue;
+                }
+                if let Some(Some(name)) = scanned.names.get(preferred) {
+                    // font name extraction
+                    let name = name
+                        .clone()
+                        .unwrap_or_else(|| {
+                            path.file_stem()
+                                .unwrap_or_default()
+                                .to_string_lossy()
+                                .into_owned()
+                        });
+                    // deduplicate by name
+                    if seen_fonts.insert(name.clone()) {
+                        font_entries.push(FontEntry {
+                            display_name: name,
+                            path: path.to_string_lossy().into_owned(),
+                        });
+                    }
+                }
+            }
+        }
+    }
+
+    if scan.duplicate_files > 0 {
+        eprintln!("Skipped {} duplicate font files", scan.duplicate_files);
+    }
+    if scan.truncated {
+        eprintln!("Font scan stopped after {:?}, {} fonts found until then", budget.unwrap_or_default(), font_entries.len());
+    }
+    sort_font_entries(&mut font_entries);
+    scan.entries = font_entries;
+    scan
+}
+
+/// key for a natural sort order: accents removed and case folded, so "Émoji" sorts next to "emoji"
+fn sort_key(name: &str) -> String {
+    name.nfkd()
+        .filter(|c| !is_combining_mark(*c))
+        .flat_map(char::to_lowercase)
+        .collect()
+}
+
+/// sort alphabetically by display_name, ignoring case and accents.
+/// the exact name breaks ties so the order is deterministic
+pub fn sort_font_entries(font_entries: &mut [FontEntry]) {
+    font_entries.sort_by_cached_key(|entry| (sort_key(&entry.display_name), entry.display_name.clone()));
+}
+
+/// indices of the fonts whose display name contains `filter`, ignoring case
+pub fn filter_fonts(font_entries: &[FontEntry], filter: &str) -> Vec<usize> {
+    let filter = filter.trim().to_lowercase();
+    font_entries
+        .iter()
+        .enumerate()
+        .filter(|(_, entry)| entry.display_name.to_lowercase().contains(&filter))
+        .map(|(i, _)| i)
+        .collect()
+}
+
+/// move the fonts of `indices` named in `favorites` to the front. both parts keep their
+/// order, so the favorites stay sorted like the rest
+pub fn favorites_first(indices: &mut [usize], font_entries: &[FontEntry], favorites: &[String]) {
+    indices.sort_by_key(|&i| !favorites.contains(&font_entries[i].display_name));
+}
+
+/// scan all system font directories, `preferred` is the language tag for the display names.
+/// the scan stops after `budget`, if given, see `scan_font_dirs`
+pub fn scan_fonts(preferred: &str, budget: Option<Duration>) -> FontScan {
+    scan_font_dirs(&get_system_font_dirs(), preferred, budget)
+}
+
+/// characters of `text` without a glyph in the font at `font_path`, each once in the order
+/// of the text. they would print as blanks. white space and control characters are skipped
+pub fn missing_glyphs(font_path: &str, text: &str) -> Result<Vec<char>, LabelError> {
+    let data = fs::read(font_path)?;
+    let face = Face::parse(&data, 0).map_err(|e| LabelError::Font(format!("Invalid font file {}: {}", font_path, e)))?;
+    let mut missing = Vec::new();
+    for c in text.chars().filter(|c| !c.is_whitespace() && !c.is_control()) {
+        if face.glyph_index(c).is_none() && !missing.contains(&c) {
+            missing.push(c);
+        }
+    }
+    Ok(missing)
+}
+
+/// the first of `fonts` with a glyph for each of `chars`, to replace a font which lacks some
+pub fn fallback_font<'a>(fonts: &'a [FontEntry], chars: &[char]) -> Option<&'a FontEntry> {
+    let text: String = chars.iter().collect();
+    fonts.iter().find(|entry| missing_glyphs(&entry.path, &text).is_ok_and(|missing| missing.is_empty()))
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use crate::render::tests::TEST_FONT;
+
+    use std::env;
+
+    /// an empty folder below the temp dir for one test
+    pub(crate) fn test_dir(name: &str) -> PathBuf {
+        let dir = env::temp_dir().join(format!("label_drawer-{}-{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    fn entries(names: &[&str]) -> Vec<FontEntry> {
+        names.iter().map(|name| FontEntry { display_name: name.to_string(), path: format!("/fonts/{}.ttf", name) }).collect()
+    }
+
+    fn names(entries: &[FontEntry]) -> Vec<&str> {
+        entries.iter().map(|entry| entry.display_name.as_str()).collect()
+    }
+
+    #[test]
+    fn fonts_sort_ignoring_case_and_accents() {
+        let mut fonts = entries(&["Zapf", "Équité", "arial", "Eras", "Arial", "Öffentlich", "ocr"]);
+        sort_font_entries(&mut fonts);
+        assert_eq!(names(&fonts), vec!["Arial", "arial", "Équité", "Eras", "ocr", "Öffentlich", "Zapf"]);
+    }
+
+    #[test]
+    fn unreadable_font_folders_are_reported() {
+        let dir = test_dir("unreadable");
+        let not_a_folder = dir.join("fonts.conf");
+        fs::write(&not_a_folder, b"").unwrap();
+        let scan = scan_font_dirs(&[dir.clone(), not_a_folder.clone(), dir.join("missing")], "en", None);
+        assert_eq!(scan.readable_dirs, 1);
+        // a missing default folder is no problem worth a warning
+        assert_eq!(scan.skipped_dirs.len(), 1);
+        assert_eq!(scan.skipped_dirs[0].0, not_a_folder);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn a_symlink_cycle_ends_the_folder_scan() {
+        let dir = test_dir("symlink_cycle");
+        let inner = dir.join("fonts").join("inner");
+        fs::create_dir_all(&inner).unwrap();
+        std::os::unix::fs::symlink(&dir, inner.join("back")).unwrap();
+        let subdirs = find_subdirs_recursively(&dir);
+        assert_eq!(subdirs, vec![dir.join("fonts"), inner]);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn the_folder_scan_stops_at_the_maximal_depth() {
+        let dir = test_dir("depth");
+        let deepest = (0..MAX_DIR_DEPTH + 3).fold(dir.clone(), |path, level| path.join(level.to_string()));
+        fs::create_dir_all(&deepest).unwrap();
+        // a second way into the same folder doesn't list it twice
+        std::os::unix::fs::symlink(dir.join("0").join("1"), dir.join("link")).unwrap();
+        let subdirs = find_subdirs_recursively(&dir);
+        assert_eq!(subdirs.len(), MAX_DIR_DEPTH + 1);
+        assert!(!subdirs.contains(&deepest));
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    /// `font` with its name table replaced by windows full names in the given languages
+    fn with_full_names(font: &[u8], names: &[(u16, &str)]) -> Vec<u8> {
+        let strings: Vec<Vec<u8>> = names.iter().map(|(_, name)| name.encode_utf16().flat_map(u16::to_be_bytes).collect()).collect();
+        let mut table = Vec::new();
+        for value in [0, names.len() as u16, 6 + 12 * names.len() as u16] {
+            table.extend_from_slice(&value.to_be_bytes());
+        }
+        let mut offset = 0;
+        for ((language, _), string) in names.iter().zip(&strings) {
+            for value in [3, 1, *language, ttf_parser::name_id::FULL_NAME, string.len() as u16, offset] {
+                table.extend_from_slice(&value.to_be_bytes());
+            }
+            offset += string.len() as u16;
+        }
+        table.extend(strings.concat());
+        // the new table goes to the end, its record in the table directory points there
+        let mut font = font.to_vec();
+        font.resize(font.len().next_multiple_of(4), 0);
+        let tables = u16::from_be_bytes([font[4], font[5]]) as usize;
+        let record = (0..tables).map(|i| 12 + 16 * i).find(|&at| &font[at..at + 4] == b"name").unwrap();
+        let position = font.len() as u32;
+        font[record + 8..record + 12].copy_from_slice(&position.to_be_bytes());
+        font[record + 12..record + 16].copy_from_slice(&(table.len() as u32).to_be_bytes());
+        font.extend(table);
+        font
+    }
+
+    #[test]
+    fn the_english_font_name_wins_by_default() {
+        let Ok(data) = fs::read(TEST_FONT) else { return };
+        // japanese first, so the first name of the table isn't simply taken
+        let data = with_full_names(&data, &[(0x0411, "テスト ゴシック"), (0x0409, "Test Gothic"), (0x0407, "Test Gotisch")]);
+        let face = Face::parse(&data, 0).unwrap();
+        assert_eq!(font_display_name(&face, "en").as_deref(), Some("Test Gothic"));
+        assert_eq!(font_display_name(&face, "fr").as_deref(), Some("Test Gothic"));
+        assert_eq!(font_display_name(&face, "ja").as_deref(), Some("テスト ゴシック"));
+        assert_eq!(font_display_name(&face, "de_DE.UTF-8").as_deref(), Some("Test Gotisch"));
+        let data = with_full_names(&data, &[(0x0411, "テスト ゴシック")]);
+        assert_eq!(font_display_name(&Face::parse(&data, 0).unwrap(), "en").as_deref(), Some("テスト ゴシック"));
+    }
+
+    #[test]
+    fn identical_font_files_are_scanned_once() {
+        if !Path::new(TEST_FONT).is_file() {
+            return;
+        }
+        let dir = test_dir("duplicates");
+        let (a, b) = (dir.join("a"), dir.join("b"));
+        fs::create_dir_all(&a).unwrap();
+        fs::create_dir_all(&b).unwrap();
+        fs::copy(TEST_FONT, a.join("one.ttf")).unwrap();
+        fs::copy(TEST_FONT, b.join("two.ttf")).unwrap();
+        let scan = scan_font_dirs(&[a.clone(), b.clone()], "en", None);
+        assert_eq!(scan.entries.len(), 1);
+        assert_eq!(scan.duplicate_files, 1);
+
+        // a changed file is read again, the cached hash of the copy is outdated
+        fs::write(b.join("two.ttf"), b"no font").unwrap();
+        let scan = scan_font_dirs(&[a, b], "en", None);
+        assert_eq!(scan.entries.len(), 1);
+        assert_eq!(scan.duplicate_files, 0);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn an_unchanged_file_comes_from_the_cache() {
+        if !Path::new(TEST_FONT).is_file() {
+            return;
+        }
+        let dir = test_dir("cache");
+        let path = dir.join("font.ttf");
+        fs::copy(TEST_FONT, &path).unwrap();
+        let first = scan_file(&path, "en").unwrap();
+        assert!(matches!(first.names.get("en"), Some(Some(Some(_)))));
+        // other bytes with the same size and modification time aren't read
+        let modified = fs::metadata(&path).unwrap().modified().unwrap();
+        fs::write(&path, vec![0u8; first.size as usize]).unwrap();
+        File::options().write(true).open(&path).unwrap().set_modified(modified).unwrap();
+        assert_eq!(scan_file(&path, "en"), Some(first));
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn characters_without_a_glyph_are_reported() {
+        if !Path::new(TEST_FONT).is_file() {
+            return;
+        }
+        assert_eq!(missing_glyphs(TEST_FONT, "Box 漢字\t漢 äß").unwrap(), vec!['漢', '字']);
+        assert!(missing_glyphs(TEST_FONT, "Größe 12\n").unwrap().is_empty());
+        let entry = |path: &str| FontEntry { display_name: path.to_string(), path: path.to_string() };
+        let fonts = [entry("/nonexistent/font.ttf"), entry(TEST_FONT)];
+        assert_eq!(fallback_font(&fonts, &['€']).map(|font| font.path.as_str()), Some(TEST_FONT));
+        assert!(fallback_font(&fonts, &['漢']).is_none());
+        assert!(matches!(missing_glyphs("/nonexistent/font.ttf", "a"), Err(LabelError::Io(_))));
+    }
+
+    #[test]
+    fn the_scan_stops_when_the_budget_is_used_up() {
+        if !Path::new(TEST_FONT).is_file() {
+            return;
+        }
+        let dir = test_dir("budget");
+        fs::copy(TEST_FONT, dir.join("font.ttf")).unwrap();
+        // a scan which is already too slow when it starts
+        let scan = scan_font_dirs(&[dir.clone(), dir.join("more")], "en", Some(Duration::ZERO));
+        assert!(scan.truncated);
+        assert!(scan.entries.is_empty());
+        assert_eq!(scan.readable_dirs, 0);
+        let scan = scan_font_dirs(std::slice::from_ref(&dir), "en", Some(Duration::from_secs(60)));
+        assert!(!scan.truncated);
+        let unlimited = scan_font_dirs(std::slice::from_ref(&dir), "en", None);
+        assert_eq!(names(&scan.entries), names(&unlimited.entries));
+        assert_eq!(scan.entries.len(), 1);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn favorites_sort_to_the_top() {
+        let fonts = entries(&["Arial", "Courier", "DejaVu", "Noto", "Zapf"]);
+        let mut indices: Vec<usize> = (0..fonts.len()).collect();
+        favorites_first(&mut indices, &fonts, &["Zapf".to_string(), "Courier".to_string(), "Gone".to_string()]);
+        // both parts keep the sorted order, the indices still point to the entries
+        assert_eq!(indices, vec![1, 4, 0, 2, 3]);
+        assert_eq!(names(&indices.iter().map(|&i| fonts[i].clone()).collect::<Vec<_>>()), vec!["Courier", "Zapf", "Arial", "DejaVu", "Noto"]);
+        // a filtered list keeps only its own fonts
+        let mut filtered = vec![0, 3, 4];
+        favorites_first(&mut filtered, &fonts, &["Noto".to_string()]);
+        assert_eq!(filtered, vec![3, 0, 4]);
+    }
+}
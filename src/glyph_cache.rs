@@ -0,0 +1,187 @@
+//! Cache of rasterized glyphs, shared by the text rendering of the whole process, so a
+//! batch or the live preview doesn't rasterize the same characters again and again.
+
+use std::collections::HashMap;
+use std::sync::{Arc, LazyLock, Mutex, MutexGuard};
+
+use rusttype::{PositionedGlyph, point};
+
+/// fractions of a pixel a glyph position is rounded to, in each direction
+pub const SUBPIXEL_STEPS: u32 = 4;
+/// glyphs kept in the process wide cache, see `glyph_cache`
+pub const GLYPH_CACHE_CAPACITY: usize = 4096;
+
+/// a glyph of one font at one size and subpixel offset
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+struct GlyphKey {
+    /// path of the font file
+    font: String,
+    glyph: u16,
+    /// bits of the horizontal and vertical scale
+    scale: (u32, u32),
+    /// subpixel offset in `SUBPIXEL_STEPS`
+    subpixel: (u32, u32),
+}
+
+/// coverage (0..1) of a rasterized glyph, the box is relative to the whole pixel of its origin
+#[derive(Debug, Clone, PartialEq)]
+pub struct GlyphBitmap {
+    pub min_x: i32,
+    pub min_y: i32,
+    pub width: u32,
+    pub height: u32,
+    pub coverage: Vec<f32>,
+}
+
+impl GlyphBitmap {
+    /// call `o(x, y, coverage)` for every pixel of the box, like `PositionedGlyph::draw`
+    pub fn draw(&self, mut o: impl FnMut(u32, u32, f32)) {
+        for y in 0..self.height {
+            for x in 0..self.width {
+                o(x, y, self.coverage[(y * self.width + x) as usize]);
+            }
+        }
+    }
+}
+
+/// lookups since the start or the last `GlyphCache::clear`
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct GlyphCacheStats {
+    pub hits: u64,
+    pub misses: u64,
+    pub entries: usize,
+}
+
+/// glyph bitmaps with a least recently used eviction
+pub struct GlyphCache {
+    capacity: usize,
+    /// bitmap (`None` for a blank glyph) and the tick of the last use
+    entries: HashMap<GlyphKey, (Option<Arc<GlyphBitmap>>, u64)>,
+    tick: u64,
+    hits: u64,
+    misses: u64,
+}
+
+impl GlyphCache {
+    pub fn new(capacity: usize) -> Self {
+        GlyphCache { capacity: capacity.max(1), entries: HashMap::new(), tick: 0, hits: 0, misses: 0 }
+    }
+
+    /// the bitmap of `glyph` of the font at `font_path` and the whole pixel its box is relative
+    /// to, `None` for a glyph without ink. the position is rounded to `SUBPIXEL_STEPS`
+    pub fn get(&mut self, font_path: &str, glyph: &PositionedGlyph<'_>) -> Option<(Arc<GlyphBitmap>, i32, i32)> {
+        let steps = SUBPIXEL_STEPS as i64;
+        let position = glyph.position();
+        let (qx, qy) = ((position.x * SUBPIXEL_STEPS as f32).round() as i64, (position.y * SUBPIXEL_STEPS as f32).round() as i64);
+        let origin = (qx.div_euclid(steps) as i32, qy.div_euclid(steps) as i32);
+        let subpixel = (qx.rem_euclid(steps) as u32, qy.rem_euclid(steps) as u32);
+        let scale = glyph.scale();
+        let key = GlyphKey { font: font_path.to_string(), glyph: glyph.id().0, scale: (scale.x.to_bits(), scale.y.to_bits()), subpixel };
+        self.tick += 1;
+        if let Some((bitmap, last_used)) = self.entries.get_mut(&key) {
+            *last_used = self.tick;
+            self.hits += 1;
+            return bitmap.clone().map(|bitmap| (bitmap, origin.0, origin.1));
+        }
+        self.misses += 1;
+        let offset = point(subpixel.0 as f32 / SUBPIXEL_STEPS as f32, subpixel.1 as f32 / SUBPIXEL_STEPS as f32);
+        let bitmap = rasterize(&glyph.unpositioned().clone().positioned(offset)).map(Arc::new);
+        if self.entries.len() >= self.capacity {
+            self.evict();
+        }
+        self.entries.insert(key, (bitmap.clone(), self.tick));
+        bitmap.map(|bitmap| (bitmap, origin.0, origin.1))
+    }
+
+    /// drop the least recently used quarter, so a full cache isn't scanned on every miss
+    fn evict(&mut self) {
+        let mut ticks: Vec<u64> = self.entries.values().map(|(_, last_used)| *last_used).collect();
+        let drop = (self.capacity / 4).max(1).min(ticks.len());
+        let (_, oldest_kept, _) = ticks.select_nth_unstable(drop - 1);
+        let limit = *oldest_kept;
+        self.entries.retain(|_, (_, last_used)| *last_used > limit);
+    }
+
+    pub fn stats(&self) -> GlyphCacheStats {
+        GlyphCacheStats { hits: self.hits, misses: self.misses, entries: self.entries.len() }
+    }
+
+    /// forget all glyphs and the statistics
+    pub fn clear(&mut self) {
+        self.entries.clear();
+        self.hits = 0;
+        self.misses = 0;
+    }
+}
+
+/// coverage of a glyph positioned within its first pixel
+fn rasterize(glyph: &PositionedGlyph<'_>) -> Option<GlyphBitmap> {
+    let bb = glyph.pixel_bounding_box()?;
+    let (width, height) = (bb.width() as u32, bb.height() as u32);
+    let mut coverage = vec![0.0; (width * height) as usize];
+    glyph.draw(|x, y, v| coverage[(y * width + x) as usize] = v);
+    Some(GlyphBitmap { min_x: bb.min.x, min_y: bb.min.y, width, height, coverage })
+}
+
+static GLYPH_CACHE: LazyLock<Mutex<GlyphCache>> = LazyLock::new(|| Mutex::new(GlyphCache::new(GLYPH_CACHE_CAPACITY)));
+
+/// the process wide cache
+pub fn glyph_cache() -> MutexGuard<'static, GlyphCache> {
+    GLYPH_CACHE.lock().unwrap_or_else(|e| e.into_inner())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use rusttype::{Font, Scale};
+
+    use crate::render::tests::test_font;
+
+    fn glyph<'a>(font: &Font<'a>, c: char, x: f32) -> PositionedGlyph<'a> {
+        font.glyph(c).scaled(Scale::uniform(40.0)).positioned(point(x, 30.0))
+    }
+
+    #[test]
+    fn glyphs_are_reused_and_the_least_recently_used_evicted() {
+        let Some(font) = test_font() else { return };
+        let mut cache = GlyphCache::new(8);
+        for c in 'A'..='H' {
+            assert!(cache.get("test", &glyph(&font, c, 0.0)).is_some());
+        }
+        assert_eq!(cache.stats(), GlyphCacheStats { hits: 0, misses: 8, entries: 8 });
+        // the same glyph a few pixels on is a hit with another origin, the bitmap is shared
+        let (first, _, _) = cache.get("test", &glyph(&font, 'A', 0.0)).unwrap();
+        let (moved, x, y) = cache.get("test", &glyph(&font, 'A', 3.05)).unwrap();
+        assert!(Arc::ptr_eq(&first, &moved));
+        assert_eq!((x, y), (3, 30));
+        assert_eq!(cache.stats().hits, 2);
+        // it is drawn like the glyph itself, relative to its origin
+        let direct = glyph(&font, 'A', 0.0);
+        let bb = direct.pixel_bounding_box().unwrap();
+        assert_eq!((first.min_x, first.min_y + 30, first.width, first.height), (bb.min.x, bb.min.y, bb.width() as u32, bb.height() as u32));
+        direct.draw(|x, y, v| assert_eq!(first.coverage[(y * first.width + x) as usize], v));
+
+        // a full cache drops the quarter used longest ago, 'A' was just used
+        assert!(cache.get("test", &glyph(&font, 'I', 0.0)).is_some());
+        assert_eq!(cache.stats().entries, 7);
+        cache.get("test", &glyph(&font, 'A', 0.0));
+        assert_eq!(cache.stats().misses, 9);
+        cache.get("test", &glyph(&font, 'B', 0.0));
+        cache.get("test", &glyph(&font, 'C', 0.0));
+        assert_eq!(cache.stats().misses, 11);
+        assert!(cache.stats().entries <= 8);
+
+        // another subpixel offset, size or font is another glyph, a blank one is cached too
+        cache.clear();
+        assert_eq!(cache.stats(), GlyphCacheStats::default());
+        cache.get("test", &glyph(&font, 'A', 0.0));
+        cache.get("test", &glyph(&font, 'A', 0.5));
+        cache.get("other", &glyph(&font, 'A', 0.0));
+        cache.get("test", &font.glyph('A').scaled(Scale::uniform(41.0)).positioned(point(0.0, 30.0)));
+        assert_eq!(cache.stats().misses, 4);
+        assert!(cache.get("test", &glyph(&font, ' ', 0.0)).is_none());
+        assert!(cache.get("test", &glyph(&font, ' ', 0.0)).is_none());
+        assert_eq!(cache.stats(), GlyphCacheStats { hits: 1, misses: 5, entries: 5 });
+    }
+}
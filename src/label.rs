@@ -0,0 +1,190 @@
+//! Label definitions, stored as `.label.json` files.
+
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use serde::{Deserialize, Serialize};
+
+use crate::elements::ElementKind;
+use crate::error::LabelError;
+
+/// label design as stored in a `.label.json` file. the settings are kept as the app shows
+/// them, a field missing in an older file gets the value of a new app window
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct LabelDef {
+    pub text: String,
+    pub font_name: String,
+    pub font_size: i32,
+    /// lines the text is fitted to, 0 keeps `font_size`
+    pub fit_lines: i32,
+    pub fixed_pitch: bool,
+    /// cell of a fixed pitch in pixels, 0 for the widest glyph
+    pub cell_width: i32,
+    /// in percent of the font size
+    pub line_spacing: i32,
+    pub snap_to_pixels: bool,
+    pub image_path: String,
+    pub image_x_offset: i32,
+    pub image_y_offset: i32,
+    pub image_in_label: bool,
+    /// name of an `Orientation`
+    pub image_orientation: String,
+    pub level_black: i32,
+    pub level_white: i32,
+    pub level_midpoint: i32,
+    pub auto_contrast: bool,
+    /// name of a `ResizeFilter`
+    pub resize_filter: String,
+    /// name of a `Dither`
+    pub dither: String,
+    pub despeckle: bool,
+    pub letterbox_width: i32,
+    pub letterbox_black: bool,
+    pub counter_enabled: bool,
+    pub date_enabled: bool,
+    pub element_order: Vec<ElementKind>,
+    pub invert_text: bool,
+    pub invert_counter: bool,
+    pub invert_image: bool,
+    pub cut_out_text: bool,
+    pub mirror: bool,
+    /// label length the content is scaled to, 0 keeps its length
+    pub scale_to_length: i32,
+    pub two_color: bool,
+    pub red_text: bool,
+    pub red_counter: bool,
+    pub red_image: bool,
+}
+
+impl Default for LabelDef {
+    fn default() -> Self {
+        LabelDef {
+            text: String::new(),
+            font_name: String::new(),
+            font_size: 96,
+            fit_lines: 0,
+            fixed_pitch: false,
+            cell_width: 0,
+            line_spacing: 100,
+            snap_to_pixels: false,
+            image_path: String::new(),
+            image_x_offset: 0,
+            image_y_offset: 0,
+            image_in_label: false,
+            image_orientation: "upright".to_string(),
+            level_black: 0,
+            level_white: 255,
+            level_midpoint: 100,
+            auto_contrast: false,
+            resize_filter: "lanczos3".to_string(),
+            dither: "floyd-steinberg".to_string(),
+            despeckle: false,
+            letterbox_width: 0,
+            letterbox_black: false,
+            counter_enabled: false,
+            date_enabled: false,
+            element_order: ElementKind::ALL.to_vec(),
+            invert_text: false,
+            invert_counter: false,
+            invert_image: false,
+            cut_out_text: false,
+            mirror: false,
+            scale_to_length: 0,
+            two_color: false,
+            red_text: false,
+            red_counter: false,
+            red_image: false,
+        }
+    }
+}
+
+/// image types the app loads, as offered in the file dialog
+pub const IMAGE_EXTENSIONS: [&str; 6] = ["png", "jpg", "jpeg", "bmp", "gif", "svg"];
+
+/// a file the app was started with, e.g. by the file manager
+#[derive(Debug, Clone, PartialEq)]
+pub enum OpenTarget {
+    Label(PathBuf),
+    Image(PathBuf),
+}
+
+/// what to do with a start argument, by the file extension. options and other
+/// files give `None`
+pub fn open_target(arg: &str) -> Option<OpenTarget> {
+    if arg.starts_with("--") {
+        return None;
+    }
+    let path = PathBuf::from(arg);
+    let name = path.file_name()?.to_str()?.to_lowercase();
+    if name.ends_with(".json") {
+        return Some(OpenTarget::Label(path));
+    }
+    let extension = name.rsplit_once('.')?.1;
+    IMAGE_EXTENSIONS.contains(&extension).then_some(OpenTarget::Image(path))
+}
+
+/// write a label definition as pretty printed json
+pub fn save_label_def(path: &Path, label: &LabelDef) -> Result<(), LabelError> {
+    let json = serde_json::to_string_pretty(label)?;
+    fs::write(path, json)?;
+    Ok(())
+}
+
+/// read a label definition from a json file
+pub fn load_label_def(path: &Path) -> Result<LabelDef, LabelError> {
+    let json = fs::read_to_string(path)?;
+    Ok(serde_json::from_str(&json)?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn a_label_round_trips_through_its_file() {
+        let label = LabelDef {
+            text: "Kühlschrank\nFach 2".to_string(),
+            font_name: "DejaVu Sans Bold".to_string(),
+            font_size: 40,
+            fit_lines: 2,
+            fixed_pitch: true,
+            cell_width: 30,
+            line_spacing: 120,
+            image_path: "/tmp/logo.png".to_string(),
+            image_x_offset: 200,
+            image_y_offset: -4,
+            image_in_label: true,
+            image_orientation: "auto".to_string(),
+            level_white: 200,
+            dither: "serpentine".to_string(),
+            despeckle: true,
+            counter_enabled: true,
+            element_order: vec![ElementKind::Image, ElementKind::Text, ElementKind::Date, ElementKind::Counter],
+            invert_text: true,
+            cut_out_text: true,
+            mirror: true,
+            scale_to_length: 600,
+            two_color: true,
+            red_text: true,
+            ..LabelDef::default()
+        };
+        let dir = std::env::temp_dir().join("label_drawer_round_trip");
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("fach.label.json");
+        save_label_def(&path, &label).unwrap();
+        assert_eq!(load_label_def(&path).unwrap(), label);
+    }
+
+    #[test]
+    fn a_missing_field_gets_its_default() {
+        let dir = std::env::temp_dir().join("label_drawer_missing_field");
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("old.label.json");
+        fs::write(&path, r#"{"text": "Keller", "image_x_offset": 10}"#).unwrap();
+        let label = load_label_def(&path).unwrap();
+        assert_eq!(label, LabelDef { text: "Keller".to_string(), image_x_offset: 10, ..LabelDef::default() });
+        assert_eq!(label.font_size, 96);
+        assert_eq!(label.element_order, ElementKind::ALL.to_vec());
+    }
+}
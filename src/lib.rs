@@ -0,0 +1,93 @@
+//! Core of the label drawer: font discovery, rendering of text and images into a
+//! 1 bit label bitmap, bit packing and the transfer to the pimped KL-780.
+//!
+//! The GUI in `main.rs` is only a thin frontend on top of this crate, so the same
+//! functions can be used headless or embedded in another tool:
+//!
+//! ```no_run
+//! use std::sync::Arc;
+//! use label_drawer::{create_image_with_text, get_bitmap_data_with, write_image, print_image};
+//! use label_drawer::{fonts::scan_fonts, printer::set_transport, render::TextOptions, Config, LABEL_WIDTH};
+//!
+//! // the printer of the selected profile, `write_image` and `print_image` go to it
+//! let profile = Config::load().profile();
+//! set_transport(Arc::new(profile.transport()));
+//! let height = profile.head_height as usize;
+//! let fonts = scan_fonts("en", None).entries;
+//! let mut used_len = 0;
+//! let img = create_image_with_text(LABEL_WIDTH, height, "Hello", &fonts[0].path, &TextOptions::default(), &mut used_len).unwrap();
+//! write_image(get_bitmap_data_with(&img, height, LABEL_WIDTH, &profile.packing)).unwrap();
+//! print_image(used_len as u32).unwrap();
+//! ```
+//!
+//! Started with `--batch` the binary reads json label specs from stdin, `--report <file>`
+//! saves a summary of the batch, see [`batch`].
+//! `--print-bitmap <file>` sends a ready made bitmap without rendering, see [`bitmap`].
+//! `--text-file <file>` prints the text of a file, see [`batch::text_file_spec`].
+
+pub mod batch;
+pub mod bitmap;
+pub mod compress;
+pub mod config;
+pub mod discovery;
+pub mod elements;
+pub mod error;
+pub mod export;
+pub mod fonts;
+pub mod glyph_cache;
+pub mod label;
+pub mod printer;
+pub mod profile;
+pub mod render;
+pub mod transform;
+
+pub use config::Config;
+pub use elements::{LabelElement, compose, compose_dithered};
+pub use error::LabelError;
+pub use fonts::FontEntry;
+pub use label::{LabelDef, load_label_def, save_label_def};
+pub use printer::{print_image, write_image};
+pub use render::{PackingOptions, create_image_with_text, create_images_with_text, get_bitmap_data, get_bitmap_data_with, load_source_image, place_image, render_image};
+
+/// maximal label length in pixels
+pub const LABEL_WIDTH: usize = 2000;
+/// label height in pixels, equals the dot count of the print head
+pub const LABEL_HEIGHT: usize = 96;
+/// resolution of the print head in dots per inch
+pub const PRINTER_DPI: u32 = 200;
+
+/// pixels per inch of a logical pixel of the ui at scale factor 1
+pub const LOGICAL_DPI: f32 = 96.0;
+
+/// logical ui pixels per printer dot for a preview in the physical size of the label.
+/// `screen_dpi` are the physical pixels per inch of the monitor, if missing a logical pixel
+/// is taken as 1/96 inch as the os intends it with `scale_factor`
+pub fn true_size_scale(printer_dpi: u32, screen_dpi: Option<f32>, scale_factor: f32) -> f32 {
+    let logical_dpi = match screen_dpi {
+        Some(dpi) if dpi > 0.0 => dpi / scale_factor.max(0.01),
+        _ => LOGICAL_DPI,
+    };
+    logical_dpi / printer_dpi.max(1) as f32
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn the_true_size_scale_follows_both_resolutions() {
+        // an os without the monitor resolution: 96 logical pixels per inch
+        assert_eq!(true_size_scale(200, None, 1.0), 0.48);
+        assert_eq!(true_size_scale(200, None, 2.0), 0.48);
+        assert_eq!(true_size_scale(300, None, 1.0), 0.32);
+        // 192 physical pixels per inch at a scale factor of 2 are still 96 logical ones
+        assert_eq!(true_size_scale(200, Some(192.0), 2.0), 0.48);
+        assert_eq!(true_size_scale(200, Some(120.0), 1.0), 0.6);
+        // a label of 1 inch is 1 inch on the screen
+        assert_eq!(200.0 * true_size_scale(200, Some(144.0), 1.5), 96.0);
+        // nonsense values don't divide by 0
+        assert_eq!(true_size_scale(200, Some(0.0), 1.0), 0.48);
+        assert_eq!(true_size_scale(0, None, 1.0), 96.0);
+        assert!(true_size_scale(200, Some(96.0), 0.0).is_finite());
+    }
+}
@@ -1,193 +1,326 @@
 #![cfg_attr(not(debug_assertions), windows_subsystem = "windows")]
 
-use base64::{engine::general_purpose, Engine as _};
-use serde_json::json;
-use reqwest::blocking::Client;
-use image::{GenericImageView, Luma, ImageBuffer, imageops::{self, FilterType, dither, BiLevel}};
-use rusttype::{Font, Scale};
-use slint::{Image, Rgba8Pixel, SharedPixelBuffer, ModelRc, VecModel, SharedString};
-use std::{fs, fs::File, io::Read};
-use ttf_parser::Face;
-use rfd::FileDialog;
+use image::{GrayImage, ImageBuffer, Luma};
+use slint::{CloseRequestResponse, Image, PhysicalPosition, PhysicalSize, Rgba8Pixel, SharedPixelBuffer, Model, ModelNotify, ModelRc, ModelTracker, SharedString, Timer, TimerMode, VecModel};
+use slint::winit_030::WinitWindowAccessor;
+use rfd::{FileDialog, MessageButtons, MessageDialog, MessageDialogResult, MessageLevel};
 use std::env;
+use std::io::{self, IsTerminal, Read};
 
+use std::cell::RefCell;
+use std::collections::HashMap;
 use std::error::Error;
-use std::path::PathBuf;
-use std::path::Path;
-use std::collections::HashSet;
+use std::path::{Path, PathBuf};
+use std::rc::Rc;
+use std::sync::{Arc, Mutex};
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+
+use label_drawer::{compose, LabelError, load_label_def, load_source_image, save_label_def};
+use label_drawer::{Config, LabelDef, LabelElement, LABEL_WIDTH, true_size_scale};
+use label_drawer::batch::{process_spec, run_batch, set_fallback_image, run_batch_parallel, text_file_spec, write_report};
+use label_drawer::bitmap::{hex_preview, print_bitmap_file};
+use label_drawer::config::{PrintStats, WindowGeometry};
+use label_drawer::discovery::{is_reachable, scan_subnet};
+use label_drawer::elements::{compose_dithered_with, compose_gray, compose_two_color, move_element, snap_to_grid, CounterElement, CutOutElement, DateElement, DateStamp, ElementKind, ImageElement, InkColor, InvertedElement, TextElement, TicketCounter};
+use label_drawer::export::{ExportDestination, OverwritePolicy, export_destination, export_bmp, export_bmp_to_folder, export_pdf, export_pdf_to_folder, export_png, export_png_to_folder, open_folder, Background};
+use label_drawer::fonts::{environment_locale, fallback_font, favorites_first, filter_fonts, missing_glyphs, scan_fonts, FontEntry};
+use label_drawer::label::{open_target, OpenTarget, IMAGE_EXTENSIONS};
+use label_drawer::printer::{length_with_feed, print_bands, parse_raw_body, poll_transport_status, send_raw, set_transport, verify_head_width, transport, LabelTransport, PrinterState, UploadOutcome};
+use label_drawer::profile::{preset, presets, PrinterProfile};
+use label_drawer::render::{MAX_FONT_SIZE, MIN_FONT_SIZE, Orientation, VerticalClip, despeckle, split_into_bands, nudge_font_size, luma_histogram, sanitize_label_text, self_test, split_planes, unpack_bitmap_data, Dither, Letterbox, Levels, Overflow, Pitch, ResizeFilter, TextOptions};
+use label_drawer::transform::{add_lead_in, add_margins, draw_registration_mark, content_columns, crop_region, mirror_used_columns, pad_to_width, RegistrationMark, repeat_to_length, scale_to_length, shift_left, snap_length};
 
 slint::include_modules!();
 
-#[derive(Debug, Clone)]
-struct FontEntry {
-    display_name: SharedString,
-    path: String,
-}
+fn main() -> Result<(), Box<dyn Error>> {
+    let config = Config::load();
+    set_transport(Arc::new(config.profile().transport()));
 
-/// helper function: find all subdirectories of a directory
-fn find_subdirs_recursively(dir: &Path) -> Vec<PathBuf> {
-    let mut subdirs = Vec::new();
-    if let Ok(entries) = fs::read_dir(dir) {
-        for entry in entries.flatten() {
-            let path = entry.path();
-            if path.is_dir() {
-                subdirs.push(path.clone());
-                // add recursiv subdirs
-                subdirs.extend(find_subdirs_recursively(&path));
+    let args: Vec<String> = env::args().collect();
+    // headless: json label specs on stdin, results on stdout
+    if args.iter().any(|arg| arg == "--batch") {
+        let fonts = scan_fonts(&config.font_name_language, config.font_scan_budget()).entries;
+        set_fallback_image(config.fallback_image.clone());
+        let results = match args.iter().position(|arg| arg == "--printers") {
+            Some(pos) => {
+                let names = args.get(pos + 1).ok_or("--printers needs profile names")?;
+                let mut printers: Vec<(PrinterProfile, Arc<dyn LabelTransport>)> = Vec::new();
+                for name in names.split(',').map(str::trim).filter(|name| !name.is_empty()) {
+                    let profile = config.profiles.iter().find(|p| p.name == name).ok_or_else(|| format!("no printer profile named '{}'", name))?;
+                    printers.push((profile.clone(), Arc::new(profile.transport())));
+                }
+                let (results, summaries) = run_batch_parallel(io::stdin().lock(), io::stdout(), &fonts, &printers)?;
+                for summary in summaries {
+                    eprintln!("{}: {} printed, {} failed", summary.name, summary.printed, summary.failed);
+                }
+                results
             }
+            None => run_batch(io::stdin().lock(), io::stdout().lock(), &fonts, &config.profile())?,
+        };
+        if let Some(pos) = args.iter().position(|arg| arg == "--report") {
+            let path = args.get(pos + 1).ok_or("--report needs a file")?;
+            write_report(&results, Path::new(path))?;
+            eprintln!("Report of {} labels saved: {}", results.len(), path);
         }
+        return Ok(());
     }
-    subdirs
-}
-
-/// get list of common font directories for the current OS
-fn get_system_font_dirs() -> Vec<PathBuf> {
-    let mut dirs = Vec::new();
-
-    match std::env::consts::OS {
-        // ---------------- Windows ----------------
-        "windows" => {
-            dirs.push(PathBuf::from("C:\\Windows\\Fonts"));
-        }
-
-        // ---------------- macOS ----------------
-        "macos" => {
-            dirs.push(PathBuf::from("/System/Library/Fonts"));
-            dirs.push(PathBuf::from("/System/Library/Fonts/Supplemental"));
-            if let Some(home) = dirs_next::home_dir() {
-                dirs.push(home.join("Library/Fonts"));
-            }
-        }
-
-        // ---------------- Linux & BSD ----------------
-        "linux" | "freebsd" | "openbsd" | "netbsd" => {
-            dirs.push(PathBuf::from("/usr/share/fonts"));
-            dirs.push(PathBuf::from("/usr/local/share/fonts"));
-            if let Some(home) = dirs_next::home_dir() {
-                dirs.push(home.join(".fonts"));
-                dirs.push(home.join(".local/share/fonts"));
-            }
-        }
-
-        // ---------------- Fallback ----------------
-        other => {
-            eprintln!("unknown OS: {} – no font dirs estimated", other);
-        }
+    // headless: print a bitmap file as it is
+    if let Some(pos) = args.iter().position(|arg| arg == "--print-bitmap") {
+        let path = args.get(pos + 1).ok_or("--print-bitmap needs a file")?;
+        let profile = config.profile();
+        let length = print_bitmap_file(Path::new(path), profile.head_height, &profile.packing, 1)?;
+        println!("Bitmap printed, {} columns", length);
+        return Ok(());
     }
-
-    let mut all_dirs = Vec::new();
-    for dir in &dirs {
-        all_dirs.push(dir.clone());
-        all_dirs.extend(find_subdirs_recursively(dir));
+    // headless: print the text of a file with the default font
+    if let Some(pos) = args.iter().position(|arg| arg == "--text-file") {
+        let path = args.get(pos + 1).ok_or("--text-file needs a file")?;
+        let fonts = scan_fonts(&config.font_name_language, config.font_scan_budget()).entries;
+        let font = config.default_font.clone().or_else(|| fonts.first().map(|entry| entry.display_name.clone())).ok_or("no font found")?;
+        let length = process_spec(&text_file_spec(Path::new(path), &font)?, &fonts, &config.profile())?;
+        println!("Label printed, {} columns", length);
+        return Ok(());
     }
 
-    all_dirs
-}
-
-fn main() -> Result<(), Box<dyn Error>> {
     let ui = AppWindow::new()?;
 
-    // scan fonts
-    let font_dirs = get_system_font_dirs();
-    let mut font_entries: Vec<FontEntry> = Vec::new();
-    let mut font_names: Vec<SharedString> = Vec::new();
-    let mut seen_fonts = HashSet::new();
-    for font_dir in font_dirs {
-        println!("Scan folder: {}", font_dir.display());
-        if let Ok(entries) = fs::read_dir(font_dir) {
-            for entry in entries.flatten() {
-                let path = entry.path();
-                if let Some(ext) = path.extension().and_then(|e| e.to_str()) {
-                    if ext.eq_ignore_ascii_case("ttf") {
-                        // load font file
-                        if let Ok(mut file) = File::open(&path) {
-                            let mut data = Vec::new();
-                            if file.read_to_end(&mut data).is_ok() {
-                                if let Ok(face) = Face::parse(&data, 0) {
-                                    // font name extraction
-                                    let name = face
-                                        .names()
-                                        .into_iter()
-                                        .find(|n| n.name_id == ttf_parser::name_id::FULL_NAME)
-                                        .and_then(|n| n.to_string())
-                                        .unwrap_or_else(|| {
-                                            path.file_stem()
-                                                .unwrap_or_default()
-                                                .to_string_lossy()
-                                                .into_owned()
-                                        });
-                                    // deduplicate by name
-                                    if seen_fonts.insert(name.clone()) {
-                                        let entry = FontEntry {
-                                            display_name: SharedString::from(name.clone()),
-                                            path: path.to_string_lossy().into_owned(),
-                                        };
-
-                                        font_entries.push(entry);
-                                        font_names.push(SharedString::from(name));
-                                    }
-                                }
-                            }
-                        }
-                    }
-                }
-            }
-        }
+    let config = Rc::new(RefCell::new(config));
+    // last rendered label, used for the exports
+    let current_label: Rc<RefCell<Option<GrayImage>>> = Rc::new(RefCell::new(None));
+    // cancel token of the newest upload, set by the cancel button and by the next upload
+    let uploads = UploadControl::default();
+    // restarted by every change of the text or the image settings, so a fast series of
+    // edits gives one render. the generation discards outdated worker renders
+    let preview_timer = Rc::new(Timer::default());
+    let preview_generation = Arc::new(AtomicU64::new(0));
+    ui.set_confirm_print(config.borrow().confirm_print);
+    ui.set_font_name_language(config.borrow().font_name_language.as_str().into());
+    ui.set_ui_language(config.borrow().ui_language.as_str().into());
+    ui.set_ui_theme(config.borrow().ui_theme.as_str().into());
+    select_ui_language(&config.borrow().ui_language);
+    ui.set_feed_after_print(config.borrow().feed_after_print as i32);
+    ui.set_label_bands(config.borrow().label_bands.clamp(1, 4) as i32);
+    ui.set_lead_in(config.borrow().lead_in.min(i32::MAX as u32) as i32);
+    ui.set_length_multiple(config.borrow().length_multiple.min(i32::MAX as u32) as i32);
+    ui.set_exact_preview(config.borrow().exact_preview);
+    ui.set_true_size_preview(config.borrow().true_size_preview);
+    show_true_size_scale(&ui, &config.borrow());
+    ui.set_grid_size(config.borrow().grid_size.min(i32::MAX as u32) as i32);
+    ui.set_developer_mode(config.borrow().developer_mode);
+    show_stats(&ui, &config.borrow());
+    ui.set_text_overflow(config.borrow().text_overflow.name().into());
+    ui.set_overwrite_policy(config.borrow().overwrite_policy.name().into());
+    ui.set_min_font_size(MIN_FONT_SIZE);
+    ui.set_max_font_size(MAX_FONT_SIZE);
+    ui.set_text_baseline(config.borrow().text_baseline.round() as i32);
+    ui.set_glyph_threshold((config.borrow().glyph_threshold * 100.0).round().clamp(1.0, 99.0) as i32);
+    show_counter(&ui, &config.borrow().counter);
+    let date_stamp = config.borrow().date_stamp.clone();
+    ui.set_date_enabled(date_stamp.enabled);
+    ui.set_date_format(date_stamp.format.into());
+    ui.set_date_timezone(date_stamp.timezone.into());
+    show_element_order(&ui, &ElementKind::complete_order(&config.borrow().element_order));
+    if let Some(dir) = config.borrow().export_folder.as_ref() {
+        ui.set_export_folder(dir.display().to_string().into());
+    }
+    let mark = config.borrow().registration_mark;
+    ui.set_mark_enabled(mark.enabled);
+    ui.set_mark_x(mark.x.min(i32::MAX as u32) as i32);
+    ui.set_mark_y(mark.y.min(i32::MAX as u32) as i32);
+    ui.set_mark_width(mark.width.min(i32::MAX as u32) as i32);
+    ui.set_mark_height(mark.height.min(i32::MAX as u32) as i32);
+    let profile_names: Vec<SharedString> = config.borrow().profile_names().iter().map(|name| name.as_str().into()).collect();
+    ui.set_profile_names(ModelRc::new(VecModel::from(profile_names)));
+    ui.set_profile_name(config.borrow().profile().name.as_str().into());
+    ui.set_density(config.borrow().profile().density.unwrap_or(0) as i32);
+    ui.set_two_color(config.borrow().profile().two_color);
+    let preset_names: Vec<SharedString> = presets().iter().map(|p| p.name.as_str().into()).collect();
+    ui.set_preset_names(ModelRc::new(VecModel::from(preset_names)));
+    if let Some(geometry) = config.borrow().window {
+        set_window_geometry(&ui, geometry.clamped(&[]));
     }
-    
-    // sort alphabetically by display_name
-    font_entries.sort_by(|a, b| a.display_name.cmp(&b.display_name));
-    let font_names: Vec<SharedString> = font_entries
-        .iter()
-        .map(|entry| entry.display_name.clone())
-        .collect();
 
+    // scan fonts
+    let font_scan = scan_fonts(&config.borrow().font_name_language, config.borrow().font_scan_budget());
+    if font_scan.readable_dirs == 0 {
+        let reasons: Vec<String> = font_scan
+            .skipped_dirs
+            .iter()
+            .map(|(dir, reason)| format!("{}: {}", dir.display(), reason))
+            .collect();
+        set_status(&ui, StatusLevel::Warning, &format!("No font folder could be read. {}", reasons.join(", ")));
+    } else if font_scan.truncated {
+        set_status(&ui, StatusLevel::Warning, &format!("The font scan took longer than {} s, not all fonts are listed.", config.borrow().font_scan_seconds));
+    }
+    let font_list = Rc::new(RefCell::new(FontList::new(font_scan.entries, config.borrow().favorite_fonts.clone())));
 
     // set font names in UI
-    ui.set_fonts(ModelRc::new(VecModel::from(font_names)));
+    ui.set_font_items(ModelRc::from(font_list.borrow().model.clone()));
+    if font_list.borrow().entries.is_empty() {
+        set_status(&ui, StatusLevel::Error, "No fonts found. Please install a TrueType or OpenType font and rescan the fonts.");
+    } else if let Some(name) = config.borrow().default_font.as_deref() {
+        match font_list.borrow().index_of(name) {
+            Some(index) => ui.set_font_index(index),
+            None => set_status(&ui, StatusLevel::Warning, &format!("Default font '{}' not found, please choose another font.", name)),
+        }
+    }
+    if config.borrow().self_test {
+        let result = match font_list.borrow().selected(ui.get_font_index()) {
+            Some(entry) => self_test(&entry.path),
+            None => Err(LabelError::Font("no font found".to_string())),
+        };
+        match result {
+            Ok(pixels) => eprintln!("Self test passed, {} pixels printed", pixels),
+            Err(e) => {
+                eprintln!("Self test failed: {}", e);
+                set_status(&ui, StatusLevel::Error, &format!("Self test failed, text rendering doesn't work: {}", e));
+            }
+        }
+    }
 
     ui.on_request_create_label({
         let ui_handle = ui.as_weak();
-        let font_entries = font_entries.clone();
+        let config = config.clone();
+        let font_list = font_list.clone();
+        let current_label = current_label.clone();
+        let uploads = uploads.clone();
+        let preview_timer = preview_timer.clone();
+        let preview_generation = preview_generation.clone();
         move || {
-            let ui = ui_handle.unwrap();
-            let label_text = ui.get_label_text();
-            let font_index = ui.get_font_index();
-            let mut font_path = String::from("/usr/share/fonts/truetype/msttcorefonts/arial.ttf"); // Default
-
-            if let Some(entry) = font_entries.get(font_index as usize) {
-                font_path = entry.path.clone();
-                println!(
-                    "Label '{}' with font: {} (Path: {})",
-                    label_text, entry.display_name, entry.path
-                );
+            let Some(ui) = ui_handle.upgrade() else { return };
+            clear_status(&ui);
+            // a preview waiting or still rendering is outdated by this label
+            preview_timer.stop();
+            preview_generation.fetch_add(1, Ordering::SeqCst);
+            let profile = config.borrow().profile();
+            let Some(content) = content_from_ui(&ui, &config.borrow(), &font_list.borrow(), &profile) else { return };
+            match render_content(&content) {
+                Ok(rendered) => {
+                    show_rendered(&ui, &rendered);
+                    finish_label(&ui, rendered.img, rendered.used_len, rendered.preview, &profile, &current_label, &uploads);
+                }
+                Err(message) => set_status(&ui, StatusLevel::Error, &message),
             }
-
-            println!("Label '{}' with font: {}", label_text, font_path);
-
-            let width = 2000;
-            let height = 96;
-            let mut used_len = 0;
-            let img: ImageBuffer<Luma<u8>, Vec<u8>> = create_image_with_text(width, height,label_text.as_str(), font_path.as_str(), &mut used_len);
-            let byte_data =  get_bitmap_data(img.clone(), height, width);
-            let _ = write_image(byte_data);
-            ui.set_print_width(used_len as i32);
-            let slint_image = get_slint_img(img, height as u32, width as u32);
-            ui.set_previewimage(slint_image);
         }
     });
     ui.on_request_print_label({
         let ui_handle = ui.as_weak();
+        let config = config.clone();
+        let current_label = current_label.clone();
         move || {
-            let ui = ui_handle.unwrap();
-            let length = ui.get_print_width();
-            let _ = print_image(length as u32);
+            let Some(ui) = ui_handle.upgrade() else { return };
+            clear_status(&ui);
+            if ui.get_print_width() <= 0 {
+                set_status(&ui, StatusLevel::Warning, "Nothing to print, create a label with content first.");
+                return;
+            }
+            if ui.get_confirm_print() {
+                let profile = config.borrow().profile();
+                let length = ui.get_print_width().max(0) as u32;
+                let height = current_label.borrow().as_ref().map_or(profile.head_height, |img| img.height());
+                ui.set_print_summary(format!(
+                    "Printer: {}\nSize: {} x {} px ({:.1} x {:.1} mm)\nCopies: {}",
+                    profile.name, length, height, profile.px_to_mm(length), profile.px_to_mm(height), ui.get_copies()
+                ).into());
+                ui.invoke_show_print_confirmation();
+            } else {
+                print_label(&ui, &config.borrow().profile(), &current_label.borrow());
+            }
+        }
+    });
+    ui.on_print_finished({
+        let ui_handle = ui.as_weak();
+        let config = config.clone();
+        move |printed, length, completed| {
+            let Some(ui) = ui_handle.upgrade() else { return };
+            record_prints(&ui, &config, printed.max(0) as u32, length.max(0) as u32);
+            if completed {
+                advance_counter(&ui, &config);
+            }
+        }
+    });
+    ui.on_confirm_print_label({
+        let ui_handle = ui.as_weak();
+        let config = config.clone();
+        let current_label = current_label.clone();
+        move || {
+            if let Some(ui) = ui_handle.upgrade() {
+                print_label(&ui, &config.borrow().profile(), &current_label.borrow());
+            }
+        }
+    });
+    ui.on_settings_changed({
+        let ui_handle = ui.as_weak();
+        let config = config.clone();
+        let font_list = font_list.clone();
+        move || {
+            let Some(ui) = ui_handle.upgrade() else { return };
+            let mut config = config.borrow_mut();
+            config.confirm_print = ui.get_confirm_print();
+            config.feed_after_print = ui.get_feed_after_print().max(0) as u32;
+            config.lead_in = ui.get_lead_in().max(0) as u32;
+            config.length_multiple = ui.get_length_multiple().max(0) as u32;
+            config.exact_preview = ui.get_exact_preview();
+            config.true_size_preview = ui.get_true_size_preview();
+            // the window may be on another monitor than at the start
+            show_true_size_scale(&ui, &config);
+            config.grid_size = ui.get_grid_size().max(0) as u32;
+            let density = match ui.get_density() {
+                0 => None,
+                density => Some(density.clamp(1, u8::MAX as i32) as u8),
+            };
+            if density != config.profile().density {
+                config.set_profile_density(density);
+                set_transport(Arc::new(config.profile().transport()));
+            }
+            if ui.get_two_color() != config.profile().two_color {
+                config.set_profile_two_color(ui.get_two_color());
+                set_transport(Arc::new(config.profile().transport()));
+                // the upload has the other format
+                ui.set_print_width(0);
+            }
+            config.date_stamp.enabled = ui.get_date_enabled();
+            config.date_stamp.format = ui.get_date_format().to_string();
+            config.date_stamp.timezone = ui.get_date_timezone().to_string();
+            config.counter.enabled = ui.get_counter_enabled();
+            config.counter.format = ui.get_counter_format().to_string();
+            config.counter.padding = ui.get_counter_padding().max(0) as usize;
+            config.counter.start = ui.get_counter_start().max(0) as u64;
+            config.registration_mark = mark_from_ui(&ui);
+            config.glyph_threshold = ui.get_glyph_threshold().clamp(1, 99) as f32 / 100.0;
+            config.text_overflow = Overflow::from_name(&ui.get_text_overflow());
+            config.overwrite_policy = OverwritePolicy::from_name(&ui.get_overwrite_policy());
+            config.text_baseline = ui.get_text_baseline() as f32;
+            config.ui_theme = ui.get_ui_theme().to_string();
+            let ui_language = ui.get_ui_language().to_string();
+            if ui_language != config.ui_language {
+                select_ui_language(&ui_language);
+                config.ui_language = ui_language;
+            }
+            let font_name_language = ui.get_font_name_language().to_string();
+            if font_name_language != config.font_name_language {
+                // the display names come from the scan
+                font_list.borrow_mut().set_entries(scan_fonts(&font_name_language, config.font_scan_budget()).entries);
+                ui.set_font_filter("".into());
+                ui.set_font_index(0);
+                config.font_name_language = font_name_language;
+            }
+            config.label_bands = ui.get_label_bands().max(1) as u32;
+            if let Err(e) = config.save() {
+                set_status(&ui, StatusLevel::Warning, &format!("Settings could not be saved: {}", e));
+            }
         }
     });
     ui.on_load_image({
         let ui_handle = ui.as_weak();
+        let config = config.clone();
+        let current_label = current_label.clone();
+        let uploads = uploads.clone();
         move || {
-            let ui = ui_handle.unwrap();
+            let Some(ui) = ui_handle.upgrade() else { return };
+            clear_status(&ui);
             let mut image_path = ui.get_image_path().to_string();
             let mut start_folder = "";
             if env::consts::OS == "windows" {
@@ -199,7 +332,7 @@ fn main() -> Result<(), Box<dyn Error>> {
             }
             if image_path.is_empty() || !Path::new(&image_path).exists() {
                 let file_dialog = FileDialog::new()
-                    .add_filter("Image Files", &["png", "jpg", "jpeg", "bmp", "gif"])
+                    .add_filter("Image Files", &IMAGE_EXTENSIONS)
                     .set_directory(start_folder)
                     .set_title("pick image file")
                     .pick_file();
@@ -214,111 +347,1762 @@ fn main() -> Result<(), Box<dyn Error>> {
             }
             println!("Load image: {}", image_path);
             if Path::new(&image_path).exists() {
-                let img = image::open(&image_path);
+                let profile = config.borrow().profile();
+                let height = label_height(&ui, &profile);
+                let img = load_source_image(Path::new(&image_path), height);
                 match img {
                     Ok(img) => {
-                        // target size
-                        let target_height = 96u32;
-                        let target_width = 2000u32;
-
-                        // scaler proportionally
-                        let (orig_w, orig_h) = img.dimensions();
-                        let scale = target_height as f32 / orig_h as f32;
-                        let new_w = (orig_w as f32 * scale).round() as u32;
-
-                        // scale image
-                        let resized = img.resize_exact(new_w, target_height, FilterType::Lanczos3).to_luma8();
-
-                        // dithern
-                        let mut dithered = resized.clone();
-                        dither(&mut dithered, &BiLevel);
-
-                        // create final image with white background
-                        let mut final_img = ImageBuffer::from_pixel(target_width, target_height, Luma([255u8]));
-                        imageops::replace(&mut final_img, &dithered, 0, 0);
-
-                        let byte_data = get_bitmap_data(final_img.clone(), target_height as usize, target_width as usize);
-                        let _ = write_image(byte_data);
-                        ui.set_print_width(new_w as i32);
-                        let slint_image = get_slint_img(final_img, target_height, target_width);
-                        ui.set_previewimage(slint_image);
+                        let before = luma_histogram(&img.to_luma8());
+                        let (image_x, image_y) = image_offsets_from_ui(&ui);
+                        let image = ImageElement::new(img, image_x, image_y).with_levels(levels_from_ui(&ui)).with_letterbox(letterbox_from_ui(&ui)).with_auto_contrast(ui.get_auto_contrast()).with_filter(ResizeFilter::from_name(&ui.get_resize_filter())).with_dither(Dither::from_name(&ui.get_dither())).with_orientation(Orientation::from_name(&ui.get_image_orientation()));
+                        show_histograms(&ui, &before, &luma_histogram(&image.gray(height)));
+                        ui.set_image_rotated(image.is_rotated(height));
+                        let elements: Vec<Box<dyn LabelElement>> = vec![inverted_if(Box::new(image), ui.get_invert_image())];
+                        let (final_img, used_len) = cleaned(compose_dithered_with(&elements, LABEL_WIDTH as u32, height, Dither::from_name(&ui.get_dither())), ui.get_despeckle());
+                        // the source image is not needed anymore
+                        drop(elements);
+
+                        // the preview shows the dithering, a smooth image would hide the effect of the levels
+                        finish_label(&ui, final_img, used_len, None, &profile, &current_label, &uploads);
                         ui.set_image_path(image_path.into());
                     }
-                    Err(e) => {
-                        eprintln!("Error during loading the image: {}", e);
-                    }
+                    // the previous preview and upload stay untouched
+                    Err(e) => set_status(&ui, StatusLevel::Error, &image_error_message(&image_path, &e)),
                 }
             } else {
-                eprintln!("Path do not exist: {}", image_path);
+                set_status(&ui, StatusLevel::Error, &format!("Path do not exist: {}", image_path));
                 ui.set_image_path("".into());
             }
         }
     });
-    ui.run()?;
+    ui.on_nudge_font_size({
+        let ui_handle = ui.as_weak();
+        move |steps| {
+            let Some(ui) = ui_handle.upgrade() else { return };
+            let size = nudge_font_size(ui.get_font_size(), steps);
+            if size == ui.get_font_size() {
+                return;
+            }
+            ui.set_font_size(size);
+            // a held key gives one render after the last step
+            ui.invoke_text_settings_changed();
+        }
+    });
+    ui.on_text_settings_changed({
+        let ui_handle = ui.as_weak();
+        let config = config.clone();
+        let font_list = font_list.clone();
+        let current_label = current_label.clone();
+        let uploads = uploads.clone();
+        let preview_timer = preview_timer.clone();
+        let generation = preview_generation.clone();
+        move || {
+            // only a shown label follows the edits, creating the first one is left to the button
+            if current_label.borrow().is_none() {
+                return;
+            }
+            let ui_handle = ui_handle.clone();
+            let (config, font_list, uploads, generation) = (config.clone(), font_list.clone(), uploads.clone(), generation.clone());
+            preview_timer.start(TimerMode::SingleShot, PREVIEW_DELAY, move || {
+                let Some(ui) = ui_handle.upgrade() else { return };
+                let profile = config.borrow().profile();
+                let Some(content) = content_from_ui(&ui, &config.borrow(), &font_list.borrow(), &profile) else { return };
+                start_text_preview_render(&ui, content, profile, uploads.clone(), &generation);
+            });
+        }
+    });
+    ui.on_levels_changed({
+        let ui_handle = ui.as_weak();
+        let config = config.clone();
+        let preview_timer = preview_timer.clone();
+        let generation = preview_generation.clone();
+        move || {
+            let ui_handle = ui_handle.clone();
+            let config = config.clone();
+            let generation = generation.clone();
+            preview_timer.start(TimerMode::SingleShot, PREVIEW_DELAY, move || {
+                let Some(ui) = ui_handle.upgrade() else { return };
+                start_preview_render(&ui, label_height(&ui, &config.borrow().profile()), &generation);
+            });
+        }
+    });
+    ui.on_paste_label_text({
+        let ui_handle = ui.as_weak();
+        move || {
+            let Some(ui) = ui_handle.upgrade() else { return };
+            clear_status(&ui);
+            let text = match arboard::Clipboard::new().and_then(|mut clipboard| clipboard.get_text()) {
+                Ok(text) => sanitize_label_text(&text),
+                Err(e) => {
+                    set_status(&ui, StatusLevel::Warning, &format!("Nothing to paste, the clipboard has no text: {}", e));
+                    return;
+                }
+            };
+            if text.trim().is_empty() {
+                set_status(&ui, StatusLevel::Warning, "Nothing to paste, the clipboard text is empty.");
+                return;
+            }
+            ui.set_label_text(text.into());
+            ui.invoke_request_create_label();
+        }
+    });
+    ui.on_label_text_edited({
+        let ui_handle = ui.as_weak();
+        move |text| {
+            let Some(ui) = ui_handle.upgrade() else { return };
+            // text pasted with ctrl+v may contain tabs or carriage returns
+            let sanitized = sanitize_label_text(text.as_str());
+            if sanitized != text.as_str() {
+                ui.set_label_text(sanitized.into());
+            }
+            ui.invoke_text_settings_changed();
+        }
+    });
+    ui.on_reset_counter({
+        let ui_handle = ui.as_weak();
+        let config = config.clone();
+        move || {
+            let Some(ui) = ui_handle.upgrade() else { return };
+            let mut config = config.borrow_mut();
+            config.counter.start = ui.get_counter_start().max(0) as u64;
+            config.counter.reset();
+            if let Err(e) = config.save() {
+                set_status(&ui, StatusLevel::Warning, &format!("Settings could not be saved: {}", e));
+            }
+            show_counter(&ui, &config.counter);
+        }
+    });
+    ui.on_reset_stats({
+        let ui_handle = ui.as_weak();
+        let config = config.clone();
+        move || {
+            let Some(ui) = ui_handle.upgrade() else { return };
+            let mut config = config.borrow_mut();
+            config.session_stats = PrintStats::default();
+            config.lifetime_stats = PrintStats::default();
+            if let Err(e) = config.save() {
+                set_status(&ui, StatusLevel::Warning, &format!("Settings could not be saved: {}", e));
+            }
+            show_stats(&ui, &config);
+        }
+    });
+    ui.on_export_label({
+        let ui_handle = ui.as_weak();
+        let font_list = font_list.clone();
+        let config = config.clone();
+        move || {
+            let Some(ui) = ui_handle.upgrade() else { return };
+            clear_status(&ui);
+            let font_name = font_list
+                .borrow()
+                .selected(ui.get_font_index())
+                .map(|entry| entry.display_name.clone())
+                .unwrap_or_default();
+            let label = LabelDef {
+                text: ui.get_label_text().to_string(),
+                font_name,
+                font_size: ui.get_font_size(),
+                fit_lines: ui.get_fit_lines(),
+                fixed_pitch: ui.get_fixed_pitch(),
+                cell_width: ui.get_cell_width(),
+                line_spacing: ui.get_line_spacing(),
+                snap_to_pixels: ui.get_snap_to_pixels(),
+                image_path: ui.get_image_path().to_string(),
+                image_x_offset: ui.get_image_x_offset(),
+                image_y_offset: ui.get_image_y_offset(),
+                image_in_label: ui.get_image_in_label(),
+                image_orientation: ui.get_image_orientation().to_string(),
+                level_black: ui.get_level_black(),
+                level_white: ui.get_level_white(),
+                level_midpoint: ui.get_level_midpoint(),
+                auto_contrast: ui.get_auto_contrast(),
+                resize_filter: ui.get_resize_filter().to_string(),
+                dither: ui.get_dither().to_string(),
+                despeckle: ui.get_despeckle(),
+                letterbox_width: ui.get_letterbox_width(),
+                letterbox_black: ui.get_letterbox_black(),
+                counter_enabled: ui.get_counter_enabled(),
+                date_enabled: ui.get_date_enabled(),
+                element_order: ElementKind::complete_order(&config.borrow().element_order),
+                invert_text: ui.get_invert_text(),
+                invert_counter: ui.get_invert_counter(),
+                invert_image: ui.get_invert_image(),
+                cut_out_text: ui.get_cut_out_text(),
+                mirror: ui.get_mirror(),
+                scale_to_length: ui.get_scale_to_length(),
+                two_color: ui.get_two_color(),
+                red_text: ui.get_red_text(),
+                red_counter: ui.get_red_counter(),
+                red_image: ui.get_red_image(),
+            };
+            let Some(path) = FileDialog::new()
+                .add_filter("Label Files", &["json"])
+                .set_file_name("label.label.json")
+                .set_title("save label")
+                .save_file()
+            else {
+                return;
+            };
+            let Some(path) = confirmed_destination(&path, config.borrow().overwrite_policy) else {
+                return;
+            };
+            match save_label_def(&path, &label) {
+                Ok(()) => set_status(&ui, StatusLevel::Success, &format!("Label saved: {}", path.display())),
+                Err(e) => set_status(&ui, StatusLevel::Error, &format!("Error during saving the label: {}", e)),
+            }
+        }
+    });
+    ui.on_import_label({
+        let ui_handle = ui.as_weak();
+        let config = config.clone();
+        let font_list = font_list.clone();
+        move || {
+            let Some(ui) = ui_handle.upgrade() else { return };
+            clear_status(&ui);
+            let Some(path) = FileDialog::new()
+                .add_filter("Label Files", &["json"])
+                .set_title("open label")
+                .pick_file()
+            else {
+                return;
+            };
+            restore_label(&ui, &mut font_list.borrow_mut(), &mut config.borrow_mut(), &path);
+        }
+    });
+    ui.on_toggle_favorite_font({
+        let ui_handle = ui.as_weak();
+        let config = config.clone();
+        let font_list = font_list.clone();
+        move |index| {
+            let Some(ui) = ui_handle.upgrade() else { return };
+            let mut font_list = font_list.borrow_mut();
+            let Some(name) = font_list.selected(index).map(|entry| entry.display_name.clone()) else { return };
+            let selected = font_list.selected(ui.get_font_index()).map(|entry| entry.display_name.clone());
+            let mut config = config.borrow_mut();
+            config.toggle_favorite_font(&name);
+            font_list.set_favorites(config.favorite_fonts.clone());
+            // the selected font moved with the new order
+            if let Some(index) = selected.and_then(|selected| font_list.index_of(&selected)) {
+                ui.set_font_index(index);
+            }
+            if let Err(e) = config.save() {
+                set_status(&ui, StatusLevel::Warning, &format!("Settings could not be saved: {}", e));
+            }
+        }
+    });
+    ui.on_send_raw_request({
+        let ui_handle = ui.as_weak();
+        let config = config.clone();
+        move || {
+            let Some(ui) = ui_handle.upgrade() else { return };
+            let body = match parse_raw_body(&ui.get_raw_body()) {
+                Ok(body) => body,
+                Err(e) => {
+                    ui.set_raw_response(e.to_string().into());
+                    return;
+                }
+            };
+            let (method, path) = (ui.get_raw_method().to_string(), ui.get_raw_path().to_string());
+            let base_url = config.borrow().profile().host;
+            ui.set_raw_response(format!("{} {}{} ...", method, base_url, path).into());
+            let ui_handle = ui.as_weak();
+            thread::spawn(move || {
+                let text = match send_raw(&base_url, &method, &path, &body) {
+                    Ok(response) => format!(
+                        "Status {}, {} bytes\n{}\n{}",
+                        response.status,
+                        response.body.len(),
+                        hex_preview(&response.body, HEX_PREVIEW_EDGE),
+                        String::from_utf8_lossy(&response.body)
+                    ),
+                    Err(e) => format!("Request failed: {}", e),
+                };
+                let _ = ui_handle.upgrade_in_event_loop(move |ui| ui.set_raw_response(text.into()));
+            });
+        }
+    });
+    ui.on_use_fallback_font({
+        let ui_handle = ui.as_weak();
+        let font_list = font_list.clone();
+        move || {
+            let Some(ui) = ui_handle.upgrade() else { return };
+            let name = ui.get_fallback_font().to_string();
+            let index = {
+                let mut font_list = font_list.borrow_mut();
+                // the fallback may be hidden by the filter
+                if font_list.index_of(&name).is_none() {
+                    font_list.set_filter("");
+                    ui.set_font_filter("".into());
+                }
+                font_list.index_of(&name)
+            };
+            if let Some(index) = index {
+                ui.set_font_index(index);
+                ui.invoke_request_create_label();
+            }
+        }
+    });
+    ui.on_font_filter_edited({
+        let ui_handle = ui.as_weak();
+        let font_list = font_list.clone();
+        move |filter| {
+            let Some(ui) = ui_handle.upgrade() else { return };
+            let mut font_list = font_list.borrow_mut();
+            font_list.set_filter(filter.as_str());
+            ui.set_font_index(0);
+            if font_list.visible.is_empty() {
+                set_status(&ui, StatusLevel::Warning, &format!("No font matches '{}'.", filter));
+            } else {
+                clear_status(&ui);
+            }
+        }
+    });
+    ui.on_cancel_upload({
+        let uploads = uploads.clone();
+        move || {
+            uploads.cancel();
+        }
+    });
+    ui.on_print_selection({
+        let ui_handle = ui.as_weak();
+        let config = config.clone();
+        let current_label = current_label.clone();
+        let uploads = uploads.clone();
+        move || {
+            let Some(ui) = ui_handle.upgrade() else { return };
+            clear_status(&ui);
+            let current_label = current_label.borrow();
+            let Some(img) = current_label.as_ref() else {
+                set_status(&ui, StatusLevel::Warning, "Nothing to print, create a label first.");
+                return;
+            };
+            let (x, y) = (ui.get_selection_x().max(0) as u32, ui.get_selection_y().max(0) as u32);
+            let (width, height) = (ui.get_selection_width().max(0) as u32, ui.get_selection_height().max(0) as u32);
+            let (selection, length) = crop_region(img, x, y, width, height);
+            if length == 0 {
+                set_status(&ui, StatusLevel::Warning, "The selection is outside of the label.");
+                return;
+            }
+            let profile = config.borrow().profile();
+            let (width, height) = selection.dimensions();
+            let byte_data = profile.pack(&selection);
+            show_packed_bytes(&ui, &byte_data, width, height);
+            start_upload(&ui, byte_data, length, height, &uploads, true);
+        }
+    });
+    ui.on_export_png({
+        let ui_handle = ui.as_weak();
+        let current_label = current_label.clone();
+        let config = config.clone();
+        move || {
+            let Some(ui) = ui_handle.upgrade() else { return };
+            clear_status(&ui);
+            let current_label = current_label.borrow();
+            let Some(img) = current_label.as_ref() else {
+                set_status(&ui, StatusLevel::Warning, "Nothing to export, create a label first.");
+                return;
+            };
+            let background = if ui.get_transparent_export() { Background::Transparent } else { Background::White };
+            if let Some(dir) = config.borrow().export_folder.as_ref() {
+                match export_png_to_folder(img, ui.get_print_width().max(1) as u32, dir, background) {
+                    Ok(path) => set_status(&ui, StatusLevel::Success, &format!("PNG exported: {}", path.display())),
+                    Err(e) => set_status(&ui, StatusLevel::Error, &format!("Error during exporting the png: {}", e)),
+                }
+                return;
+            }
+            let Some(path) = FileDialog::new()
+                .add_filter("PNG Files", &["png"])
+                .set_file_name("label.png")
+                .set_title("export png")
+                .save_file()
+            else {
+                return;
+            };
+            let Some(path) = confirmed_destination(&path, config.borrow().overwrite_policy) else {
+                return;
+            };
+            match export_png(img, ui.get_print_width().max(1) as u32, &path, background) {
+                Ok(()) => set_status(&ui, StatusLevel::Success, &format!("PNG exported: {}", path.display())),
+                Err(e) => set_status(&ui, StatusLevel::Error, &format!("Error during exporting the png: {}", e)),
+            }
+        }
+    });
+    ui.on_export_bmp({
+        let ui_handle = ui.as_weak();
+        let current_label = current_label.clone();
+        let config = config.clone();
+        move || {
+            let Some(ui) = ui_handle.upgrade() else { return };
+            clear_status(&ui);
+            let current_label = current_label.borrow();
+            let Some(img) = current_label.as_ref() else {
+                set_status(&ui, StatusLevel::Warning, "Nothing to export, create a label first.");
+                return;
+            };
+            let profile = config.borrow().profile();
+            let (packing, dpi) = (profile.packing, profile.dpi);
+            if let Some(dir) = config.borrow().export_folder.as_ref() {
+                match export_bmp_to_folder(img, ui.get_print_width().max(1) as u32, dpi, dir, &packing) {
+                    Ok(path) => set_status(&ui, StatusLevel::Success, &format!("BMP exported: {}", path.display())),
+                    Err(e) => set_status(&ui, StatusLevel::Error, &format!("Error during exporting the bmp: {}", e)),
+                }
+                return;
+            }
+            let Some(path) = FileDialog::new()
+                .add_filter("BMP Files", &["bmp"])
+                .set_file_name("label.bmp")
+                .set_title("export bmp")
+                .save_file()
+            else {
+                return;
+            };
+            let Some(path) = confirmed_destination(&path, config.borrow().overwrite_policy) else {
+                return;
+            };
+            match export_bmp(img, ui.get_print_width().max(1) as u32, dpi, &path, &packing) {
+                Ok(()) => set_status(&ui, StatusLevel::Success, &format!("BMP exported: {}", path.display())),
+                Err(e) => set_status(&ui, StatusLevel::Error, &format!("Error during exporting the bmp: {}", e)),
+            }
+        }
+    });
+    ui.on_export_pdf({
+        let ui_handle = ui.as_weak();
+        let current_label = current_label.clone();
+        let config = config.clone();
+        move || {
+            let Some(ui) = ui_handle.upgrade() else { return };
+            clear_status(&ui);
+            let current_label = current_label.borrow();
+            let Some(img) = current_label.as_ref() else {
+                set_status(&ui, StatusLevel::Warning, "Nothing to export, create a label first.");
+                return;
+            };
+            let dpi = config.borrow().profile().dpi;
+            if let Some(dir) = config.borrow().export_folder.as_ref() {
+                match export_pdf_to_folder(img, ui.get_print_width().max(1) as u32, dpi, dir) {
+                    Ok(path) => set_status(&ui, StatusLevel::Success, &format!("PDF exported: {}", path.display())),
+                    Err(e) => set_status(&ui, StatusLevel::Error, &format!("Error during exporting the pdf: {}", e)),
+                }
+                return;
+            }
+            let Some(path) = FileDialog::new()
+                .add_filter("PDF Files", &["pdf"])
+                .set_file_name("label.pdf")
+                .set_title("export pdf")
+                .save_file()
+            else {
+                return;
+            };
+            let Some(path) = confirmed_destination(&path, config.borrow().overwrite_policy) else {
+                return;
+            };
+            match export_pdf(img, ui.get_print_width().max(1) as u32, dpi, &path) {
+                Ok(()) => set_status(&ui, StatusLevel::Success, &format!("PDF exported: {}", path.display())),
+                Err(e) => set_status(&ui, StatusLevel::Error, &format!("Error during exporting the pdf: {}", e)),
+            }
+        }
+    });
+    ui.on_move_element({
+        let ui_handle = ui.as_weak();
+        let config = config.clone();
+        move |from, to| {
+            let Some(ui) = ui_handle.upgrade() else { return };
+            {
+                let mut config = config.borrow_mut();
+                let mut order = ElementKind::complete_order(&config.element_order);
+                if !move_element(&mut order, from.max(0) as usize, to.max(0) as usize) {
+                    return;
+                }
+                show_element_order(&ui, &order);
+                config.element_order = order;
+                if let Err(e) = config.save() {
+                    set_status(&ui, StatusLevel::Warning, &format!("Settings could not be saved: {}", e));
+                }
+            }
+            ui.invoke_request_create_label();
+        }
+    });
+    ui.on_choose_export_folder({
+        let ui_handle = ui.as_weak();
+        let config = config.clone();
+        move || {
+            let Some(ui) = ui_handle.upgrade() else { return };
+            let Some(dir) = FileDialog::new().set_title("export folder").pick_folder() else {
+                return;
+            };
+            let mut config = config.borrow_mut();
+            ui.set_export_folder(dir.display().to_string().into());
+            config.export_folder = Some(dir);
+            if let Err(e) = config.save() {
+                set_status(&ui, StatusLevel::Warning, &format!("Settings could not be saved: {}", e));
+            }
+        }
+    });
+    ui.on_open_export_folder({
+        let ui_handle = ui.as_weak();
+        let config = config.clone();
+        move || {
+            let Some(ui) = ui_handle.upgrade() else { return };
+            let Some(dir) = config.borrow().export_folder.clone() else {
+                return;
+            };
+            if let Err(e) = open_folder(&dir) {
+                set_status(&ui, StatusLevel::Error, &format!("Error during opening the folder: {}", e));
+            }
+        }
+    });
+    ui.on_profile_selected({
+        let ui_handle = ui.as_weak();
+        let config = config.clone();
+        move |name| {
+            let Some(ui) = ui_handle.upgrade() else { return };
+            let mut config = config.borrow_mut();
+            if !config.select_profile(name.as_str()) {
+                return;
+            }
+            let profile = config.profile();
+            set_transport(Arc::new(profile.transport()));
+            ui.set_density(profile.density.unwrap_or(0) as i32);
+            ui.set_two_color(profile.two_color);
+            show_true_size_scale(&ui, &config);
+            // the uploaded label was made for the previous printer
+            ui.set_print_width(0);
+            if let Err(e) = config.save() {
+                eprintln!("Settings could not be saved: {}", e);
+            }
+            set_status(&ui, StatusLevel::Success, &format!("Printer profile '{}' selected ({}), create the label again.", profile.name, profile.host));
+        }
+    });
+    ui.on_add_profile_from_preset({
+        let ui_handle = ui.as_weak();
+        let config = config.clone();
+        move |preset_name| {
+            let Some(ui) = ui_handle.upgrade() else { return };
+            let Some(preset) = preset(preset_name.as_str()) else {
+                return;
+            };
+            let mut config = config.borrow_mut();
+            let name = config.add_profile_from_preset(&preset);
+            let profile_names: Vec<SharedString> = config.profile_names().iter().map(|name| name.as_str().into()).collect();
+            ui.set_profile_names(ModelRc::new(VecModel::from(profile_names)));
+            ui.set_profile_name(name.as_str().into());
+            let profile = config.profile();
+            set_transport(Arc::new(profile.transport()));
+            ui.set_density(profile.density.unwrap_or(0) as i32);
+            ui.set_two_color(profile.two_color);
+            show_true_size_scale(&ui, &config);
+            ui.set_print_width(0);
+            if let Err(e) = config.save() {
+                set_status(&ui, StatusLevel::Warning, &format!("Settings could not be saved: {}", e));
+                return;
+            }
+            set_status(&ui, StatusLevel::Success, &format!("Printer profile '{}' added from preset ({} dots, {} dpi), edit the config file to adjust it.", profile.name, profile.head_height, profile.dpi));
+        }
+    });
+    ui.on_test_connection({
+        let ui_handle = ui.as_weak();
+        let config = config.clone();
+        move || {
+            let Some(ui) = ui_handle.upgrade() else { return };
+            start_rediscovery(&ui, config.borrow().profile());
+        }
+    });
+    ui.on_use_discovered_host({
+        let ui_handle = ui.as_weak();
+        let config = config.clone();
+        move |host| {
+            let Some(ui) = ui_handle.upgrade() else { return };
+            let mut config = config.borrow_mut();
+            config.set_profile_host(host.as_str());
+            set_transport(Arc::new(config.profile().transport()));
+            if let Err(e) = config.save() {
+                eprintln!("Settings could not be saved: {}", e);
+            }
+            set_status(&ui, StatusLevel::Success, &format!("Printer address changed to {}, create the label again.", host));
+        }
+    });
+    ui.window().on_close_requested({
+        let ui_handle = ui.as_weak();
+        let config = config.clone();
+        let uploads = uploads.clone();
+        move || {
+            // a running upload stops before its next chunk, see `join_uploads`
+            uploads.cancel();
+            let Some(ui) = ui_handle.upgrade() else { return CloseRequestResponse::HideWindow };
+            let position = ui.window().position();
+            let size = ui.window().size();
+            let mut config = config.borrow_mut();
+            config.window = Some(WindowGeometry { x: position.x, y: position.y, width: size.width, height: size.height });
+            if let Err(e) = config.save() {
+                eprintln!("Window geometry could not be saved: {}", e);
+            }
+            CloseRequestResponse::HideWindow
+        }
+    });
+
+    // opened from the file manager: the file is the first argument
+    match args.get(1).and_then(|arg| open_target(arg)) {
+        Some(OpenTarget::Label(path)) => restore_label(&ui, &mut font_list.borrow_mut(), &mut config.borrow_mut(), &path),
+        Some(OpenTarget::Image(path)) => {
+            ui.set_image_path(path.display().to_string().into());
+            ui.invoke_load_image();
+        }
+        None => {}
+    }
+    // kiosk: render the given text right away, printing is left to a key press
+    if env::args().any(|arg| arg == "--kiosk") {
+        ui.set_kiosk(true);
+        match kiosk_text() {
+            Some(text) => {
+                ui.set_label_text(text.into());
+                ui.invoke_request_create_label();
+            }
+            None => set_status(&ui, StatusLevel::Warning, &format!("No label text given, set {} or pipe the text to stdin.", KIOSK_TEXT_VAR)),
+        }
+    }
+    ui.show()?;
+    // the monitors are only known once the window exists
+    if let Some(geometry) = config.borrow().window {
+        let screens = screens(&ui);
+        if !screens.is_empty() {
+            set_window_geometry(&ui, geometry.clamped(&screens));
+        }
+    }
+    slint::run_event_loop()?;
+    ui.hide()?;
+    join_uploads(SHUTDOWN_TIMEOUT);
     Ok(())
 }
 
-fn create_image_with_text(width: usize, height: usize, text: &str, font_path: &str, used_len: &mut usize) -> ImageBuffer<Luma<u8>, Vec<u8>> {
-    // image buffer
-    *used_len = 0;
-    // create white image
-    let mut img = ImageBuffer::from_pixel(width as u32, height as u32, Luma([255u8]));
-    // load font
-    let font_data = fs::read(font_path).expect("Error reading font file");
-    let font = Font::try_from_bytes(&font_data).unwrap();
-
-    // scale the font
-    let scale = Scale { x: 96.0, y: 96.0 };
-
-    // Text start position
-    let start = rusttype::point(10.0, 71.0);
-
-    // draw the text
-    for glyph in font.layout(text, scale, start) {
-        if let Some(bb) = glyph.pixel_bounding_box() {
-            glyph.draw(|x, y, v| {
-                let px = bb.min.x + x as i32;
-                let py = bb.min.y + y as i32;
-                if px >= 0 && px < width as i32 && py >= 0 && py < height as i32 {
-                    let pixel: &mut Luma<u8> = img.get_pixel_mut(px as u32, py as u32);
-                    if v > 0.5 {
-                        pixel[0] = 0; // black
-                        if px as usize > *used_len {
-                            *used_len = px as usize;
-                        }
-                    }
+/// fill the ui from a `.label.json` file, missing fonts and images are reported as warning
+fn restore_label(ui: &AppWindow, font_list: &mut FontList, config: &mut Config, path: &Path) {
+    let label = match load_label_def(path) {
+        Ok(label) => label,
+        Err(e) => {
+            set_status(ui, StatusLevel::Error, &format!("Error during loading the label: {}", e));
+            return;
+        }
+    };
+    let mut warnings = Vec::new();
+    ui.set_label_text(label.text.into());
+    if !label.font_name.is_empty() {
+        if font_list.index_of(&label.font_name).is_none() {
+            // the font may only be hidden by the filter
+            ui.set_font_filter("".into());
+            font_list.set_filter("");
+        }
+        match font_list.index_of(&label.font_name) {
+            Some(index) => ui.set_font_index(index),
+            None => warnings.push(format!("Font not found: {}", label.font_name)),
+        }
+    }
+    if !label.image_path.is_empty() && !Path::new(&label.image_path).exists() {
+        warnings.push(format!("Image not found: {}", label.image_path));
+    }
+    // the limits of the spin boxes
+    ui.set_font_size(label.font_size.clamp(ui.get_min_font_size(), ui.get_max_font_size()));
+    ui.set_fit_lines(label.fit_lines.clamp(0, 8));
+    ui.set_fixed_pitch(label.fixed_pitch);
+    ui.set_cell_width(label.cell_width);
+    ui.set_line_spacing(label.line_spacing);
+    ui.set_snap_to_pixels(label.snap_to_pixels);
+    ui.set_image_path(label.image_path.into());
+    ui.set_image_x_offset(label.image_x_offset);
+    ui.set_image_y_offset(label.image_y_offset);
+    ui.set_image_in_label(label.image_in_label);
+    ui.set_image_orientation(label.image_orientation.into());
+    ui.set_level_black(label.level_black);
+    ui.set_level_white(label.level_white);
+    ui.set_level_midpoint(label.level_midpoint);
+    ui.set_auto_contrast(label.auto_contrast);
+    ui.set_resize_filter(label.resize_filter.into());
+    ui.set_dither(label.dither.into());
+    ui.set_despeckle(label.despeckle);
+    ui.set_letterbox_width(label.letterbox_width);
+    ui.set_letterbox_black(label.letterbox_black);
+    ui.set_invert_text(label.invert_text);
+    ui.set_invert_counter(label.invert_counter);
+    ui.set_invert_image(label.invert_image);
+    ui.set_cut_out_text(label.cut_out_text);
+    ui.set_mirror(label.mirror);
+    ui.set_scale_to_length(label.scale_to_length);
+    ui.set_red_text(label.red_text);
+    ui.set_red_counter(label.red_counter);
+    ui.set_red_image(label.red_image);
+    // the elements and the ink are settings, they are kept for the next start too
+    ui.set_counter_enabled(label.counter_enabled);
+    config.counter.enabled = label.counter_enabled;
+    ui.set_date_enabled(label.date_enabled);
+    config.date_stamp.enabled = label.date_enabled;
+    let order = ElementKind::complete_order(&label.element_order);
+    show_element_order(ui, &order);
+    config.element_order = order;
+    if label.two_color != config.profile().two_color {
+        ui.set_two_color(label.two_color);
+        config.set_profile_two_color(label.two_color);
+        set_transport(Arc::new(config.profile().transport()));
+        ui.set_print_width(0);
+    }
+    if let Err(e) = config.save() {
+        warnings.push(format!("Settings could not be saved: {}", e));
+    }
+    if warnings.is_empty() {
+        set_status(ui, StatusLevel::Success, &format!("Label loaded: {}", path.display()));
+    } else {
+        set_status(ui, StatusLevel::Warning, &warnings.join(", "));
+    }
+}
+
+/// upload threads which may still be running, waited for on exit
+static UPLOAD_THREADS: Mutex<Vec<JoinHandle<()>>> = Mutex::new(Vec::new());
+
+/// how long the app waits on exit for a cancelled upload to stop
+const SHUTDOWN_TIMEOUT: Duration = Duration::from_secs(3);
+
+/// wait up to `timeout` for the upload threads. a thread blocked in a request is left
+/// behind, it only reaches the gone window through `upgrade_in_event_loop`, which fails
+fn join_uploads(timeout: Duration) {
+    let deadline = Instant::now() + timeout;
+    let threads = std::mem::take(&mut *UPLOAD_THREADS.lock().unwrap());
+    for handle in threads {
+        while !handle.is_finished() && Instant::now() < deadline {
+            thread::sleep(Duration::from_millis(20));
+        }
+        if handle.is_finished() {
+            let _ = handle.join();
+        } else {
+            eprintln!("Upload still running on exit, not waiting for it");
+        }
+    }
+}
+
+/// message for an image which could not be loaded, telling a missing file from an
+/// unsupported format and a corrupt or incomplete file
+fn image_error_message(path: &str, error: &LabelError) -> String {
+    use image::ImageError;
+    use image::error::LimitErrorKind;
+    match error {
+        LabelError::Io(e) if e.kind() == io::ErrorKind::NotFound => format!("Image not found: {}", path),
+        LabelError::Io(e) => format!("Image {} could not be read: {}", path, e),
+        LabelError::Image(ImageError::Unsupported(e)) => format!("Unsupported image format: {}. Please choose a PNG, JPEG, BMP or GIF file.", e),
+        LabelError::Image(ImageError::Decoding(e)) => format!("Image file is corrupt: {}. Please choose another file.", e),
+        // a truncated file often ends inside of the decoder
+        LabelError::Image(ImageError::IoError(e)) if e.kind() == io::ErrorKind::UnexpectedEof => format!("Image file is incomplete: {}. Please choose another file.", path),
+        LabelError::Image(ImageError::Limits(e)) if matches!(e.kind(), LimitErrorKind::DimensionError) => format!("Image is too large, at most {0} x {0} pixels are supported.", label_drawer::render::MAX_SOURCE_DIMENSION),
+        e => format!("Error during loading the image: {}. Please choose another file.", e),
+    }
+}
+
+/// registration mark from the controls
+fn mark_from_ui(ui: &AppWindow) -> RegistrationMark {
+    RegistrationMark {
+        enabled: ui.get_mark_enabled(),
+        x: ui.get_mark_x().max(0) as u32,
+        y: ui.get_mark_y().max(0) as u32,
+        width: ui.get_mark_width().max(0) as u32,
+        height: ui.get_mark_height().max(0) as u32,
+    }
+}
+
+/// `element` with inverted tones if `invert` is set
+fn inverted_if(element: Box<dyn LabelElement>, invert: bool) -> Box<dyn LabelElement> {
+    if invert { Box::new(InvertedElement::new(element)) } else { element }
+}
+
+/// offsets of the image element from the controls, snapped to the grid
+fn image_offsets_from_ui(ui: &AppWindow) -> (i32, i32) {
+    let grid = ui.get_grid_size().max(0) as u32;
+    (snap_to_grid(ui.get_image_x_offset(), grid), snap_to_grid(ui.get_image_y_offset(), grid))
+}
+
+/// levels for image elements from the controls
+fn levels_from_ui(ui: &AppWindow) -> Levels {
+    Levels {
+        black: ui.get_level_black().clamp(0, 255) as u8,
+        white: ui.get_level_white().clamp(0, 255) as u8,
+        midpoint: ui.get_level_midpoint().max(1) as f32 / 100.0,
+    }
+}
+
+/// show the texts of the window in `language`, "system" is the locale of the environment.
+/// the translations are bundled from `lang/<language>/LC_MESSAGES/label_drawer.po`
+fn select_ui_language(language: &str) {
+    let language = if language == "system" { environment_locale() } else { language.to_string() };
+    // "de_DE.UTF-8" → "de"
+    let language = language.split(['_', '.', '@']).next().unwrap_or_default();
+    let language = if language == "C" || language == "POSIX" { "" } else { language };
+    if let Err(e) = slint::select_bundled_translation(language) {
+        eprintln!("No translation for language '{}': {}", language, e);
+    }
+}
+
+/// show the element names in composition order
+fn show_element_order(ui: &AppWindow, order: &[ElementKind]) {
+    let names: Vec<SharedString> = order.iter().map(|kind| kind.name().into()).collect();
+    ui.set_element_names(ModelRc::new(VecModel::from(names)));
+}
+
+/// the letterbox box of the image controls, `None` if the image is scaled to the height
+fn letterbox_from_ui(ui: &AppWindow) -> Option<Letterbox> {
+    match ui.get_letterbox_width() {
+        width if width <= 0 => None,
+        width => Some(Letterbox { width: width as u32, fill: if ui.get_letterbox_black() { 0 } else { 255 } }),
+    }
+}
+
+/// environment variable with the label text for the kiosk mode
+const KIOSK_TEXT_VAR: &str = "LABEL_DRAWER_TEXT";
+
+/// label text for the kiosk mode from `KIOSK_TEXT_VAR` or else from a piped stdin.
+/// `None` if neither gives a non empty text
+fn kiosk_text() -> Option<String> {
+    let text = match env::var(KIOSK_TEXT_VAR) {
+        Ok(text) if !text.trim().is_empty() => text,
+        // an interactive terminal would block the start
+        _ if io::stdin().is_terminal() => return None,
+        _ => {
+            let mut text = String::new();
+            if let Err(e) = io::stdin().read_to_string(&mut text) {
+                eprintln!("Label text could not be read from stdin: {}", e);
+                return None;
+            }
+            text
+        }
+    };
+    let text = text.trim_end_matches(['\r', '\n']);
+    (!text.trim().is_empty()).then(|| text.to_string())
+}
+
+/// move and resize the window
+fn set_window_geometry(ui: &AppWindow, geometry: WindowGeometry) {
+    ui.window().set_position(PhysicalPosition::new(geometry.x, geometry.y));
+    ui.window().set_size(PhysicalSize::new(geometry.width, geometry.height));
+}
+
+/// the monitors in physical pixels, the primary one first. empty if the backend can't tell
+fn screens(ui: &AppWindow) -> Vec<WindowGeometry> {
+    ui.window()
+        .with_winit_window(|window| {
+            let primary = window.primary_monitor();
+            let mut monitors: Vec<_> = window.available_monitors().collect();
+            monitors.sort_by_key(|monitor| Some(monitor) != primary.as_ref());
+            monitors
+                .iter()
+                .map(|monitor| WindowGeometry { x: monitor.position().x, y: monitor.position().y, width: monitor.size().width, height: monitor.size().height })
+                .collect()
+        })
+        .unwrap_or_default()
+}
+
+/// fonts shown in the dropdown, `visible` maps the dropdown index to `entries`.
+/// the favorites come first
+struct FontList {
+    entries: Vec<FontEntry>,
+    visible: Vec<usize>,
+    favorites: Vec<String>,
+    filter: String,
+    model: Rc<FontThumbnails>,
+}
+
+impl FontList {
+    fn new(entries: Vec<FontEntry>, favorites: Vec<String>) -> Self {
+        let mut list = FontList { entries, visible: Vec::new(), favorites, filter: String::new(), model: Rc::new(FontThumbnails::default()) };
+        list.set_filter("");
+        list
+    }
+
+    /// the font for a dropdown index, a negative index must not wrap around to a huge usize
+    fn selected(&self, index: i32) -> Option<&FontEntry> {
+        let index = usize::try_from(index).ok()?;
+        self.visible.get(index).and_then(|&i| self.entries.get(i))
+    }
+
+    /// dropdown index of the font with the given name, if it is visible
+    fn index_of(&self, name: &str) -> Option<i32> {
+        self.visible
+            .iter()
+            .position(|&i| self.entries[i].display_name == name)
+            .map(|index| index as i32)
+    }
+
+    /// replace the fonts, e.g. after a new scan, and remove the filter
+    fn set_entries(&mut self, entries: Vec<FontEntry>) {
+        self.entries = entries;
+        self.set_filter("");
+    }
+
+    /// show only the fonts containing `filter` and update the font list
+    fn set_filter(&mut self, filter: &str) {
+        self.filter = filter.to_string();
+        self.visible = filter_fonts(&self.entries, filter);
+        favorites_first(&mut self.visible, &self.entries, &self.favorites);
+        let rows = self.visible.iter().map(|&i| {
+            let entry = self.entries[i].clone();
+            let favorite = self.favorites.contains(&entry.display_name);
+            (entry, favorite)
+        });
+        self.model.set_rows(rows.collect());
+    }
+
+    /// list other favorites first, the filter stays
+    fn set_favorites(&mut self, favorites: Vec<String>) {
+        self.favorites = favorites;
+        let filter = std::mem::take(&mut self.filter);
+        self.set_filter(&filter);
+    }
+}
+
+/// size of the font thumbnails in the font list
+const THUMBNAIL_WIDTH: u32 = 240;
+const THUMBNAIL_HEIGHT: u32 = 32;
+
+/// model of the font list. the list only asks for the rows it shows, so the "Sample"
+/// thumbnails are rendered when they scroll into view and kept per font file
+#[derive(Default)]
+struct FontThumbnails {
+    /// the fonts and if they are favorites
+    rows: RefCell<Vec<(FontEntry, bool)>>,
+    cache: RefCell<HashMap<String, Image>>,
+    notify: ModelNotify,
+}
+
+impl FontThumbnails {
+    fn set_rows(&self, rows: Vec<(FontEntry, bool)>) {
+        *self.rows.borrow_mut() = rows;
+        self.notify.reset();
+    }
+
+    /// "Sample" in the font at `path`, an empty image if the font can't be rendered
+    fn thumbnail(&self, path: &str) -> Image {
+        if let Some(image) = self.cache.borrow().get(path) {
+            return image.clone();
+        }
+        let options = TextOptions { fit_lines: 1, ..TextOptions::default() };
+        let image = match TextElement::new("Sample", path) {
+            Ok(text) => {
+                let elements: Vec<Box<dyn LabelElement>> = vec![Box::new(text.with_options(options))];
+                let (img, _) = compose(&elements, THUMBNAIL_WIDTH, THUMBNAIL_HEIGHT);
+                get_slint_img(&img, THUMBNAIL_HEIGHT, THUMBNAIL_WIDTH)
+            }
+            Err(e) => {
+                eprintln!("No thumbnail for {}: {}", path, e);
+                Image::default()
+            }
+        };
+        self.cache.borrow_mut().insert(path.to_string(), image.clone());
+        image
+    }
+}
+
+impl Model for FontThumbnails {
+    type Data = FontItem;
+
+    fn row_count(&self) -> usize {
+        self.rows.borrow().len()
+    }
+
+    fn row_data(&self, row: usize) -> Option<FontItem> {
+        let (entry, favorite) = self.rows.borrow().get(row).cloned()?;
+        Some(FontItem { name: SharedString::from(entry.display_name.as_str()), thumbnail: self.thumbnail(&entry.path), favorite })
+    }
+
+    fn model_tracker(&self) -> &dyn ModelTracker {
+        &self.notify
+    }
+}
+
+/// show the state of the ticket counter in the controls
+fn show_counter(ui: &AppWindow, counter: &TicketCounter) {
+    ui.set_counter_enabled(counter.enabled);
+    ui.set_counter_format(counter.format.as_str().into());
+    ui.set_counter_padding(counter.padding as i32);
+    ui.set_counter_start(counter.start.min(i32::MAX as u64) as i32);
+    ui.set_counter_next(counter.next.to_string().into());
+}
+
+/// count a successful print, the label is created again with the next number
+fn advance_counter(ui: &AppWindow, config: &RefCell<Config>) {
+    {
+        let mut config = config.borrow_mut();
+        if !config.counter.enabled {
+            return;
+        }
+        config.counter.advance();
+        if let Err(e) = config.save() {
+            eprintln!("Ticket number could not be saved: {}", e);
+        }
+        show_counter(ui, &config.counter);
+    }
+    ui.invoke_request_create_label();
+}
+
+/// send the print command once per copy and poll the printer status in a worker thread,
+/// so the window stays responsive while the printer is busy. a `label` taller than the
+/// head of `profile` is uploaded and printed in bands, copies share the ticket number
+fn print_label(ui: &AppWindow, profile: &PrinterProfile, label: &Option<GrayImage>) {
+    let length = ui.get_print_width();
+    if length <= 0 {
+        set_status(ui, StatusLevel::Warning, "Nothing to print, create a label with content first.");
+        return;
+    }
+    let copies = ui.get_copies().max(1);
+    let print_length = length_with_feed(length.max(0) as u32, ui.get_feed_after_print().max(0) as u32);
+    let bands = label.as_ref().filter(|img| img.height() > profile.head_height).map(|img| split_into_bands(img, profile.head_height));
+    // every band uses the tape of a label
+    let tape_length = print_length * bands.as_ref().map_or(1, Vec::len) as u32;
+    let packing = profile.packing;
+    let ui_handle = ui.as_weak();
+    let handle = thread::spawn(move || {
+        // bands are uploaded, not while another upload is sending
+        let _sending = bands.is_some().then(|| SENDING.lock().unwrap_or_else(|e| e.into_inner()));
+        let transport = transport();
+        let print_copy = || match &bands {
+            Some(bands) => print_bands(bands, print_length, &packing).map(|_| ()),
+            None => transport.print(print_length),
+        };
+        // the errors are not `Send`, only their message leaves the thread
+        let outcome = match (1..=copies).find_map(|copy| print_copy().err().map(|e| (copy, e.to_string()))) {
+            Some(failed) => Err(failed),
+            None => Ok(poll_transport_status(transport.as_ref()).map_err(|e| e.to_string())),
+        };
+        let _ = ui_handle.upgrade_in_event_loop(move |ui| {
+            let state = match outcome {
+                Ok(state) => state,
+                Err((copy, e)) => {
+                    ui.invoke_print_finished(copy - 1, tape_length as i32, false);
+                    set_status(&ui, StatusLevel::Error, &format!("Print of copy {} failed: {}. Check that the printer is switched on and reachable.", copy, e));
+                    return;
+                }
+            };
+            // the tape is used even if the status reports a problem afterwards
+            let completed = matches!(state, Ok(PrinterState::Idle) | Ok(PrinterState::Unsupported));
+            ui.invoke_print_finished(copies, tape_length as i32, completed);
+            match state {
+                Ok(PrinterState::Idle) | Ok(PrinterState::Unsupported) => {
+                    set_status(&ui, StatusLevel::Success, &format!("{} label(s) with length {} printed.", copies, length));
                 }
+                Ok(PrinterState::Printing) => {
+                    set_status(&ui, StatusLevel::Warning, "Printer is still printing, no completion reported yet.");
+                }
+                Ok(PrinterState::PaperOut) => set_status(&ui, StatusLevel::Error, "Printer reports: out of tape. Insert a new tape cassette."),
+                Ok(PrinterState::HeadOpen) => set_status(&ui, StatusLevel::Error, "Printer reports: head open. Close the cover and print again."),
+                Ok(PrinterState::Error(state)) => set_status(&ui, StatusLevel::Error, &format!("Printer reports an error: {}", state)),
+                Err(e) => set_status(&ui, StatusLevel::Warning, &format!("Label sent, but the printer status is unknown: {}", e)),
+            }
+        });
+    });
+    let mut threads = UPLOAD_THREADS.lock().unwrap();
+    threads.retain(|thread| !thread.is_finished());
+    threads.push(handle);
+}
+
+/// add printed labels of `length` columns to the statistics and save them
+fn record_prints(ui: &AppWindow, config: &RefCell<Config>, copies: u32, length: u32) {
+    if copies == 0 {
+        return;
+    }
+    let mut config = config.borrow_mut();
+    config.record_prints(copies, length);
+    if let Err(e) = config.save() {
+        eprintln!("Statistics could not be saved: {}", e);
+    }
+    show_stats(ui, &config);
+}
+
+/// tape length in mm below a meter, in m above
+fn tape_length(mm: f64) -> String {
+    if mm < 1000.0 { format!("{:.0} mm", mm) } else { format!("{:.2} m", mm / 1000.0) }
+}
+
+/// show the session and lifetime statistics
+fn show_stats(ui: &AppWindow, config: &Config) {
+    let (session, lifetime) = (config.session_stats, config.lifetime_stats);
+    ui.set_stats_text(format!(
+        "This session: {} label(s), {} tape. Total: {} label(s), {} tape.",
+        session.labels, tape_length(session.tape_mm), lifetime.labels, tape_length(lifetime.tape_mm)
+    ).into());
+}
+
+/// height of a new label in pixels: the head height times the bands
+fn label_height(ui: &AppWindow, profile: &PrinterProfile) -> u32 {
+    profile.head_height * ui.get_label_bands().clamp(1, 4) as u32
+}
+
+/// upload a label of the head height right away, the bands of a taller label are uploaded
+/// one by one when it is printed, see `print_label`
+fn upload_label(ui: &AppWindow, byte_data: Vec<u8>, used_len: u32, height: u32, profile: &PrinterProfile, uploads: &UploadControl) {
+    if height > profile.head_height {
+        // a running upload is outdated, its result must not replace this label
+        uploads.next();
+        ui.set_uploading(false);
+        ui.set_print_width(used_len as i32);
+        set_status(ui, StatusLevel::None, &format!("Label of {} bands, each band is sent to the printer when it is printed.", height.div_ceil(profile.head_height)));
+    } else {
+        start_upload(ui, byte_data, used_len, height, uploads, false);
+    }
+}
+
+/// counts the searches of `report_missing_glyphs`, only the result of the last one is shown
+static FALLBACK_SEARCH: AtomicU64 = AtomicU64::new(0);
+
+/// warn below the text about characters the font lacks and offer a font which has them.
+/// the offered font is searched in a worker thread, every font file has to be parsed
+fn report_missing_glyphs(ui: &AppWindow, entry: &FontEntry, fonts: &[FontEntry], text: &str) {
+    let id = FALLBACK_SEARCH.fetch_add(1, Ordering::SeqCst) + 1;
+    ui.set_fallback_font("".into());
+    let missing = match missing_glyphs(&entry.path, text) {
+        Ok(missing) => missing,
+        Err(e) => {
+            eprintln!("Glyph check of {} failed: {}", entry.display_name, e);
+            Vec::new()
+        }
+    };
+    if missing.is_empty() {
+        ui.set_missing_glyphs("".into());
+        return;
+    }
+    let chars: String = missing.iter().collect();
+    let message = format!("{} lacks {} character(s), they print as blanks: {}", entry.display_name, missing.len(), chars);
+    eprintln!("{}", message);
+    ui.set_missing_glyphs(message.into());
+    let fonts = fonts.to_vec();
+    let ui_handle = ui.as_weak();
+    thread::spawn(move || {
+        let fallback = fallback_font(&fonts, &missing).map(|fallback| fallback.display_name.clone()).unwrap_or_default();
+        let _ = ui_handle.upgrade_in_event_loop(move |ui| {
+            // the text or the font changed meanwhile
+            if FALLBACK_SEARCH.load(Ordering::SeqCst) == id {
+                ui.set_fallback_font(fallback.into());
+            }
+        });
+    });
+}
+
+/// note below the text options which overflow policy changed the text and whether
+/// glyphs are cut off at the top or bottom
+fn overflow_note(text: &TextElement, applied: Option<Overflow>, clip: VerticalClip) -> String {
+    let mut note = match applied {
+        None => String::new(),
+        Some(Overflow::Clip) => "The text is wider than the label, the end is cut off.".to_string(),
+        Some(Overflow::Shrink) => format!("The text is wider than the label, the font size is reduced to {:.0} px.", text.options().size),
+        Some(Overflow::Wrap) => format!("The text is wider than the label, it is wrapped into {} lines.", text.text().lines().count()),
+        // refused before drawing
+        Some(Overflow::Error) => String::new(),
+    };
+    if clip.is_clipped() {
+        if !note.is_empty() {
+            note.push(' ');
+        }
+        note.push_str(&format!(
+            "The text is vertically clipped ({} px at the top, {} px at the bottom), choose a smaller size or move the baseline.",
+            clip.above, clip.below
+        ));
+    }
+    note
+}
+
+/// bytes at each end of the packed data in the debug panel
+const HEX_PREVIEW_EDGE: usize = 64;
+
+/// fill the debug panel with the size and a hex dump of the data sent to the printer
+fn show_packed_bytes(ui: &AppWindow, data: &[u8], width: u32, height: u32) {
+    ui.set_packed_summary(format!("{} bytes, {} x {} px", data.len(), width, height).into());
+    ui.set_packed_hex(hex_preview(data, HEX_PREVIEW_EDGE).into());
+}
+
+/// show a message in the status bar, errors and warnings also go to stderr
+fn set_status(ui: &AppWindow, level: StatusLevel, message: &str) {
+    match level {
+        StatusLevel::Warning | StatusLevel::Error => eprintln!("{}", message),
+        _ => println!("{}", message),
+    }
+    ui.set_status_level(level);
+    ui.set_status_text(message.into());
+}
+
+/// remove the message of the previous action
+fn clear_status(ui: &AppWindow) {
+    ui.set_status_level(StatusLevel::None);
+    ui.set_status_text("".into());
+}
+
+/// held by the thread sending to the printer, so an upload only starts once the cancelled
+/// one stopped and the chunks of two labels never mix
+static SENDING: Mutex<()> = Mutex::new(());
+
+/// id and cancel token of the newest upload. every upload gets a token of its own, the next
+/// upload cancels it and the result of an outdated upload is ignored
+#[derive(Clone, Default)]
+struct UploadControl {
+    current: Arc<Mutex<(u64, Arc<AtomicBool>)>>,
+}
+
+impl UploadControl {
+    /// stop the running upload before its next chunk
+    fn cancel(&self) {
+        self.current.lock().unwrap().1.store(true, Ordering::SeqCst);
+    }
+
+    /// cancel the running upload and return the id and token of the next one
+    fn next(&self) -> (u64, Arc<AtomicBool>) {
+        let mut current = self.current.lock().unwrap();
+        current.1.store(true, Ordering::SeqCst);
+        *current = (current.0 + 1, Arc::new(AtomicBool::new(false)));
+        (current.0, current.1.clone())
+    }
+
+    /// whether the upload `id` wasn't replaced by another one
+    fn is_current(&self, id: u64) -> bool {
+        self.current.lock().unwrap().0 == id
+    }
+}
+
+/// upload `data` as soon as no other upload is sending, see `SENDING`
+fn send_exclusive(transport: &dyn LabelTransport, data: &[u8], cancel: &AtomicBool, progress: &mut dyn FnMut(usize)) -> Result<UploadOutcome, LabelError> {
+    let _sending = SENDING.lock().unwrap_or_else(|e| e.into_inner());
+    transport.upload(data, cancel, progress)
+}
+
+/// upload the packed bitmap in a worker thread, the print length is only set
+/// once the upload is complete so a partial upload can't be printed. with `print_after`
+/// the print is requested right after a complete upload. a running upload is cancelled and
+/// this one waits until it stopped. there is no abort request, the part of a cancelled
+/// upload stays on the printer until the next upload replaces it
+fn start_upload(ui: &AppWindow, byte_data: Vec<u8>, used_len: u32, height: u32, uploads: &UploadControl, print_after: bool) {
+    let (id, cancel) = uploads.next();
+    let uploads = uploads.clone();
+    ui.set_uploading(true);
+    ui.set_print_width(0);
+    let ui_handle = ui.as_weak();
+    let handle = thread::spawn(move || {
+        let total = byte_data.len();
+        let progress_handle = ui_handle.clone();
+        let progress_uploads = uploads.clone();
+        let transport = transport();
+        let result = verify_head_width(transport.as_ref(), height).and_then(|_| {
+            send_exclusive(transport.as_ref(), &byte_data, &cancel, &mut |sent| {
+                let uploads = progress_uploads.clone();
+                let _ = progress_handle.upgrade_in_event_loop(move |ui| {
+                    if uploads.is_current(id) {
+                        ui.set_status_text(format!("Uploading {} / {} bytes ...", sent, total).into());
+                    }
+                });
+            })
+        });
+        let _ = ui_handle.upgrade_in_event_loop(move |ui| {
+            // a newer upload or label replaced this one, its length must not be printed
+            if !uploads.is_current(id) {
+                return;
+            }
+            ui.set_uploading(false);
+            let complete = matches!(result, Ok(UploadOutcome::Complete));
+            if complete {
+                ui.set_print_width(used_len as i32);
+            }
+            report_upload(&ui, result);
+            if complete && print_after {
+                ui.invoke_request_print_label();
+            }
+        });
+    });
+    let mut threads = UPLOAD_THREADS.lock().unwrap();
+    threads.retain(|thread| !thread.is_finished());
+    threads.push(handle);
+}
+
+/// show the result of an upload to the printer
+fn report_upload(ui: &AppWindow, result: Result<UploadOutcome, LabelError>) {
+    match result {
+        Ok(UploadOutcome::Complete) => set_status(ui, StatusLevel::Success, "Label sent to printer."),
+        Ok(UploadOutcome::Cancelled { sent, total }) => set_status(ui, StatusLevel::Warning, &format!("Upload cancelled after {} of {} bytes, the label can't be printed.", sent, total)),
+        Err(LabelError::UploadFailed { index, status }) => set_status(ui, StatusLevel::Error, &format!("The printer refused the label at byte {} (status {}). Try setting a smaller max_request_size in the config.", index, status)),
+        Err(LabelError::InvalidInput(msg)) => set_status(ui, StatusLevel::Error, &format!("Upload not possible: {}", msg)),
+        Err(e @ LabelError::HeadMismatch { .. }) => set_status(ui, StatusLevel::Error, &format!("{}, choose the printer profile with the right head height. Nothing was sent.", e)),
+        Err(LabelError::Network(e)) => {
+            set_status(ui, StatusLevel::Error, &format!("Upload to printer failed: {}. Searching for the printer ...", e));
+            ui.invoke_test_connection();
+        }
+        Err(e) => set_status(ui, StatusLevel::Error, &format!("Upload to printer failed: {}. Check that the printer is switched on and reachable.", e)),
+    }
+}
+
+/// test the connection to the printer of `profile` in a worker thread. if it is unreachable
+/// its subnet is searched and a found printer is offered as the new host
+fn start_rediscovery(ui: &AppWindow, profile: PrinterProfile) {
+    let ui_handle = ui.as_weak();
+    set_status(ui, StatusLevel::None, &format!("Testing the connection to {} ...", profile.host));
+    thread::spawn(move || {
+        if is_reachable(&profile.host) {
+            let _ = ui_handle.upgrade_in_event_loop(move |ui| {
+                set_status(&ui, StatusLevel::Success, &format!("Printer reachable at {}.", profile.host));
             });
+            return;
+        }
+        let found = scan_subnet(&profile.host, &profile.endpoints);
+        let _ = ui_handle.upgrade_in_event_loop(move |ui| match found {
+            Some(host) => {
+                set_status(&ui, StatusLevel::Warning, &format!("Printer not reachable at {}, but found at {}.", profile.host, host));
+                ui.set_discovered_host(host.into());
+                ui.invoke_show_rediscovery();
+            }
+            None => set_status(&ui, StatusLevel::Error, &format!("Printer not reachable at {} and not found in its network. Check that it is switched on.", profile.host)),
+        });
+    });
+}
+
+/// settings of the image element from the controls
+struct ImageContent {
+    path: String,
+    offsets: (i32, i32),
+    levels: Levels,
+    letterbox: Option<Letterbox>,
+    auto_contrast: bool,
+    filter: ResizeFilter,
+    dither: Dither,
+    orientation: Orientation,
+    despeckle: bool,
+    invert: bool,
+    red: bool,
+}
+
+/// everything a label is drawn from, taken from the controls so the drawing can also
+/// run in a worker thread, see `render_content`
+struct LabelContent {
+    text: String,
+    font_path: String,
+    options: TextOptions,
+    counter: TicketCounter,
+    date_stamp: DateStamp,
+    order: Vec<ElementKind>,
+    image: Option<ImageContent>,
+    invert_text: bool,
+    invert_counter: bool,
+    red_text: bool,
+    red_counter: bool,
+    cut_out_text: bool,
+    two_color: bool,
+    exact_preview: bool,
+    width: u32,
+    height: u32,
+}
+
+/// the content of the label from the controls, `None` with a status message if no font
+/// is selected
+fn content_from_ui(ui: &AppWindow, config: &Config, font_list: &FontList, profile: &PrinterProfile) -> Option<LabelContent> {
+    let label_text = ui.get_label_text();
+    let font_index = ui.get_font_index();
+    let Some(entry) = font_list.selected(font_index) else {
+        if font_list.entries.is_empty() {
+            set_status(ui, StatusLevel::Error, "No fonts available, please install a font and rescan the fonts.");
+        } else {
+            set_status(ui, StatusLevel::Error, &format!("No valid font selected (index {}), please choose a font.", font_index));
+        }
+        return None;
+    };
+    println!(
+        "Label '{}' with font: {} (Path: {})",
+        label_text, entry.display_name, entry.path
+    );
+    report_missing_glyphs(ui, entry, &font_list.entries, label_text.as_str());
+
+    let pitch = match (ui.get_fixed_pitch(), ui.get_cell_width()) {
+        (false, _) => Pitch::Proportional,
+        (true, 0) => Pitch::Widest,
+        (true, cell_width) => Pitch::Cell(cell_width as f32),
+    };
+    let options = TextOptions {
+        pitch,
+        size: ui.get_font_size() as f32,
+        fit_lines: ui.get_fit_lines().max(0) as usize,
+        threshold: ui.get_glyph_threshold().clamp(1, 99) as f32 / 100.0,
+        snap_to_pixels: ui.get_snap_to_pixels(),
+        line_spacing: ui.get_line_spacing().max(1) as f32 / 100.0,
+        overflow: Overflow::from_name(&ui.get_text_overflow()),
+        baseline: ui.get_text_baseline() as f32,
+    };
+    let image_path = ui.get_image_path().to_string();
+    let image = (ui.get_image_in_label() && !image_path.is_empty()).then(|| ImageContent {
+        path: image_path,
+        offsets: image_offsets_from_ui(ui),
+        levels: levels_from_ui(ui),
+        letterbox: letterbox_from_ui(ui),
+        auto_contrast: ui.get_auto_contrast(),
+        filter: ResizeFilter::from_name(&ui.get_resize_filter()),
+        dither: Dither::from_name(&ui.get_dither()),
+        orientation: Orientation::from_name(&ui.get_image_orientation()),
+        despeckle: ui.get_despeckle(),
+        invert: ui.get_invert_image(),
+        red: ui.get_red_image(),
+    });
+    Some(LabelContent {
+        text: label_text.to_string(),
+        font_path: entry.path.clone(),
+        options,
+        counter: config.counter.clone(),
+        date_stamp: config.date_stamp.clone(),
+        order: ElementKind::complete_order(&config.element_order),
+        image,
+        invert_text: ui.get_invert_text(),
+        invert_counter: ui.get_invert_counter(),
+        red_text: ui.get_red_text(),
+        red_counter: ui.get_red_counter(),
+        cut_out_text: ui.get_cut_out_text(),
+        two_color: profile.two_color,
+        exact_preview: ui.get_exact_preview(),
+        width: LABEL_WIDTH as u32,
+        height: label_height(ui, profile),
+    })
+}
+
+/// a composed label before its transformations
+struct RenderedContent {
+    img: GrayImage,
+    used_len: u32,
+    /// the label with anti-aliased text, `None` if the packed bits are shown
+    preview: Option<GrayImage>,
+    overflow_note: String,
+    /// whether the image was turned, `None` without an image
+    image_rotated: Option<bool>,
+}
+
+/// compose the elements of `content`, the error is the message for the status bar
+fn render_content(content: &LabelContent) -> Result<RenderedContent, String> {
+    let (width, height) = (content.width, content.height);
+    let mut elements: Vec<Box<dyn LabelElement>> = Vec::new();
+    // ink of each element, only used by two-color printers
+    let mut colors: Vec<InkColor> = Vec::new();
+    let mut note = String::new();
+    let mut image_rotated = None;
+    // positions of text and image in `elements`, for cutting the text out
+    let (mut text_index, mut image_index) = (None, None);
+    for kind in &content.order {
+        match kind {
+            ElementKind::Text => match TextElement::new(&content.text, &content.font_path).and_then(|text| text.with_options(content.options.clone()).fit_to_width(width, height)) {
+                Ok((text, applied)) => {
+                    note = overflow_note(&text, applied, text.vertical_clip(width, height));
+                    text_index = Some(elements.len());
+                    elements.push(inverted_if(Box::new(text), content.invert_text));
+                    colors.push(ink_color(content.red_text));
+                }
+                Err(LabelError::InvalidInput(msg)) => return Err(format!("{}. Shorten the text or choose another policy for too wide text.", msg)),
+                Err(e) => return Err(format!("Error during loading the font: {}. Please choose another font.", e)),
+            },
+            ElementKind::Counter if content.counter.enabled => match CounterElement::new(&content.counter, &content.font_path) {
+                Ok(number) => {
+                    elements.push(inverted_if(Box::new(number.with_options(content.options.clone())), content.invert_counter));
+                    colors.push(ink_color(content.red_counter));
+                }
+                Err(e) => eprintln!("Ticket number not drawn: {}", e),
+            },
+            // the time of this creation, not of the settings
+            ElementKind::Date if content.date_stamp.enabled => match DateElement::new(&content.date_stamp, &content.font_path) {
+                Ok(date) => {
+                    elements.push(Box::new(date.with_options(content.options.clone())));
+                    colors.push(InkColor::Black);
+                }
+                Err(LabelError::InvalidInput(msg)) => return Err(format!("{}. Correct the date settings.", msg)),
+                Err(e) => eprintln!("Date not drawn: {}", e),
+            },
+            ElementKind::Image if let Some(settings) = &content.image => match load_source_image(Path::new(&settings.path), height) {
+                Ok(img) => {
+                    let (image_x, image_y) = settings.offsets;
+                    let image = ImageElement::new(img, image_x, image_y).with_levels(settings.levels).with_letterbox(settings.letterbox).with_auto_contrast(settings.auto_contrast).with_filter(settings.filter).with_dither(settings.dither).with_orientation(settings.orientation);
+                    image_rotated = Some(image.is_rotated(height));
+                    image_index = Some(elements.len());
+                    elements.push(inverted_if(Box::new(image), settings.invert));
+                    colors.push(ink_color(settings.red));
+                }
+                Err(e) => return Err(image_error_message(&settings.path, &e)),
+            },
+            _ => {}
+        }
+    }
+    if content.cut_out_text
+        && let Some(text_index) = text_index
+    {
+        // the image moves to the place of the text, as its background
+        let background = image_index.map(|index| {
+            colors.remove(index);
+            elements.remove(index)
+        });
+        let text_index = if image_index.is_some_and(|index| index < text_index) { text_index - 1 } else { text_index };
+        let text = elements.remove(text_index);
+        elements.insert(text_index, Box::new(CutOutElement::new(text, background)));
+    }
+    let with_image = image_index.is_some();
+    if content.two_color && colors.contains(&InkColor::Red) {
+        let (img, used_len) = compose_two_color(&elements, &colors, width, height, with_image);
+        return Ok(RenderedContent { img, used_len, preview: None, overflow_note: note, image_rotated });
+    }
+    // with an image the anti-aliased text is dithered together with it
+    let (img, used_len) = match &content.image {
+        Some(settings) if with_image => cleaned(compose_dithered_with(&elements, width, height, settings.dither), settings.despeckle),
+        _ => compose(&elements, width, height),
+    };
+    let preview = (!content.exact_preview).then(|| compose_gray(&elements, width, height).0);
+    Ok(RenderedContent { img, used_len, preview, overflow_note: note, image_rotated })
+}
+
+/// show the notes of a rendered label next to the controls
+fn show_rendered(ui: &AppWindow, rendered: &RenderedContent) {
+    ui.set_overflow_note(rendered.overflow_note.as_str().into());
+    if let Some(rotated) = rendered.image_rotated {
+        ui.set_image_rotated(rotated);
+    }
+}
+
+/// render the label in a worker thread after an edit of the text, the font or the size.
+/// the last good preview stays visible until the render is done, an outdated render is
+/// discarded. nothing is sent, the label is uploaded when it is created again
+fn start_text_preview_render(ui: &AppWindow, content: LabelContent, profile: PrinterProfile, uploads: UploadControl, generation: &Arc<AtomicU64>) {
+    let id = generation.fetch_add(1, Ordering::SeqCst) + 1;
+    let generation = generation.clone();
+    ui.set_preview_updating(true);
+    let ui_handle = ui.as_weak();
+    thread::spawn(move || {
+        let result = render_content(&content);
+        let _ = ui_handle.upgrade_in_event_loop(move |ui| {
+            // a newer render was started or a new label shown meanwhile
+            if generation.load(Ordering::SeqCst) != id || !ui.get_preview_updating() {
+                return;
+            }
+            ui.set_preview_updating(false);
+            match result {
+                Ok(rendered) => {
+                    show_rendered(&ui, &rendered);
+                    let (img, _, preview) = transform_label(&ui, rendered.img, rendered.used_len, rendered.preview, &profile);
+                    show_label(&ui, &img, preview.as_ref(), &profile);
+                    // the label on the printer is outdated, a running upload of it too
+                    uploads.next();
+                    ui.set_uploading(false);
+                    ui.set_print_width(0);
+                    set_status(&ui, StatusLevel::None, "Text changed, create the label to send it to the printer.");
+                }
+                // the last good preview stays
+                Err(message) => set_status(&ui, StatusLevel::Error, &message),
+            }
+        });
+    });
+}
+
+/// transform, show and upload the composed label. `preview` is the same label with
+/// anti-aliased text, it gets every transformation of `img` and is shown instead of it
+fn finish_label(ui: &AppWindow, img: GrayImage, used_len: u32, preview: Option<GrayImage>, profile: &PrinterProfile, current_label: &RefCell<Option<GrayImage>>, uploads: &UploadControl) {
+    let (img, used_len, preview) = transform_label(ui, img, used_len, preview, profile);
+    let byte_data = show_label(ui, &img, preview.as_ref(), profile);
+    upload_label(ui, byte_data, used_len, img.height(), profile, uploads);
+    *current_label.borrow_mut() = Some(img);
+}
+
+/// the transformations of the controls and of `profile` (trim, scale, repeat, mirror,
+/// margins, mark, length multiple, lead-in), the preview gets the same
+fn transform_label(ui: &AppWindow, img: GrayImage, used_len: u32, mut preview: Option<GrayImage>, profile: &PrinterProfile) -> (GrayImage, u32, Option<GrayImage>) {
+    let (img, used_len) = if ui.get_auto_trim() {
+        // the columns to trim come from the printed label, the gray edges don't count
+        let content = content_columns(&img);
+        let trimmed_width = content.map_or(0, |(_, width)| width);
+        println!("Label trimmed from {} to {} columns", used_len, trimmed_width);
+        let first = content.map_or(0, |(first, _)| first);
+        preview = preview.map(|p| shift_left(&p, first));
+        (shift_left(&img, first), trimmed_width)
+    } else {
+        (img, used_len)
+    };
+    let (img, used_len) = match (ui.get_scale_to_length().max(0) as u32).min(img.width()) {
+        0 => (img, used_len),
+        length => {
+            preview = preview.map(|p| scale_to_length(&p, used_len, length));
+            (scale_to_length(&img, used_len, length), length)
         }
+    };
+    let (img, used_len) = match (ui.get_repeat_length().max(0) as u32).min(LABEL_WIDTH as u32) {
+        0 => (img, used_len),
+        length => {
+            let gap = ui.get_repeat_gap().max(0) as u32;
+            preview = preview.map(|p| repeat_to_length(&p, used_len, length, gap));
+            (repeat_to_length(&img, used_len, length, gap), length)
+        }
+    };
+    let img = if ui.get_mirror() {
+        preview = preview.map(|p| mirror_used_columns(&p, used_len));
+        mirror_used_columns(&img, used_len)
+    } else {
+        img
+    };
+    let (mut img, mut used_len) = match (profile.margin_start, profile.margin_end) {
+        (0, 0) => (img, used_len),
+        (start, end) => {
+            preview = preview.map(|p| add_margins(&p, used_len, start, end).0);
+            add_margins(&img, used_len, start, end)
+        }
+    };
+    let mark = mark_from_ui(ui);
+    if mark.enabled {
+        if let Some(p) = preview.as_mut() {
+            draw_registration_mark(p, used_len, &mark);
+        }
+        used_len = draw_registration_mark(&mut img, used_len, &mark);
+    }
+    let snapped = snap_length(used_len, ui.get_length_multiple().max(0) as u32, LABEL_WIDTH as u32);
+    if snapped != used_len {
+        preview = preview.map(|p| pad_to_width(&p, snapped));
+        img = pad_to_width(&img, snapped);
+        used_len = snapped;
+    }
+    let lead_in = ui.get_lead_in().max(0) as u32;
+    if lead_in > 0 {
+        preview = preview.map(|p| add_lead_in(&p, used_len, lead_in).0);
+        (img, used_len) = add_lead_in(&img, used_len, lead_in);
+    }
+    (img, used_len, preview)
+}
+
+/// pack the label for the printer of `profile` and show it, returns the packed bytes
+fn show_label(ui: &AppWindow, img: &GrayImage, preview: Option<&GrayImage>, profile: &PrinterProfile) -> Vec<u8> {
+    let (width, height) = img.dimensions();
+    let byte_data = profile.pack(img);
+    show_packed_bytes(ui, &byte_data, width, height);
+    if profile.two_color {
+        // the planes are sent as they are, the red ink is shown in red
+        show_preview(ui, get_two_color_slint_img(img));
+    } else if ui.get_exact_preview() {
+        let packed = unpack_bitmap_data(&byte_data, height as usize, width as usize, &profile.packing);
+        show_preview(ui, get_slint_img(&packed, height, width));
+    } else {
+        let preview = preview.unwrap_or(img);
+        show_preview(ui, get_slint_img(preview, preview.height(), preview.width()));
     }
-    *used_len += 1;
-    img
+    byte_data
 }
 
-fn get_bitmap_data(img: ImageBuffer<Luma<u8>, Vec<u8>>, height: usize, width: usize) -> Vec<u8> { 
-    let mut packed: Vec<u8> = Vec::with_capacity((width * height).div_ceil(8));
-    let mut current_byte = 0u8;
-    let mut bit_pos = 0;
-    println!("Image dimensions: WxH {}x{}", width, height);
-    for x in 0..width {
-        for y in 0..height {
-            let Luma([val]) = *img.get_pixel(x as u32, height as u32 - y as u32 - 1);
-            let bit = if val < 128 { 1 } else { 0 };
-            current_byte |= bit << (7 - bit_pos);
-            bit_pos += 1;
+/// time without a change before the preview is rendered again
+const PREVIEW_DELAY: Duration = Duration::from_millis(150);
 
-            if bit_pos == 8 {
-                packed.push(current_byte);
-                current_byte = 0;
-                bit_pos = 0;
+/// render the image preview with the current levels in a worker thread. the last good
+/// preview stays visible until the render is done, an outdated render is discarded
+fn start_preview_render(ui: &AppWindow, height: u32, generation: &Arc<AtomicU64>) {
+    let image_path = ui.get_image_path().to_string();
+    if image_path.is_empty() || !Path::new(&image_path).exists() {
+        return;
+    }
+    let ((x_offset, y_offset), levels, letterbox, auto_contrast) = (image_offsets_from_ui(ui), levels_from_ui(ui), letterbox_from_ui(ui), ui.get_auto_contrast());
+    let invert = ui.get_invert_image();
+    let filter = ResizeFilter::from_name(&ui.get_resize_filter());
+    let dither = Dither::from_name(&ui.get_dither());
+    let orientation = Orientation::from_name(&ui.get_image_orientation());
+    let despeckle_dots = ui.get_despeckle();
+    let id = generation.fetch_add(1, Ordering::SeqCst) + 1;
+    let generation = generation.clone();
+    ui.set_preview_updating(true);
+    let ui_handle = ui.as_weak();
+    thread::spawn(move || {
+        let result = load_source_image(Path::new(&image_path), height).map(|img| {
+            let before = luma_histogram(&img.to_luma8());
+            let image = ImageElement::new(img, x_offset, y_offset).with_levels(levels).with_letterbox(letterbox).with_auto_contrast(auto_contrast).with_filter(filter).with_dither(dither).with_orientation(orientation);
+            let after = luma_histogram(&image.gray(height));
+            let rotated = image.is_rotated(height);
+            let elements: Vec<Box<dyn LabelElement>> = vec![inverted_if(Box::new(image), invert)];
+            (cleaned(compose_dithered_with(&elements, LABEL_WIDTH as u32, height, dither), despeckle_dots).0, before, after, rotated)
+        });
+        let _ = ui_handle.upgrade_in_event_loop(move |ui| {
+            // a newer render was started or a new label shown meanwhile
+            if generation.load(Ordering::SeqCst) != id || !ui.get_preview_updating() {
+                return;
+            }
+            ui.set_preview_updating(false);
+            match result {
+                // preview only, the upload happens when the image is loaded again
+                Ok((preview, before, after, rotated)) => {
+                    let (width, height) = preview.dimensions();
+                    ui.set_previewimage(get_slint_img(&preview, height, width));
+                    show_histograms(&ui, &before, &after);
+                    ui.set_image_rotated(rotated);
+                    ui.set_print_width(0);
+                    set_status(&ui, StatusLevel::None, "Levels changed, load the image again to send it to the printer.");
+                }
+                Err(e) => set_status(&ui, StatusLevel::Error, &image_error_message(&image_path, &e)),
             }
+        });
+    });
+}
+
+/// a dithered label, with `remove_dots` set without isolated dots
+fn cleaned((mut img, used_len): (GrayImage, u32), remove_dots: bool) -> (GrayImage, u32) {
+    if remove_dots {
+        let flipped = despeckle(&mut img);
+        println!("Despeckle removed {} isolated pixels", flipped);
+    }
+    (img, used_len)
+}
+
+/// the file an export writes to after applying `policy` to the chosen `path`, `None` if
+/// the user keeps the existing file
+fn confirmed_destination(path: &Path, policy: OverwritePolicy) -> Option<PathBuf> {
+    match export_destination(path, policy) {
+        ExportDestination::Write(path) => Some(path),
+        ExportDestination::Confirm(path) => {
+            let answer = MessageDialog::new()
+                .set_level(MessageLevel::Warning)
+                .set_title("overwrite file")
+                .set_description(format!("{} already exists. Replace it?", path.display()))
+                .set_buttons(MessageButtons::YesNo)
+                .show();
+            (answer == MessageDialogResult::Yes).then_some(path)
         }
     }
-    println!("Bit-packed length: {} bytes", packed.len());
-    packed
 }
 
-fn get_slint_img (img: ImageBuffer<Luma<u8>, Vec<u8>>, height: u32, width: u32) -> slint::Image {
+/// logical pixels per dot of the true size preview for the selected printer
+fn show_true_size_scale(ui: &AppWindow, config: &Config) {
+    ui.set_true_size_scale(true_size_scale(config.profile().dpi, config.screen_dpi, ui.window().scale_factor()));
+}
+
+/// black, red and white preview of a two-color label, see `split_planes`
+fn get_two_color_slint_img(img: &GrayImage) -> slint::Image {
+    let (black, red) = split_planes(img);
+    let mut buffer = SharedPixelBuffer::<Rgba8Pixel>::new(img.width(), img.height());
+    for ((pixel, Luma([b])), Luma([r])) in buffer.make_mut_slice().iter_mut().zip(black.pixels()).zip(red.pixels()) {
+        *pixel = match (*b < 128, *r < 128) {
+            (true, _) => Rgba8Pixel { r: 0, g: 0, b: 0, a: 255 },
+            (false, true) => Rgba8Pixel { r: 204, g: 0, b: 0, a: 255 },
+            (false, false) => Rgba8Pixel { r: 255, g: 255, b: 255, a: 255 },
+        };
+    }
+    Image::from_rgba8(buffer)
+}
+
+/// ink of an element with the red box ticked
+fn ink_color(red: bool) -> InkColor {
+    if red { InkColor::Red } else { InkColor::Black }
+}
+
+/// height of the histogram charts in pixels
+const HISTOGRAM_HEIGHT: u32 = 48;
+
+/// bar chart of a histogram, one column per gray value, the fullest value fills the height
+fn histogram_image(histogram: &[usize; 256], height: u32) -> GrayImage {
+    let max = histogram.iter().copied().max().unwrap_or(0).max(1);
+    GrayImage::from_fn(256, height, |x, y| {
+        let bar = (histogram[x as usize] as u64 * height as u64).div_ceil(max as u64) as u32;
+        Luma([if height - y <= bar { 0 } else { 255 }])
+    })
+}
+
+/// show the histograms of the source image and of the corrected image
+fn show_histograms(ui: &AppWindow, before: &[usize; 256], after: &[usize; 256]) {
+    ui.set_histogram_before(get_slint_img(&histogram_image(before, HISTOGRAM_HEIGHT), HISTOGRAM_HEIGHT, 256));
+    ui.set_histogram_after(get_slint_img(&histogram_image(after, HISTOGRAM_HEIGHT), HISTOGRAM_HEIGHT, 256));
+}
+
+/// show a new preview, the replaced one is kept for the compare view
+fn show_preview(ui: &AppWindow, image: Image) {
+    // a running image preview render is outdated now
+    ui.set_preview_updating(false);
+    ui.set_previous_preview(ui.get_previewimage());
+    ui.set_previewimage(image);
+}
+
+fn get_slint_img (img: &ImageBuffer<Luma<u8>, Vec<u8>>, height: u32, width: u32) -> slint::Image {
     let mut buffer = SharedPixelBuffer::<Rgba8Pixel>::new(width, height);
     {
         let pixels = buffer.make_mut_bytes();
@@ -333,43 +2117,148 @@ fn get_slint_img (img: ImageBuffer<Luma<u8>, Vec<u8>>, height: u32, width: u32)
     Image::from_rgba8(buffer)
 }
 
-fn write_image(bytesvec: Vec<u8>) -> Result<(), Box<dyn Error>> {
-    const CHUNK_SIZE: usize = 96;
+#[cfg(test)]
+mod tests {
+    use super::*;
 
-    let client = Client::new();
+    use std::time::{Duration, Instant};
 
-    let mut index = 0;
+    fn font_list() -> FontList {
+        let entry = |name: &str| FontEntry { display_name: name.to_string(), path: format!("/nonexistent/{}.ttf", name) };
+        FontList::new(vec![entry("Alpha"), entry("Beta")], Vec::new())
+    }
 
-    for chunk in bytesvec.chunks(CHUNK_SIZE) {
-        let b64 = general_purpose::STANDARD.encode(chunk);
-        // 3 JSON erzeugen
-        let body = json!({
-            "index": index,
-            "data": b64
-        });
-        // 4 HTTP POST Request an /uploadjson
-    
-        let response = client.post("http://192.168.54.148/uploadjson")
-            .json(&body)
-            .send()?;
+    #[test]
+    fn an_invalid_font_index_selects_no_font() {
+        let list = font_list();
+        assert!(list.selected(-1).is_none());
+        assert!(list.selected(i32::MIN).is_none());
+        assert!(list.selected(2).is_none());
+        assert_eq!(list.selected(1).map(|entry| entry.display_name.as_str()), Some("Beta"));
+    }
+
+    #[test]
+    fn a_missing_font_file_is_an_error() {
+        assert!(matches!(TextElement::new("Label", "/nonexistent/arial.ttf"), Err(LabelError::Io(_))));
+    }
+
+    /// sends a chunk every 10 ms until the upload is cancelled
+    struct SlowTransport;
+
+    impl LabelTransport for SlowTransport {
+        fn upload(&self, data: &[u8], cancel: &AtomicBool, progress: &mut dyn FnMut(usize)) -> Result<UploadOutcome, LabelError> {
+            for sent in 1..=data.len() {
+                if cancel.load(Ordering::SeqCst) {
+                    return Ok(UploadOutcome::Cancelled { sent: sent - 1, total: data.len() });
+                }
+                thread::sleep(Duration::from_millis(10));
+                progress(sent);
+            }
+            Ok(UploadOutcome::Complete)
+        }
 
-        if !response.status().is_success() {
-            eprintln!("Error at UploadJson, Index {}: {}", index, response.status());
-            return Err("Upload failed".into());
+        fn print(&self, _length: u32) -> Result<(), LabelError> {
+            Ok(())
         }
-        index += CHUNK_SIZE;
     }
-    
-    println!("UploadJson successfully in {} blocks!", index);
 
-    Ok(())
+    #[test]
+    fn closing_during_an_upload_cancels_and_waits_for_it() {
+        let uploads = UploadControl::default();
+        let (_, cancel) = uploads.next();
+        let outcome = Arc::new(Mutex::new(None));
+        let (worker_cancel, worker_outcome) = (cancel.clone(), outcome.clone());
+        let handle = thread::spawn(move || {
+            let result = SlowTransport.upload(&[0; 1000], &worker_cancel, &mut |_| {});
+            *worker_outcome.lock().unwrap() = Some(result.unwrap());
+        });
+        UPLOAD_THREADS.lock().unwrap().push(handle);
+        thread::sleep(Duration::from_millis(50));
+        // what the close handler does
+        uploads.cancel();
+        let start = Instant::now();
+        join_uploads(SHUTDOWN_TIMEOUT);
+        assert!(start.elapsed() < Duration::from_secs(1));
+        assert!(UPLOAD_THREADS.lock().unwrap().is_empty());
+        let outcome = outcome.lock().unwrap().unwrap();
+        assert!(matches!(outcome, UploadOutcome::Cancelled { sent, total: 1000 } if sent < 1000), "{:?}", outcome);
+    }
 
-}
+    #[test]
+    fn a_new_upload_cancels_the_running_one_and_waits_for_it() {
+        let uploads = UploadControl::default();
+        let (first, first_cancel) = uploads.next();
+        let first_done = Arc::new(Mutex::new(None));
+        let worker_done = first_done.clone();
+        let running = thread::spawn(move || {
+            let outcome = send_exclusive(&SlowTransport, &[0; 1000], &first_cancel, &mut |_| {}).unwrap();
+            *worker_done.lock().unwrap() = Some(Instant::now());
+            outcome
+        });
+        thread::sleep(Duration::from_millis(50));
+        let (second, second_cancel) = uploads.next();
+        assert!(!uploads.is_current(first) && uploads.is_current(second));
+        let mut started = None;
+        let outcome = send_exclusive(&SlowTransport, &[0; 3], &second_cancel, &mut |_| {
+            started.get_or_insert_with(Instant::now);
+        });
+        assert_eq!(outcome.unwrap(), UploadOutcome::Complete);
+        assert!(matches!(running.join().unwrap(), UploadOutcome::Cancelled { sent, total: 1000 } if sent < 1000));
+        // the chunks of the second upload only went out after the first one stopped
+        assert!(first_done.lock().unwrap().unwrap() <= started.unwrap());
+    }
 
-fn print_image(length: u32) -> Result<(), Box<dyn Error>> {
-    let client = Client::new();
-    client.get(format!("http://192.168.54.148/print?length={}", length))
-        .send()?;
+    #[test]
+    fn a_truncated_png_is_reported_as_broken() {
+        let dir = env::temp_dir().join(format!("label_drawer-truncated-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let mut png = Vec::new();
+        let img = GrayImage::from_fn(64, 64, |x, y| Luma([((x * 4) ^ (y * 4)) as u8]));
+        img.write_to(&mut io::Cursor::new(&mut png), image::ImageFormat::Png).unwrap();
+        let path = dir.join("logo.png");
+        std::fs::write(&path, &png[..png.len() / 2]).unwrap();
+        let error = load_source_image(&path, 96).err().unwrap();
+        let message = image_error_message("logo.png", &error);
+        assert!(message.starts_with("Image file is corrupt") || message.starts_with("Image file is incomplete"), "{}", message);
 
-    Ok(())
-}
\ No newline at end of file
+        let unknown = dir.join("logo.xyz");
+        std::fs::write(&unknown, b"no image at all").unwrap();
+        assert!(image_error_message("logo.xyz", &load_source_image(&unknown, 96).err().unwrap()).starts_with("Unsupported image format"));
+        let missing = load_source_image(&dir.join("missing.png"), 96).err().unwrap();
+        assert_eq!(image_error_message("missing.png", &missing), "Image not found: missing.png");
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn the_text_preview_renders_in_a_worker_thread() {
+        let font = "/usr/share/fonts/truetype/dejavu/DejaVuSans.ttf";
+        if !Path::new(font).is_file() {
+            return;
+        }
+        let content = |text: &str, font_path: &str| LabelContent {
+            text: text.to_string(),
+            font_path: font_path.to_string(),
+            options: TextOptions::default(),
+            counter: TicketCounter::default(),
+            date_stamp: DateStamp::default(),
+            order: ElementKind::ALL.to_vec(),
+            image: None,
+            invert_text: false,
+            invert_counter: false,
+            red_text: false,
+            red_counter: false,
+            cut_out_text: false,
+            two_color: false,
+            exact_preview: false,
+            width: LABEL_WIDTH as u32,
+            height: 96,
+        };
+        let worker = content("Box 1", font);
+        let rendered = thread::spawn(move || render_content(&worker)).join().unwrap().unwrap();
+        let text = TextElement::new("Box 1", font).unwrap();
+        let (expected, length) = compose(&[Box::new(text) as Box<dyn LabelElement>], LABEL_WIDTH as u32, 96);
+        assert_eq!((rendered.img, rendered.used_len), (expected, length));
+        assert!(rendered.preview.is_some() && rendered.image_rotated.is_none());
+        assert!(render_content(&content("Box 1", "/nonexistent/font.ttf")).err().unwrap().starts_with("Error during loading the font"));
+    }
+}
@@ -0,0 +1,883 @@
+//! Transfer of the packed bitmap to the printer and the print command.
+
+use std::collections::BTreeMap;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, OnceLock, RwLock};
+use std::thread;
+use std::time::Duration;
+
+use base64::{engine::general_purpose, Engine as _};
+use image::{ImageBuffer, Luma};
+use reqwest::blocking::{Client, RequestBuilder};
+use reqwest::{Method, StatusCode};
+use reqwest::header::CONTENT_TYPE;
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+
+use crate::LABEL_WIDTH;
+use crate::compress::Compression;
+use crate::error::LabelError;
+use crate::render::{PackingOptions, get_bitmap_data_with};
+
+/// address of the printer
+pub const DEFAULT_BASE_URL: &str = "http://192.168.54.148";
+
+/// how often `poll_status` asks the printer before giving up
+const STATUS_POLLS: u32 = 20;
+/// pause between two status requests
+const STATUS_INTERVAL: Duration = Duration::from_millis(250);
+
+/// longest wait for the connection to the printer
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(5);
+/// longest wait for one request, a chunk or a print command
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(30);
+/// longest wait for the status answer, the status is polled and a late answer is of no use
+const STATUS_TIMEOUT: Duration = Duration::from_secs(2);
+
+/// bytes of bitmap data per upload request
+pub const CHUNK_SIZE: usize = 96;
+
+/// state of the printer as reported by its `/status` endpoint
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum PrinterState {
+    Idle,
+    Printing,
+    PaperOut,
+    HeadOpen,
+    Error(String),
+    /// the firmware has no status endpoint
+    Unsupported,
+}
+
+/// result of an upload which can be cancelled
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum UploadOutcome {
+    Complete,
+    /// stopped after `sent` of `total` bytes. no abort is sent, the partial label stays on the
+    /// printer until the next upload replaces it and must not be printed
+    Cancelled { sent: usize, total: usize },
+}
+
+/// a way to get a label to a printer. the default is `HttpTransport`, other printers can be
+/// plugged in with `set_transport`.
+///
+/// contract: `upload` receives the packed bitmap (see `get_bitmap_data`) and replaces the label
+/// stored on the printer. it checks `cancel` between its transfer steps and reports the bytes sent
+/// so far with `progress`. `print` prints the last complete upload with `length` columns, it may
+/// be called several times for copies. implementations are shared between threads and
+/// report their own failures as `LabelError::Transport`
+///
+/// ```
+/// use std::sync::atomic::AtomicBool;
+/// use std::sync::{Arc, Mutex};
+/// use label_drawer::error::LabelError;
+/// use label_drawer::printer::{LabelTransport, UploadOutcome, set_transport, transport};
+///
+/// /// keeps the label in memory instead of printing it
+/// #[derive(Default)]
+/// struct MemoryTransport {
+///     label: Mutex<Vec<u8>>,
+///     printed: Mutex<Vec<u32>>,
+/// }
+///
+/// impl LabelTransport for MemoryTransport {
+///     fn upload(&self, data: &[u8], _cancel: &AtomicBool, progress: &mut dyn FnMut(usize)) -> Result<UploadOutcome, LabelError> {
+///         *self.label.lock().unwrap() = data.to_vec();
+///         progress(data.len());
+///         Ok(UploadOutcome::Complete)
+///     }
+///     fn print(&self, length: u32) -> Result<(), LabelError> {
+///         self.printed.lock().unwrap().push(length);
+///         Ok(())
+///     }
+/// }
+///
+/// let memory = Arc::new(MemoryTransport::default());
+/// set_transport(memory.clone());
+/// transport().upload(&[0xff; 24], &AtomicBool::new(false), &mut |_| {}).unwrap();
+/// transport().print(16).unwrap();
+/// assert_eq!(memory.label.lock().unwrap().len(), 24);
+/// assert_eq!(*memory.printed.lock().unwrap(), vec![16]);
+/// ```
+pub trait LabelTransport: Send + Sync {
+    /// transfer the packed bitmap to the printer
+    fn upload(&self, data: &[u8], cancel: &AtomicBool, progress: &mut dyn FnMut(usize)) -> Result<UploadOutcome, LabelError>;
+    /// print the uploaded label with `length` columns
+    fn print(&self, length: u32) -> Result<(), LabelError>;
+    /// current state of the printer, `PrinterState::Unsupported` if it can't tell
+    fn status(&self) -> Result<PrinterState, LabelError> {
+        Ok(PrinterState::Unsupported)
+    }
+    /// dots of the print head as reported by the printer, `None` if it can't tell
+    fn head_width(&self) -> Result<Option<u32>, LabelError> {
+        Ok(None)
+    }
+    /// the settings of an `HttpTransport`, kept by requests to another host, see
+    /// `http_transport_at`. `None` for other transports
+    fn as_http(&self) -> Option<&HttpTransport> {
+        None
+    }
+}
+
+/// paths of the firmware requests, relative to the base url
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Endpoints {
+    pub upload: String,
+    pub print: String,
+    pub status: String,
+}
+
+impl Default for Endpoints {
+    fn default() -> Self {
+        Endpoints { upload: "/uploadjson".to_string(), print: "/print".to_string(), status: "/status".to_string() }
+    }
+}
+
+/// how a chunk is put into the body of an upload request
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum BodyEncoding {
+    /// `{"index": 0, "data": "<base64>"}` as `application/json`
+    #[default]
+    Json,
+    /// `index=0&data=<base64>` as `application/x-www-form-urlencoded`
+    Form,
+    /// only the base64 text as `text/plain`, the index goes into the query `?index=0`
+    Base64,
+}
+
+/// body encoding and extra headers of the upload requests
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct UploadFormat {
+    pub encoding: BodyEncoding,
+    /// sent with every upload request, e.g. an api key of the firmware
+    pub headers: BTreeMap<String, String>,
+    /// compression of the whole bitmap before it is split into chunks, the print command
+    /// names it in `compression=` so the firmware knows how to read the upload
+    pub compression: Compression,
+}
+
+/// one upload request, built by `upload_request`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct UploadRequest {
+    pub url: String,
+    /// content type first, then the extra headers
+    pub headers: Vec<(String, String)>,
+    pub body: Vec<u8>,
+}
+
+/// the request which uploads `chunk` to position `index` of the label
+pub fn upload_request(url: &str, index: usize, chunk: &[u8], format: &UploadFormat) -> UploadRequest {
+    let b64 = general_purpose::STANDARD.encode(chunk);
+    let (url, content_type, body) = match format.encoding {
+        BodyEncoding::Json => (url.to_string(), "application/json", json!({ "index": index, "data": b64 }).to_string()),
+        BodyEncoding::Form => {
+            // '+', '/' and '=' of the base64 alphabet are reserved in a form
+            let data = b64.replace('+', "%2B").replace('/', "%2F").replace('=', "%3D");
+            (url.to_string(), "application/x-www-form-urlencoded", format!("index={}&data={}", index, data))
+        }
+        BodyEncoding::Base64 => {
+            let separator = if url.contains('?') { '&' } else { '?' };
+            (format!("{}{}index={}", url, separator, index), "text/plain", b64)
+        }
+    };
+    let mut headers = vec![(CONTENT_TYPE.as_str().to_string(), content_type.to_string())];
+    headers.extend(format.headers.iter().map(|(name, value)| (name.clone(), value.clone())));
+    UploadRequest { url, headers, body: body.into_bytes() }
+}
+
+/// the label printer with the http firmware
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct HttpTransport {
+    pub base_url: String,
+    /// largest request body the firmware accepts, see `chunk_size_for_limit`
+    pub max_request_size: Option<usize>,
+    pub endpoints: Endpoints,
+    pub format: UploadFormat,
+    /// darkness sent with every print command, the setting of the printer if missing
+    pub density: Option<u8>,
+    /// the uploads hold a black and a red plane, told to the firmware with the print command
+    pub two_color: bool,
+}
+
+impl HttpTransport {
+    pub fn new(base_url: &str) -> Self {
+        HttpTransport { base_url: base_url.to_string(), max_request_size: None, endpoints: Endpoints::default(), format: UploadFormat::default(), density: None, two_color: false }
+    }
+
+    /// url of the print command for `length` columns
+    pub fn print_url(&self, length: u32) -> String {
+        let mut url = print_request_url(&format!("{}{}", self.base_url, self.endpoints.print), length, self.density);
+        if let Some(name) = self.format.compression.name() {
+            url = format!("{}&compression={}", url, name);
+        }
+        if self.two_color {
+            url.push_str("&planes=2");
+        }
+        url
+    }
+}
+
+/// `url` with the query of a print command, the density is only added if it is set
+pub fn print_request_url(url: &str, length: u32, density: Option<u8>) -> String {
+    match density {
+        Some(density) => format!("{}?length={}&density={}", url, length, density),
+        None => format!("{}?length={}", url, length),
+    }
+}
+
+impl Default for HttpTransport {
+    fn default() -> Self {
+        HttpTransport::new(DEFAULT_BASE_URL)
+    }
+}
+
+impl LabelTransport for HttpTransport {
+    fn upload(&self, data: &[u8], cancel: &AtomicBool, progress: &mut dyn FnMut(usize)) -> Result<UploadOutcome, LabelError> {
+        let upload = self.format.compression.apply(data);
+        if upload.len() != data.len() {
+            eprintln!("Compressed {} bytes to {}", data.len(), upload.len());
+        }
+        // the progress stays in bytes of the uncompressed bitmap
+        let mut progress = |sent: usize| progress(sent * data.len() / upload.len().max(1));
+        upload_json(&format!("{}{}", self.base_url, self.endpoints.upload), &upload, self.max_request_size, &self.format, cancel, &mut progress)
+    }
+
+    fn print(&self, length: u32) -> Result<(), LabelError> {
+        check_print_length(length)?;
+        print_url(&self.print_url(length))
+    }
+
+    fn status(&self) -> Result<PrinterState, LabelError> {
+        status_request(shared_client().get(format!("{}{}", self.base_url, self.endpoints.status)).timeout(STATUS_TIMEOUT))
+    }
+
+    fn head_width(&self) -> Result<Option<u32>, LabelError> {
+        let response = shared_client().get(format!("{}{}", self.base_url, self.endpoints.status)).timeout(STATUS_TIMEOUT).send()?;
+        if !response.status().is_success() {
+            // no status endpoint, no head width
+            return Ok(None);
+        }
+        Ok(parse_head_width(&response.text()?))
+    }
+
+    fn as_http(&self) -> Option<&HttpTransport> {
+        Some(self)
+    }
+}
+
+/// the http client of all printer requests, created on first use. it keeps the connections
+/// open between the chunks of an upload and the prints, and can be used from every thread
+static SHARED_CLIENT: OnceLock<Client> = OnceLock::new();
+
+/// the client shared by uploads, prints and status requests
+pub fn shared_client() -> &'static Client {
+    SHARED_CLIENT.get_or_init(|| {
+        Client::builder()
+            .connect_timeout(CONNECT_TIMEOUT)
+            .timeout(REQUEST_TIMEOUT)
+            .build()
+            .unwrap_or_else(|e| {
+                eprintln!("Http client with timeouts not available, using the default client: {}", e);
+                Client::new()
+            })
+    })
+}
+
+/// the transport used by the app, `None` until `set_transport` is called
+static ACTIVE_TRANSPORT: RwLock<Option<Arc<dyn LabelTransport>>> = RwLock::new(None);
+
+/// make `transport` the one returned by `transport()`
+pub fn set_transport(transport: Arc<dyn LabelTransport>) {
+    *ACTIVE_TRANSPORT.write().unwrap_or_else(|e| e.into_inner()) = Some(transport);
+}
+
+/// the active transport, the default `HttpTransport` if none was set
+pub fn transport() -> Arc<dyn LabelTransport> {
+    ACTIVE_TRANSPORT
+        .read()
+        .unwrap_or_else(|e| e.into_inner())
+        .clone()
+        .unwrap_or_else(|| Arc::new(HttpTransport::default()))
+}
+
+/// the printer at `base_url` with the settings of the active transport, the defaults if the
+/// active transport isn't an `HttpTransport`
+pub fn http_transport_at(base_url: &str) -> HttpTransport {
+    let settings = transport().as_http().cloned().unwrap_or_default();
+    HttpTransport { base_url: base_url.to_string(), ..settings }
+}
+
+/// upload the packed bitmap with the active transport, see `set_transport`
+pub fn write_image(bytesvec: Vec<u8>) -> Result<(), LabelError> {
+    transport().upload(&bytesvec, &AtomicBool::new(false), &mut |_| {})?;
+    Ok(())
+}
+
+/// largest body of an upload request with `chunk_len` bytes of data at `index`
+pub fn encoded_request_size(index: usize, chunk_len: usize, encoding: BodyEncoding) -> usize {
+    let format = UploadFormat { encoding, ..UploadFormat::default() };
+    // 0xff encodes to '/', the largest case of a form
+    upload_request("", index, &vec![0xffu8; chunk_len], &format).body.len()
+}
+
+/// largest chunk size up to `CHUNK_SIZE` whose requests for `total` bytes stay within
+/// `max_request_size` bytes of body, `CHUNK_SIZE` if there is no limit
+pub fn chunk_size_for_limit(total: usize, max_request_size: Option<usize>, encoding: BodyEncoding) -> Result<usize, LabelError> {
+    let Some(max_request_size) = max_request_size else {
+        return Ok(CHUNK_SIZE);
+    };
+    // the last index has the most digits, so it gives the largest request
+    (1..=CHUNK_SIZE)
+        .rev()
+        .find(|&chunk| encoded_request_size(total.saturating_sub(1) / chunk * chunk, chunk, encoding) <= max_request_size)
+        .ok_or_else(|| LabelError::InvalidInput(format!("Max request size of {} bytes is too small for an upload request", max_request_size)))
+}
+
+/// like `write_image` for the printer at `base_url` (see `http_transport_at`), but `cancel`
+/// is checked before every chunk and `progress` is called with the number of bytes sent so
+/// far after every chunk. with `max_request_size` the chunks are made small enough that no
+/// request body exceeds it, without it the limit of the active transport applies
+pub fn write_image_cancellable(base_url: &str, bytesvec: Vec<u8>, max_request_size: Option<usize>, cancel: &AtomicBool, mut progress: impl FnMut(usize)) -> Result<UploadOutcome, LabelError> {
+    let printer = http_transport_at(base_url);
+    let printer = HttpTransport { max_request_size: max_request_size.or(printer.max_request_size), ..printer };
+    printer.upload(&bytesvec, cancel, &mut progress)
+}
+
+/// post the chunks of `bytesvec` to the upload url
+fn upload_json(url: &str, bytesvec: &[u8], max_request_size: Option<usize>, format: &UploadFormat, cancel: &AtomicBool, progress: &mut dyn FnMut(usize)) -> Result<UploadOutcome, LabelError> {
+    let chunk_size = chunk_size_for_limit(bytesvec.len(), max_request_size, format.encoding)?;
+    eprintln!("UploadJson with chunk size {} bytes", chunk_size);
+
+    let client = shared_client();
+
+    let mut index = 0;
+
+    for chunk in bytesvec.chunks(chunk_size) {
+        if cancel.load(Ordering::SeqCst) {
+            eprintln!("UploadJson cancelled after {} of {} bytes", index, bytesvec.len());
+            return Ok(UploadOutcome::Cancelled { sent: index, total: bytesvec.len() });
+        }
+        let request = upload_request(url, index, chunk, format);
+        let mut builder = client.post(&request.url);
+        for (name, value) in &request.headers {
+            builder = builder.header(name, value);
+        }
+        let response = builder.body(request.body).send()?;
+
+        if !response.status().is_success() {
+            eprintln!("Error at UploadJson, Index {}: {}", index, response.status());
+            return Err(LabelError::UploadFailed { index, status: response.status().as_u16() });
+        }
+        response.bytes()?;
+        index += chunk_size;
+        progress(index.min(bytesvec.len()));
+    }
+    
+    eprintln!("UploadJson successfully in {} blocks!", index);
+
+    Ok(UploadOutcome::Complete)
+
+}
+
+/// print the uploaded bitmap with the active transport, `length` is the number of columns to print
+pub fn print_image(length: u32) -> Result<(), LabelError> {
+    transport().print(length)
+}
+
+/// like `print_image` for the printer at `base_url`, see `http_transport_at`
+pub fn print_image_at(base_url: &str, length: u32) -> Result<(), LabelError> {
+    http_transport_at(base_url).print(length)
+}
+
+/// refuse a print of 0 columns, the firmware would get `length=0` for an empty label
+pub fn check_print_length(length: u32) -> Result<(), LabelError> {
+    if length == 0 {
+        return Err(LabelError::InvalidInput("Nothing to print, the label length is 0".to_string()));
+    }
+    Ok(())
+}
+
+/// send the print request
+fn print_url(url: &str) -> Result<(), LabelError> {
+    let response = shared_client().get(url)
+        .send()?;
+    if !response.status().is_success() {
+        return Err(LabelError::Status { status: response.status().as_u16() });
+    }
+    // the connection is only reused once the answer is read
+    response.bytes()?;
+
+    Ok(())
+}
+
+/// print length for a label of `length` columns followed by `feed` blank columns.
+/// the firmware has no feed command, so the feed is printed from the blank rest of the
+/// uploaded buffer and can't go beyond its width
+pub fn length_with_feed(length: u32, feed: u32) -> u32 {
+    length.saturating_add(feed).min(LABEL_WIDTH as u32)
+}
+
+/// map the status answer (`{"state": "..."}` or plain text) of the firmware to a state
+pub fn parse_status(body: &str) -> PrinterState {
+    let state = serde_json::from_str::<Value>(body)
+        .ok()
+        .and_then(|v| v.get("state").and_then(Value::as_str).map(str::to_string))
+        .unwrap_or_else(|| body.trim().to_string());
+    match state.to_ascii_lowercase().as_str() {
+        "idle" | "ready" | "done" => PrinterState::Idle,
+        "printing" | "busy" => PrinterState::Printing,
+        "paper_out" | "paperout" | "no_tape" => PrinterState::PaperOut,
+        "head_open" | "headopen" | "cover_open" => PrinterState::HeadOpen,
+        _ => PrinterState::Error(state),
+    }
+}
+
+/// head width from the status answer of the firmware (`{"head_width": 96, ...}`, some
+/// firmwares call it `dots`), `None` for a plain text answer or without the field
+pub fn parse_head_width(body: &str) -> Option<u32> {
+    let value = serde_json::from_str::<Value>(body).ok()?;
+    ["head_width", "dots"]
+        .iter()
+        .find_map(|key| value.get(key).and_then(Value::as_u64))
+        .and_then(|dots| u32::try_from(dots).ok())
+}
+
+/// ask the printer at `base_url` for the dots of its print head
+pub fn get_head_width(base_url: &str) -> Result<Option<u32>, LabelError> {
+    http_transport_at(base_url).head_width()
+}
+
+/// a label of `height` pixels only prints right on a head with as many dots.
+/// an unknown head width passes
+pub fn check_head_width(head: Option<u32>, height: u32) -> Result<(), LabelError> {
+    match head {
+        Some(head) if head != height => Err(LabelError::HeadMismatch { head, height }),
+        _ => Ok(()),
+    }
+}
+
+/// check the label height against the head of the printer before an upload. if the printer
+/// can't be asked the check is skipped, the upload reports the connection problem itself
+pub fn verify_head_width(transport: &dyn LabelTransport, height: u32) -> Result<(), LabelError> {
+    match transport.head_width() {
+        Ok(head) => check_head_width(head, height),
+        Err(e) => {
+            eprintln!("Head width not checked: {}", e);
+            Ok(())
+        }
+    }
+}
+
+/// ask the printer once for its state
+pub fn get_status(client: &Client, base_url: &str) -> Result<PrinterState, LabelError> {
+    status_request(client.get(format!("{}{}", base_url, http_transport_at(base_url).endpoints.status)))
+}
+
+/// send the status request and parse the state
+fn status_request(request: RequestBuilder) -> Result<PrinterState, LabelError> {
+    let response = request.send()?;
+    if response.status() == StatusCode::NOT_FOUND {
+        return Ok(PrinterState::Unsupported);
+    }
+    if !response.status().is_success() {
+        return Err(LabelError::Status { status: response.status().as_u16() });
+    }
+    Ok(parse_status(&response.text()?))
+}
+
+/// a request typed into the developer panel, see `raw_request`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RawRequest {
+    pub method: Method,
+    pub url: String,
+    pub body: Vec<u8>,
+}
+
+/// answer to a raw request, an error status is shown and not turned into an error
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RawResponse {
+    pub status: u16,
+    pub body: Vec<u8>,
+}
+
+/// bytes of a raw request body: hex digits, spaces between the bytes are allowed,
+/// or base64 after a `base64:` prefix. empty text is an empty body
+pub fn parse_raw_body(text: &str) -> Result<Vec<u8>, LabelError> {
+    let text = text.trim();
+    if let Some(encoded) = text.strip_prefix("base64:") {
+        return general_purpose::STANDARD.decode(encoded.trim()).map_err(|e| LabelError::InvalidInput(format!("Invalid base64: {}", e)));
+    }
+    let digits: String = text.chars().filter(|c| !c.is_whitespace()).collect();
+    let digits = digits.strip_prefix("0x").unwrap_or(&digits);
+    if !digits.is_ascii() {
+        return Err(LabelError::InvalidInput("Only hex digits are allowed".to_string()));
+    }
+    if !digits.len().is_multiple_of(2) {
+        return Err(LabelError::InvalidInput(format!("Odd number of hex digits: {}", digits.len())));
+    }
+    (0..digits.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(&digits[i..i + 2], 16).map_err(|_| LabelError::InvalidInput(format!("Invalid hex byte: {}", &digits[i..i + 2]))))
+        .collect()
+}
+
+/// build a request for `path` below `base_url`, `method` is GET, POST, PUT or DELETE
+pub fn raw_request(base_url: &str, method: &str, path: &str, body: &[u8]) -> Result<RawRequest, LabelError> {
+    let method = match method.trim().to_ascii_uppercase().as_str() {
+        "GET" => Method::GET,
+        "POST" => Method::POST,
+        "PUT" => Method::PUT,
+        "DELETE" => Method::DELETE,
+        other => return Err(LabelError::InvalidInput(format!("Unsupported method: {}", other))),
+    };
+    let path = path.trim();
+    let separator = if path.starts_with('/') { "" } else { "/" };
+    let url = format!("{}{}{}", base_url.trim_end_matches('/'), separator, path);
+    Ok(RawRequest { method, url, body: body.to_vec() })
+}
+
+/// send any request to the printer and return its answer, for exploring the firmware
+pub fn send_raw(base_url: &str, method: &str, path: &str, body: &[u8]) -> Result<RawResponse, LabelError> {
+    let request = raw_request(base_url, method, path, body)?;
+    let mut builder = shared_client().request(request.method, &request.url);
+    if !request.body.is_empty() {
+        builder = builder.header(CONTENT_TYPE, "application/octet-stream").body(request.body);
+    }
+    let response = builder.send()?;
+    let status = response.status().as_u16();
+    Ok(RawResponse { status, body: response.bytes()?.to_vec() })
+}
+
+/// wait until the printer is no longer printing, at most `STATUS_POLLS` requests.
+/// returns `PrinterState::Unsupported` right away if the firmware has no status endpoint
+pub fn poll_status(base_url: &str) -> Result<PrinterState, LabelError> {
+    poll_transport_status(&http_transport_at(base_url))
+}
+
+/// like `poll_status` for any transport
+pub fn poll_transport_status(transport: &dyn LabelTransport) -> Result<PrinterState, LabelError> {
+    let mut state = PrinterState::Printing;
+    for _ in 0..STATUS_POLLS {
+        state = transport.status()?;
+        if state != PrinterState::Printing {
+            break;
+        }
+        thread::sleep(STATUS_INTERVAL);
+    }
+    Ok(state)
+}
+
+/// upload and print the bands of a tall label (see `split_into_bands`) one after another
+/// with the active transport so they join into one long label. every band is packed with
+/// `options` and printed with `length` columns. returns the total length
+pub fn print_bands(bands: &[ImageBuffer<Luma<u8>, Vec<u8>>], length: u32, options: &PackingOptions) -> Result<u32, LabelError> {
+    let printer = transport();
+    if let Some(band) = bands.first() {
+        verify_head_width(printer.as_ref(), band.height())?;
+    }
+    for (i, band) in bands.iter().enumerate() {
+        eprintln!("Print band {} of {}", i + 1, bands.len());
+        let (width, height) = band.dimensions();
+        printer.upload(&get_bitmap_data_with(band, height as usize, width as usize, options), &AtomicBool::new(false), &mut |_| {})?;
+        printer.print(length)?;
+    }
+    Ok(length * bands.len() as u32)
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use std::io::{BufRead, BufReader, Read, Write};
+    use std::net::TcpListener;
+    use std::sync::{Arc, Mutex};
+
+    /// a request received by `mock_printer`
+    #[derive(Debug, Clone)]
+    pub(crate) struct MockRequest {
+        /// e.g. `POST /upload HTTP/1.1`
+        pub(crate) line: String,
+        /// names in lower case
+        pub(crate) headers: Vec<(String, String)>,
+        /// number of the connection the request came on, counted from 0
+        pub(crate) connection: usize,
+        pub(crate) body: Vec<u8>,
+    }
+
+    /// a printer on a local port answering every request with `answer(request line)`, a
+    /// status and a body. returns its base url and the requests received so far
+    pub(crate) fn mock_printer(answer: impl Fn(&str) -> (u16, String) + Send + Sync + 'static) -> (String, Arc<Mutex<Vec<MockRequest>>>) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let base_url = format!("http://{}", listener.local_addr().unwrap());
+        let requests = Arc::new(Mutex::new(Vec::new()));
+        let (received, answer) = (requests.clone(), Arc::new(answer));
+        thread::spawn(move || {
+            for (connection, stream) in listener.incoming().flatten().enumerate() {
+                let (received, answer) = (received.clone(), answer.clone());
+                // a connection is kept open for the following requests
+                thread::spawn(move || {
+                    let mut reader = BufReader::new(stream.try_clone().unwrap());
+                    let mut stream = stream;
+                    loop {
+                        let mut line = String::new();
+                        if reader.read_line(&mut line).unwrap_or(0) == 0 {
+                            return;
+                        }
+                        let mut headers = Vec::new();
+                        loop {
+                            let mut header = String::new();
+                            reader.read_line(&mut header).unwrap();
+                            let Some((name, value)) = header.trim_end().split_once(':') else { break };
+                            headers.push((name.to_ascii_lowercase(), value.trim().to_string()));
+                        }
+                        let length = headers.iter().find(|(name, _)| name == "content-length").map_or(0, |(_, value)| value.parse().unwrap());
+                        let mut body = vec![0; length];
+                        reader.read_exact(&mut body).unwrap();
+                        let line = line.trim_end().to_string();
+                        let (status, text) = answer(&line);
+                        received.lock().unwrap().push(MockRequest { line, headers, connection, body });
+                        let response = format!("HTTP/1.1 {} Mock\r\nContent-Length: {}\r\n\r\n{}", status, text.len(), text);
+                        if stream.write_all(response.as_bytes()).is_err() {
+                            return;
+                        }
+                    }
+                });
+            }
+        });
+        (base_url, requests)
+    }
+
+    #[test]
+    fn status_answers_map_to_printer_states() {
+        assert_eq!(parse_status(r#"{"state": "Ready"}"#), PrinterState::Idle);
+        assert_eq!(parse_status("busy\n"), PrinterState::Printing);
+        assert_eq!(parse_status(r#"{"state": "paper_out"}"#), PrinterState::PaperOut);
+        assert_eq!(parse_status("cover_open"), PrinterState::HeadOpen);
+        assert_eq!(parse_status(r#"{"state": "overheated"}"#), PrinterState::Error("overheated".to_string()));
+    }
+
+    #[test]
+    fn polling_waits_until_the_printer_is_done() {
+        let polls = Arc::new(Mutex::new(0));
+        let counted = polls.clone();
+        let (base_url, _) = mock_printer(move |_| {
+            let mut polls = counted.lock().unwrap();
+            *polls += 1;
+            (200, if *polls < 3 { "printing" } else { r#"{"state": "paper_out"}"# }.to_string())
+        });
+        assert_eq!(poll_status(&base_url).unwrap(), PrinterState::PaperOut);
+        assert_eq!(*polls.lock().unwrap(), 3);
+        // a firmware without status endpoint isn't polled again
+        let (base_url, requests) = mock_printer(|_| (404, String::new()));
+        assert_eq!(poll_status(&base_url).unwrap(), PrinterState::Unsupported);
+        let requests = requests.lock().unwrap();
+        assert_eq!(requests.len(), 1);
+        assert_eq!(requests[0].line, "GET /status HTTP/1.1");
+    }
+
+    #[test]
+    fn a_cancelled_upload_sends_no_further_chunks() {
+        let (base_url, requests) = mock_printer(|_| (200, "OK".to_string()));
+        let cancel = AtomicBool::new(false);
+        let mut reported = Vec::new();
+        let outcome = write_image_cancellable(&base_url, vec![0x55; CHUNK_SIZE * 5], None, &cancel, |sent| {
+            reported.push(sent);
+            if sent >= 2 * CHUNK_SIZE {
+                cancel.store(true, Ordering::SeqCst);
+            }
+        });
+        assert_eq!(outcome.unwrap(), UploadOutcome::Cancelled { sent: 2 * CHUNK_SIZE, total: 5 * CHUNK_SIZE });
+        assert_eq!(reported, vec![CHUNK_SIZE, 2 * CHUNK_SIZE]);
+        let requests = requests.lock().unwrap();
+        assert_eq!(requests.len(), 2);
+        assert!(requests.iter().all(|request| request.line.starts_with("POST /uploadjson")));
+    }
+
+    #[test]
+    fn the_chunks_are_shrunk_to_the_request_limit() {
+        let total = 24000;
+        assert_eq!(chunk_size_for_limit(total, None, BodyEncoding::Json).unwrap(), CHUNK_SIZE);
+        for encoding in [BodyEncoding::Json, BodyEncoding::Form, BodyEncoding::Base64] {
+            let chunk = chunk_size_for_limit(total, Some(100), encoding).unwrap();
+            assert!(chunk < CHUNK_SIZE);
+            let last_index = (total - 1) / chunk * chunk;
+            assert!(encoded_request_size(last_index, chunk, encoding) <= 100);
+            // one byte more would not fit
+            let last_index = (total - 1) / (chunk + 1) * (chunk + 1);
+            assert!(encoded_request_size(last_index, chunk + 1, encoding) > 100);
+        }
+        assert!(chunk_size_for_limit(total, Some(10), BodyEncoding::Json).is_err());
+
+        let (base_url, requests) = mock_printer(|_| (200, "OK".to_string()));
+        write_image_cancellable(&base_url, vec![0xff; 1000], Some(100), &AtomicBool::new(false), |_| {}).unwrap();
+        let requests = requests.lock().unwrap();
+        assert!(requests.len() > 1000 / CHUNK_SIZE + 1);
+        assert!(requests.iter().all(|request| request.body.len() <= 100));
+    }
+
+    /// keeps the label in memory instead of printing it
+    #[derive(Default)]
+    struct MemoryTransport {
+        label: Mutex<Vec<u8>>,
+        printed: Mutex<Vec<u32>>,
+    }
+
+    impl LabelTransport for MemoryTransport {
+        fn upload(&self, data: &[u8], _cancel: &AtomicBool, progress: &mut dyn FnMut(usize)) -> Result<UploadOutcome, LabelError> {
+            *self.label.lock().unwrap() = data.to_vec();
+            progress(data.len());
+            Ok(UploadOutcome::Complete)
+        }
+
+        fn print(&self, length: u32) -> Result<(), LabelError> {
+            self.printed.lock().unwrap().push(length);
+            Ok(())
+        }
+    }
+
+    /// serializes the tests which change or use the active transport
+    static TRANSPORT_LOCK: Mutex<()> = Mutex::new(());
+
+    /// no other test changes the active transport while it is held
+    pub(crate) fn lock_transport() -> std::sync::MutexGuard<'static, ()> {
+        TRANSPORT_LOCK.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    /// holds `lock_transport` and puts the transport active before back when dropped
+    pub(crate) struct TransportGuard {
+        previous: Option<Arc<dyn LabelTransport>>,
+        _lock: std::sync::MutexGuard<'static, ()>,
+    }
+
+    impl TransportGuard {
+        pub(crate) fn new() -> Self {
+            let lock = lock_transport();
+            TransportGuard { previous: ACTIVE_TRANSPORT.read().unwrap_or_else(|e| e.into_inner()).clone(), _lock: lock }
+        }
+    }
+
+    impl Drop for TransportGuard {
+        fn drop(&mut self) {
+            *ACTIVE_TRANSPORT.write().unwrap_or_else(|e| e.into_inner()) = self.previous.take();
+        }
+    }
+
+    #[test]
+    fn entry_points_use_the_active_transport() {
+        let guard = TransportGuard::new();
+        let memory = Arc::new(MemoryTransport::default());
+        set_transport(memory.clone());
+        write_image(vec![0xff; 24]).unwrap();
+        print_image(16).unwrap();
+        assert_eq!(memory.label.lock().unwrap().len(), 24);
+        assert_eq!(*memory.printed.lock().unwrap(), vec![16]);
+        // every band is uploaded and printed on its own
+        let bands = vec![ImageBuffer::from_pixel(LABEL_WIDTH as u32, 96, Luma([0u8])); 2];
+        assert_eq!(print_bands(&bands, 50, &PackingOptions::default()).unwrap(), 100);
+        assert_eq!(memory.label.lock().unwrap().len(), LABEL_WIDTH * 96 / 8);
+        assert_eq!(*memory.printed.lock().unwrap(), vec![16, 50, 50]);
+        // a custom transport has no http settings to keep
+        assert_eq!(http_transport_at("http://10.0.0.2"), HttpTransport::new("http://10.0.0.2"));
+
+        let endpoints = Endpoints { upload: "/api/upload".to_string(), print: "/api/print".to_string(), status: "/api/status".to_string() };
+        set_transport(Arc::new(HttpTransport { endpoints: endpoints.clone(), max_request_size: Some(300), ..HttpTransport::new("http://10.0.0.1") }));
+        let other = http_transport_at("http://10.0.0.2");
+        assert_eq!(other, HttpTransport { endpoints: endpoints.clone(), max_request_size: Some(300), ..HttpTransport::new("http://10.0.0.2") });
+        drop(guard);
+        assert!(transport().as_http().is_none_or(|http| http.endpoints != endpoints));
+    }
+
+    #[test]
+    fn the_upload_is_sent_with_the_chosen_encoding_and_headers() {
+        let (base_url, requests) = mock_printer(|_| (200, "OK".to_string()));
+        let chunk = [0xfb, 0xff, 0x00];
+        for (encoding, content_type, line, body) in [
+            (BodyEncoding::Json, "application/json", "POST /uploadjson HTTP/1.1", r#"{"data":"+/8A","index":0}"#),
+            (BodyEncoding::Form, "application/x-www-form-urlencoded", "POST /uploadjson HTTP/1.1", "index=0&data=%2B%2F8A"),
+            (BodyEncoding::Base64, "text/plain", "POST /uploadjson?index=0 HTTP/1.1", "+/8A"),
+        ] {
+            let mut transport = HttpTransport::new(&base_url);
+            transport.format = UploadFormat { encoding, headers: BTreeMap::from([("X-Api-Key".to_string(), "secret".to_string())]), ..UploadFormat::default() };
+            transport.upload(&chunk, &AtomicBool::new(false), &mut |_| {}).unwrap();
+            let request = requests.lock().unwrap().pop().unwrap();
+            assert_eq!(request.line, line);
+            assert!(request.headers.contains(&("content-type".to_string(), content_type.to_string())), "{:?}", request.headers);
+            assert!(request.headers.contains(&("x-api-key".to_string(), "secret".to_string())));
+            assert_eq!(String::from_utf8(request.body).unwrap(), body);
+        }
+    }
+
+    #[test]
+    fn the_density_is_only_sent_when_set() {
+        let (base_url, requests) = mock_printer(|_| (200, "OK".to_string()));
+        let transport = HttpTransport::new(&base_url);
+        transport.print(120).unwrap();
+        HttpTransport { density: Some(12), ..transport }.print(120).unwrap();
+        let lines: Vec<String> = requests.lock().unwrap().iter().map(|request| request.line.clone()).collect();
+        assert_eq!(lines, vec!["GET /print?length=120 HTTP/1.1", "GET /print?length=120&density=12 HTTP/1.1"]);
+        assert_eq!(print_request_url("http://printer/go", 8, Some(0)), "http://printer/go?length=8&density=0");
+    }
+
+    #[test]
+    fn a_label_of_length_0_is_not_sent_to_the_printer() {
+        let (base_url, requests) = mock_printer(|_| (200, "OK".to_string()));
+        let transport = HttpTransport::new(&base_url);
+        assert!(matches!(transport.print(0), Err(LabelError::InvalidInput(_))));
+        assert!(requests.lock().unwrap().is_empty());
+        transport.print(1).unwrap();
+        assert_eq!(requests.lock().unwrap().len(), 1);
+        assert!(check_print_length(u32::MAX).is_ok());
+    }
+
+    #[test]
+    fn sequential_prints_reuse_the_connection() {
+        let (base_url, requests) = mock_printer(|_| (200, "OK".to_string()));
+        let transport = HttpTransport::new(&base_url);
+        for length in [10, 20, 30] {
+            transport.print(length).unwrap();
+        }
+        transport.upload(&[0x55; CHUNK_SIZE * 2], &AtomicBool::new(false), &mut |_| {}).unwrap();
+        let requests = requests.lock().unwrap();
+        assert_eq!(requests.len(), 5);
+        assert!(requests.iter().all(|request| request.connection == 0), "{:?}", requests);
+        assert!(std::ptr::eq(shared_client(), shared_client()));
+    }
+
+    #[test]
+    fn a_label_for_another_head_is_refused() {
+        let (base_url, _) = mock_printer(|_| (200, r#"{"state": "ready", "head_width": 128}"#.to_string()));
+        let printer = HttpTransport::new(&base_url);
+        assert_eq!(printer.head_width().unwrap(), Some(128));
+        assert!(matches!(verify_head_width(&printer, 96), Err(LabelError::HeadMismatch { head: 128, height: 96 })));
+        assert!(verify_head_width(&printer, 128).is_ok());
+        // a firmware without the head width or the status can't be checked
+        let (silent, _) = mock_printer(|_| (200, r#"{"state": "ready"}"#.to_string()));
+        assert!(verify_head_width(&HttpTransport::new(&silent), 96).is_ok());
+        let (missing, _) = mock_printer(|_| (404, String::new()));
+        assert_eq!(HttpTransport::new(&missing).head_width().unwrap(), None);
+        assert_eq!(parse_head_width(r#"{"dots": 384}"#), Some(384));
+    }
+
+    #[test]
+    fn a_raw_request_is_built_from_the_panel_input() {
+        assert_eq!(parse_raw_body("1b 40 0A").unwrap(), vec![0x1b, 0x40, 0x0a]);
+        assert_eq!(parse_raw_body("0x1b40").unwrap(), vec![0x1b, 0x40]);
+        assert_eq!(parse_raw_body("base64: G0A=").unwrap(), vec![0x1b, 0x40]);
+        assert!(parse_raw_body("  ").unwrap().is_empty());
+        for invalid in ["1b4", "zz", "1bä0", "base64:!!"] {
+            assert!(matches!(parse_raw_body(invalid), Err(LabelError::InvalidInput(_))), "{}", invalid);
+        }
+        let request = raw_request("http://10.0.0.1/", " post ", "config", &[1, 2]).unwrap();
+        assert_eq!(request, RawRequest { method: Method::POST, url: "http://10.0.0.1/config".to_string(), body: vec![1, 2] });
+        assert_eq!(raw_request("http://10.0.0.1", "GET", "/status", &[]).unwrap().url, "http://10.0.0.1/status");
+        assert!(matches!(raw_request("http://10.0.0.1", "PATCH", "/", &[]), Err(LabelError::InvalidInput(_))));
+        // an error status is an answer, not an error
+        let (base_url, requests) = mock_printer(|_| (418, "teapot".to_string()));
+        let response = send_raw(&base_url, "PUT", "/raw", &[0xff]).unwrap();
+        assert_eq!(response, RawResponse { status: 418, body: b"teapot".to_vec() });
+        let request = requests.lock().unwrap().pop().unwrap();
+        assert_eq!((request.line.as_str(), request.body), ("PUT /raw HTTP/1.1", vec![0xff]));
+    }
+}
@@ -0,0 +1,95 @@
+//! Named printer profiles, so switching between printers is one selection.
+
+use image::GrayImage;
+use serde::{Deserialize, Serialize};
+
+use crate::printer::{DEFAULT_BASE_URL, Endpoints, HttpTransport, UploadFormat};
+use crate::render::{PackingOptions, get_bitmap_data_with, get_two_color_data};
+use crate::{LABEL_HEIGHT, PRINTER_DPI};
+
+/// everything that differs between two printers
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct PrinterProfile {
+    /// shown in the profile selection
+    pub name: String,
+    /// base url of the firmware
+    pub host: String,
+    pub dpi: u32,
+    /// dot count of the print head, the label height in pixels
+    pub head_height: u32,
+    /// columns the printer can't print at the start of the label
+    pub margin_start: u32,
+    /// blank columns added after the label
+    pub margin_end: u32,
+    pub endpoints: Endpoints,
+    /// largest request body the firmware accepts in bytes, no limit if missing
+    pub max_request_size: Option<usize>,
+    /// byte layout of the bitmap the firmware expects
+    pub packing: PackingOptions,
+    /// body encoding and extra headers of the upload requests
+    pub upload_format: UploadFormat,
+    /// print darkness, the firmware keeps its own setting if missing
+    pub density: Option<u8>,
+    /// the printer prints red and black, labels are sent as two planes, see `get_two_color_data`
+    pub two_color: bool,
+}
+
+impl Default for PrinterProfile {
+    fn default() -> Self {
+        PrinterProfile {
+            name: "default".to_string(),
+            host: DEFAULT_BASE_URL.to_string(),
+            dpi: PRINTER_DPI,
+            head_height: LABEL_HEIGHT as u32,
+            margin_start: 0,
+            margin_end: 0,
+            endpoints: Endpoints::default(),
+            max_request_size: None,
+            packing: PackingOptions::default(),
+            upload_format: UploadFormat::default(),
+            density: None,
+            two_color: false,
+        }
+    }
+}
+
+impl PrinterProfile {
+    /// transport to the printer of this profile
+    pub fn transport(&self) -> HttpTransport {
+        HttpTransport { base_url: self.host.clone(), max_request_size: self.max_request_size, endpoints: self.endpoints.clone(), format: self.upload_format.clone(), density: self.density, two_color: self.two_color }
+    }
+
+    /// the bytes sent to this printer for `img`, two planes for a two-color printer
+    pub fn pack(&self, img: &GrayImage) -> Vec<u8> {
+        let (width, height) = img.dimensions();
+        if self.two_color {
+            get_two_color_data(img, height as usize, width as usize, &self.packing)
+        } else {
+            get_bitmap_data_with(img, height as usize, width as usize, &self.packing)
+        }
+    }
+
+    /// a copy of `preset` called `name` for the printer at `host`
+    pub fn from_preset(preset: &PrinterProfile, name: &str, host: &str) -> PrinterProfile {
+        PrinterProfile { name: name.to_string(), host: host.to_string(), ..preset.clone() }
+    }
+
+    /// convert a length in dots of this printer to millimeters
+    pub fn px_to_mm(&self, px: u32) -> f32 {
+        px as f32 * 25.4 / self.dpi.max(1) as f32
+    }
+}
+
+/// profiles of known printers, shipped with the app
+const PRESETS_JSON: &str = include_str!("printer_presets.json");
+
+/// the shipped presets, the host of a preset is the default host
+pub fn presets() -> Vec<PrinterProfile> {
+    serde_json::from_str(PRESETS_JSON).expect("valid printer presets")
+}
+
+/// the shipped preset called `name`
+pub fn preset(name: &str) -> Option<PrinterProfile> {
+    presets().into_iter().find(|p| p.name == name)
+}
@@ -0,0 +1,1584 @@
+//! Rendering of text and images into the label bitmap and the bit packing for the printer.
+
+use image::{DynamicImage, GenericImageView, ImageBuffer, Luma, imageops::{self, BiLevel, FilterType, dither}};
+use image::error::{DecodingError, ImageError};
+use image::{GrayImage, ImageFormat, ImageReader, Limits, RgbImage, RgbaImage};
+use jpeg_decoder::PixelFormat;
+use resvg::{tiny_skia, usvg};
+use rusttype::{Font, PositionedGlyph, Scale, VMetrics};
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::io::BufReader;
+use std::path::Path;
+
+use crate::error::LabelError;
+use crate::glyph_cache::glyph_cache;
+
+/// x position of the first glyph
+pub const TEXT_START_X: f32 = 10.0;
+/// default baseline of the text, measured from the top
+pub const TEXT_BASELINE: f32 = 71.0;
+
+/// smallest font size in pixels offered in the ui
+pub const MIN_FONT_SIZE: i32 = 4;
+/// largest font size in pixels offered in the ui
+pub const MAX_FONT_SIZE: i32 = 200;
+/// pixels one key press changes the font size
+pub const FONT_SIZE_STEP: i32 = 2;
+
+/// `size` changed by `steps` times `FONT_SIZE_STEP`, kept within the offered sizes
+pub fn nudge_font_size(size: i32, steps: i32) -> i32 {
+    size.saturating_add(steps.saturating_mul(FONT_SIZE_STEP)).clamp(MIN_FONT_SIZE, MAX_FONT_SIZE)
+}
+
+/// horizontal placement of the glyphs
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum Pitch {
+    /// natural advance of the font
+    #[default]
+    Proportional,
+    /// every glyph gets a cell as wide as the widest glyph of the text
+    Widest,
+    /// every glyph gets a cell of the given width in pixels
+    Cell(f32),
+}
+
+/// filter for scaling images to the label height. lanczos keeps photos sharp but rings
+/// around hard edges, nearest keeps pixel art and logos crisp
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum ResizeFilter {
+    Nearest,
+    Triangle,
+    Gaussian,
+    #[default]
+    Lanczos3,
+}
+
+impl ResizeFilter {
+    pub const ALL: [ResizeFilter; 4] = [ResizeFilter::Lanczos3, ResizeFilter::Gaussian, ResizeFilter::Triangle, ResizeFilter::Nearest];
+
+    /// name in the ui
+    pub fn name(self) -> &'static str {
+        match self {
+            ResizeFilter::Nearest => "nearest",
+            ResizeFilter::Triangle => "triangle",
+            ResizeFilter::Gaussian => "gaussian",
+            ResizeFilter::Lanczos3 => "lanczos3",
+        }
+    }
+
+    /// the filter with the `name`, the default for an unknown one
+    pub fn from_name(name: &str) -> ResizeFilter {
+        ResizeFilter::ALL.into_iter().find(|filter| filter.name() == name).unwrap_or_default()
+    }
+
+    pub fn filter_type(self) -> FilterType {
+        match self {
+            ResizeFilter::Nearest => FilterType::Nearest,
+            ResizeFilter::Triangle => FilterType::Triangle,
+            ResizeFilter::Gaussian => FilterType::Gaussian,
+            ResizeFilter::Lanczos3 => FilterType::Lanczos3,
+        }
+    }
+}
+
+/// error diffusion used to turn gray tones into black and white dots
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Dither {
+    /// floyd-steinberg of the image crate, every row from left to right
+    #[default]
+    FloydSteinberg,
+    /// floyd-steinberg with the direction alternating per row, avoids the diagonal
+    /// drift of the error in smooth gradients
+    Serpentine,
+}
+
+impl Dither {
+    pub const ALL: [Dither; 2] = [Dither::FloydSteinberg, Dither::Serpentine];
+
+    /// name in the ui
+    pub fn name(self) -> &'static str {
+        match self {
+            Dither::FloydSteinberg => "floyd-steinberg",
+            Dither::Serpentine => "serpentine",
+        }
+    }
+
+    /// the dither with the `name`, the default for an unknown one
+    pub fn from_name(name: &str) -> Dither {
+        Dither::ALL.into_iter().find(|dither| dither.name() == name).unwrap_or_default()
+    }
+}
+
+/// settings for rendering text
+#[derive(Debug, Clone, PartialEq)]
+pub struct TextOptions {
+    pub pitch: Pitch,
+    /// font size in pixels, ignored if `fit_lines` is set
+    pub size: f32,
+    /// if not 0, the size is chosen so this many lines fill the label height
+    /// and the widest line still fits the width
+    pub fit_lines: usize,
+    /// glyph coverage (0..1) above which a pixel becomes black, lower values give
+    /// heavier strokes, higher values thinner ones
+    pub threshold: f32,
+    /// round the baseline and every glyph origin to whole pixels, crisper at small
+    /// sizes but the spacing gets uneven at large ones
+    pub snap_to_pixels: bool,
+    /// factor for the distance of two lines, 1.0 is the line height of the font
+    pub line_spacing: f32,
+    /// what happens to text wider than the label, applied by `fit_text_to_width`
+    pub overflow: Overflow,
+    /// baseline of the first line from the top, ignored if `fit_lines` is set
+    pub baseline: f32,
+}
+
+impl Default for TextOptions {
+    fn default() -> Self {
+        TextOptions { pitch: Pitch::Proportional, size: 96.0, fit_lines: 0, threshold: 0.5, snap_to_pixels: false, line_spacing: 1.0, overflow: Overflow::default(), baseline: TEXT_BASELINE }
+    }
+}
+
+/// orientation of an image on the tape
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Orientation {
+    /// as the image is
+    #[default]
+    Upright,
+    /// the one which fills the label best, see `best_orientation`
+    Auto,
+    /// turned by 90° counter-clockwise, the top of the image is at the start of the tape
+    Rotated,
+}
+
+impl Orientation {
+    pub const ALL: [Orientation; 3] = [Orientation::Upright, Orientation::Auto, Orientation::Rotated];
+
+    /// name in the ui
+    pub fn name(self) -> &'static str {
+        match self {
+            Orientation::Auto => "auto",
+            Orientation::Upright => "upright",
+            Orientation::Rotated => "rotated",
+        }
+    }
+
+    /// the orientation with the `name`, the default for an unknown one
+    pub fn from_name(name: &str) -> Orientation {
+        Orientation::ALL.into_iter().find(|orientation| orientation.name() == name).unwrap_or_default()
+    }
+}
+
+/// scale factors of a `width` x `height` image fitted into `head` rows and at most `length`
+/// columns of tape, upright and rotated
+pub fn orientation_scales(width: u32, height: u32, head: u32, length: u32) -> (f32, f32) {
+    let scale = |along: u32, across: u32| (head as f32 / across.max(1) as f32).min(length as f32 / along.max(1) as f32);
+    (scale(width, height), scale(height, width))
+}
+
+/// `Upright` or `Rotated`, whichever shows a `width` x `height` image larger on a head of
+/// `head` rows and at most `length` columns, so the least of the label stays blank. a tall
+/// image is turned, upright wins a tie, so square images are not turned
+pub fn best_orientation(width: u32, height: u32, head: u32, length: u32) -> Orientation {
+    let (upright, rotated) = orientation_scales(width, height, head, length);
+    if rotated > upright { Orientation::Rotated } else { Orientation::Upright }
+}
+
+/// what happens to text wider than the label
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Overflow {
+    /// draw it anyway, the end is cut off
+    Clip,
+    /// reduce the font size until the widest line fits
+    #[default]
+    Shrink,
+    /// break the lines between the words
+    Wrap,
+    /// refuse the label
+    Error,
+}
+
+impl Overflow {
+    pub const ALL: [Overflow; 4] = [Overflow::Shrink, Overflow::Wrap, Overflow::Clip, Overflow::Error];
+
+    /// name in the ui and the config
+    pub fn name(self) -> &'static str {
+        match self {
+            Overflow::Clip => "clip",
+            Overflow::Shrink => "shrink",
+            Overflow::Wrap => "wrap",
+            Overflow::Error => "error",
+        }
+    }
+
+    /// the policy with the `name`, the default for an unknown one
+    pub fn from_name(name: &str) -> Overflow {
+        Overflow::ALL.into_iter().find(|overflow| overflow.name() == name).unwrap_or_default()
+    }
+}
+
+/// text and options to draw after the overflow policy, see `fit_text_to_width`
+#[derive(Debug, Clone, PartialEq)]
+pub struct FittedText {
+    pub text: String,
+    pub options: TextOptions,
+    /// the policy which was applied, `None` if the text fit as it was
+    pub applied: Option<Overflow>,
+}
+
+/// render `text` with the font at `font_path` into a white `width` x `height` image.
+/// `used_len` is set to the number of columns the text covers. a missing or invalid
+/// font is an error, there is no built in fallback path
+pub fn create_image_with_text(width: usize, height: usize, text: &str, font_path: &str, options: &TextOptions, used_len: &mut usize) -> Result<ImageBuffer<Luma<u8>, Vec<u8>>, LabelError> {
+    // create white image
+    let mut img = ImageBuffer::from_pixel(width as u32, height as u32, Luma([255u8]));
+    let font = load_font(font_path)?;
+
+    let fitted = fit_text_to_width(&font, text, width as f32 - TEXT_START_X, height as f32, options)?;
+    *used_len = draw_text_cached(&mut img, &font, font_path, &fitted.text, TEXT_START_X, &fitted.options, false);
+    Ok(img)
+}
+
+/// like `create_image_with_text`, but also returns the text with anti-aliased edges for a
+/// smooth preview. the first image is the one to print
+pub fn create_images_with_text(width: usize, height: usize, text: &str, font_path: &str, options: &TextOptions, used_len: &mut usize) -> Result<(GrayImage, GrayImage), LabelError> {
+    let font = load_font(font_path)?;
+    let fitted = fit_text_to_width(&font, text, width as f32 - TEXT_START_X, height as f32, options)?;
+    let mut img = ImageBuffer::from_pixel(width as u32, height as u32, Luma([255u8]));
+    *used_len = draw_text_cached(&mut img, &font, font_path, &fitted.text, TEXT_START_X, &fitted.options, false);
+    let mut preview = ImageBuffer::from_pixel(width as u32, height as u32, Luma([255u8]));
+    draw_text_cached(&mut preview, &font, font_path, &fitted.text, TEXT_START_X, &fitted.options, true);
+    Ok((img, preview))
+}
+
+/// read and parse the font file at `font_path`
+fn load_font(font_path: &str) -> Result<Font<'static>, LabelError> {
+    let font_data = fs::read(font_path).map_err(|e| LabelError::Font(format!("Can't read font file {}: {}", font_path, e)))?;
+    Font::try_from_vec(font_data).ok_or_else(|| LabelError::Font(format!("Invalid font file: {}", font_path)))
+}
+
+/// number of pixels of `img` which would be printed
+pub fn printed_pixels(img: &ImageBuffer<Luma<u8>, Vec<u8>>) -> usize {
+    img.pixels().filter(|Luma([val])| *val < 128).count()
+}
+
+/// text rendered by `self_test`
+pub const SELF_TEST_TEXT: &str = "Label 123";
+
+/// render `SELF_TEST_TEXT` with the font at `font_path` and check that pixels get printed,
+/// an empty image points to a broken font stack. returns the printed pixels
+pub fn self_test(font_path: &str) -> Result<usize, LabelError> {
+    let font_data = fs::read(font_path)?;
+    let font = Font::try_from_vec(font_data).ok_or_else(|| LabelError::Font(format!("Invalid font file: {}", font_path)))?;
+    let mut img = ImageBuffer::from_pixel(400, 96, Luma([255u8]));
+    draw_text(&mut img, &font, SELF_TEST_TEXT, TEXT_START_X, &TextOptions { fit_lines: 1, ..TextOptions::default() });
+    match printed_pixels(&img) {
+        0 => Err(LabelError::Font(format!("Rendering '{}' with {} gave an empty image", SELF_TEST_TEXT, font_path))),
+        pixels => Ok(pixels),
+    }
+}
+
+/// make pasted text printable: line endings become `\n`, tabs a space and all other
+/// control characters are removed
+pub fn sanitize_label_text(text: &str) -> String {
+    text.replace("\r\n", "\n")
+        .replace('\r', "\n")
+        .chars()
+        .filter_map(|c| match c {
+            '\n' => Some('\n'),
+            '\t' => Some(' '),
+            c if c.is_control() => None,
+            c => Some(c),
+        })
+        .collect()
+}
+
+/// font size at which `lines` lines with `spacing` times the line height between their
+/// baselines fill `height`, `v_metrics` are the metrics at size 1
+pub fn fit_scale_to_lines(v_metrics: VMetrics, height: f32, lines: usize, spacing: f32) -> f32 {
+    let line_height = v_metrics.ascent - v_metrics.descent;
+    if lines == 0 || line_height <= 0.0 {
+        return height;
+    }
+    // the last line only needs its own height
+    height / (line_height * (1.0 + (lines - 1) as f32 * spacing.max(0.0)))
+}
+
+/// size of `text` with the natural advances at `scale`, without drawing it. returns the
+/// width from the pen start to the right edge of the widest line (the ink, or the advance of
+/// trailing blanks), the height of all lines and the baseline of the first line from the top
+pub fn measure_text(text: &str, font: &Font, scale: Scale) -> (f32, f32, f32) {
+    let v_metrics = font.v_metrics(scale);
+    let width = text
+        .lines()
+        .map(|line| {
+            font.layout(line, scale, rusttype::point(0.0, 0.0))
+                .map(|glyph| {
+                    let x = glyph.position().x;
+                    let glyph = glyph.unpositioned();
+                    match glyph.exact_bounding_box() {
+                        Some(bb) => x + bb.max.x,
+                        None => x + glyph.h_metrics().advance_width,
+                    }
+                })
+                .fold(0.0, f32::max)
+        })
+        .fold(0.0, f32::max);
+    let lines = text.lines().count().max(1);
+    let line_height = v_metrics.ascent - v_metrics.descent;
+    let height = line_height + (lines - 1) as f32 * (line_height + v_metrics.line_gap);
+    (width, height, v_metrics.ascent)
+}
+
+/// font size at which the widest line of `text` fills `width`
+pub fn fit_scale_to_width(font: &Font, text: &str, width: f32) -> f32 {
+    let (widest, _, _) = measure_text(text, font, Scale::uniform(1.0));
+    if widest <= 0.0 {
+        return f32::MAX;
+    }
+    width / widest
+}
+
+/// best fit in both directions: `lines` lines fill `height` unless the text gets wider than `width`
+pub fn fit_scale(font: &Font, text: &str, width: f32, height: f32, lines: usize, spacing: f32) -> f32 {
+    let vertical = fit_scale_to_lines(font.v_metrics(Scale::uniform(1.0)), height, lines, spacing);
+    vertical.min(fit_scale_to_width(font, text, width))
+}
+
+/// width of the cells of a fixed `pitch` for all lines of `text`, `None` for a proportional pitch
+fn cell_width(font: &Font, text: &str, scale: Scale, pitch: Pitch) -> Option<f32> {
+    match pitch {
+        Pitch::Proportional => None,
+        Pitch::Widest => Some(
+            text.chars()
+                .filter(|c| *c != '\n')
+                .map(|c| font.glyph(c).scaled(scale).h_metrics().advance_width)
+                .fold(0.0, f32::max),
+        ),
+        Pitch::Cell(width) => Some(width),
+    }
+}
+
+/// position the glyphs of one line, either with the font advances or on a grid of
+/// `cell_width`, see `cell_width`
+fn layout_line<'a>(font: &'a Font, line: &str, scale: Scale, start_x: f32, baseline: f32, cell_width: Option<f32>) -> Vec<PositionedGlyph<'a>> {
+    let Some(cell_width) = cell_width else {
+        return font.layout(line, scale, rusttype::point(start_x, baseline)).collect();
+    };
+    line
+        .chars()
+        .enumerate()
+        .map(|(i, c)| {
+            let glyph = font.glyph(c).scaled(scale);
+            // center the glyph in its cell
+            let x = start_x + i as f32 * cell_width + (cell_width - glyph.h_metrics().advance_width) / 2.0;
+            glyph.positioned(rusttype::point(x, baseline))
+        })
+        .collect()
+}
+
+/// move every glyph to the nearest whole pixel position
+pub fn snap_glyphs(glyphs: Vec<PositionedGlyph<'_>>) -> Vec<PositionedGlyph<'_>> {
+    glyphs
+        .into_iter()
+        .map(|glyph| {
+            let position = glyph.position();
+            glyph.into_unpositioned().positioned(rusttype::point(position.x.round(), position.y.round()))
+        })
+        .collect()
+}
+
+/// draw `text` in black onto `img`, the first glyph starts at `start_x`, every `\n` starts a new line.
+/// returns the rightmost blackened column + 1, for a fixed pitch the end of the last cell
+pub fn draw_text(img: &mut ImageBuffer<Luma<u8>, Vec<u8>>, font: &Font, text: &str, start_x: f32, options: &TextOptions) -> usize {
+    draw_text_with(img, font, text, start_x, options, false)
+}
+
+/// like `draw_text`, but the glyphs keep their anti-aliased edges as gray, for labels
+/// which are dithered as a whole after the composition
+pub fn draw_text_gray(img: &mut ImageBuffer<Luma<u8>, Vec<u8>>, font: &Font, text: &str, start_x: f32, options: &TextOptions) -> usize {
+    draw_text_with(img, font, text, start_x, options, true)
+}
+
+/// font size, first baseline and distance of the baselines of `text` in a `width` x `height`
+/// area. a fitted text starts at the top, otherwise on the fixed baseline
+fn vertical_layout(font: &Font, text: &str, width: f32, height: f32, options: &TextOptions) -> (f32, f32, f32) {
+    let spacing = options.line_spacing.max(0.0);
+    if options.fit_lines > 0 {
+        let size = fit_scale(font, text, width, height, options.fit_lines, spacing);
+        let v_metrics = font.v_metrics(Scale::uniform(size));
+        (size, v_metrics.ascent, (v_metrics.ascent - v_metrics.descent) * spacing)
+    } else {
+        let v_metrics = font.v_metrics(Scale::uniform(options.size));
+        (options.size, options.baseline, (v_metrics.ascent - v_metrics.descent + v_metrics.line_gap) * spacing)
+    }
+}
+
+/// pixels the lines of `text` reach below `height`, 0 or less if they fit
+pub fn text_overflow(font: &Font, text: &str, width: f32, height: f32, options: &TextOptions) -> f32 {
+    let (size, first_baseline, line_advance) = vertical_layout(font, text, width, height, options);
+    let lines = text.lines().count().max(1);
+    let bottom = first_baseline + (lines - 1) as f32 * line_advance - font.v_metrics(Scale::uniform(size)).descent;
+    bottom - height
+}
+
+/// width of the widest line of `text` at `size` on cells of `cell_width`, see `cell_width`
+fn text_width(font: &Font, text: &str, size: f32, cell_width: Option<f32>) -> f32 {
+    let scale = Scale::uniform(size);
+    text.lines()
+        .map(|line| match cell_width {
+            None => measure_text(line, font, scale).0,
+            Some(cell_width) => cell_width * line.chars().count() as f32,
+        })
+        .fold(0.0, f32::max)
+}
+
+/// apply the overflow policy of `options` to `text` for an area of `width` x `height` pixels.
+/// `Overflow::Error` gives an error for a text which doesn't fit, the others change text or options
+pub fn fit_text_to_width(font: &Font, text: &str, width: f32, height: f32, options: &TextOptions) -> Result<FittedText, LabelError> {
+    let (size, _, _) = vertical_layout(font, text, width, height, options);
+    let cells = cell_width(font, text, Scale::uniform(size), options.pitch);
+    let widest = text_width(font, text, size, cells);
+    let mut fitted = FittedText { text: text.to_string(), options: options.clone(), applied: None };
+    if widest <= width || width <= 0.0 {
+        return Ok(fitted);
+    }
+    fitted.applied = Some(options.overflow);
+    match options.overflow {
+        Overflow::Clip => {}
+        Overflow::Shrink => {
+            let ratio = width / widest;
+            fitted.options.size = size * ratio;
+            fitted.options.fit_lines = 0;
+            if let Pitch::Cell(cell) = options.pitch {
+                fitted.options.pitch = Pitch::Cell(cell * ratio);
+            }
+        }
+        Overflow::Wrap => fitted.text = wrap_text(font, text, size, cells, width),
+        Overflow::Error => return Err(LabelError::InvalidInput(format!("The text is {:.0} px wide, only {:.0} px fit on the label", widest, width))),
+    }
+    Ok(fitted)
+}
+
+/// break the lines of `text` between words so each fits into `width` at `size`.
+/// a word wider than `width` is broken between its characters
+fn wrap_text(font: &Font, text: &str, size: f32, cell_width: Option<f32>, width: f32) -> String {
+    let fits = |line: &str| text_width(font, line, size, cell_width) <= width;
+    let mut wrapped = Vec::new();
+    for line in text.lines() {
+        let mut current = String::new();
+        for word in line.split_whitespace() {
+            let candidate = if current.is_empty() { word.to_string() } else { format!("{} {}", current, word) };
+            if fits(&candidate) {
+                current = candidate;
+                continue;
+            }
+            if !current.is_empty() {
+                wrapped.push(std::mem::take(&mut current));
+            }
+            for c in word.chars() {
+                current.push(c);
+                if current.chars().count() > 1 && !fits(&current) {
+                    current.pop();
+                    wrapped.push(std::mem::replace(&mut current, c.to_string()));
+                }
+            }
+        }
+        wrapped.push(current);
+    }
+    wrapped.join("\n")
+}
+
+/// glyphs of all lines of `text` in a `width` x `height` image, the first glyph starts at
+/// `start_x`. for a fixed pitch the end of the widest line of cells is returned too
+fn layout_text<'a>(font: &'a Font, text: &str, start_x: f32, width: f32, height: f32, options: &TextOptions) -> (Vec<PositionedGlyph<'a>>, Option<f32>) {
+    let (size, first_baseline, line_advance) = vertical_layout(font, text, width - start_x, height, options);
+    let scale = Scale::uniform(size);
+    let cells = cell_width(font, text, scale, options.pitch);
+    let mut all_glyphs = Vec::new();
+    let mut cells_end: Option<f32> = None;
+    for (line_no, line) in text.lines().enumerate() {
+        let mut baseline = first_baseline + line_no as f32 * line_advance;
+        if options.snap_to_pixels {
+            baseline = baseline.round();
+        }
+        let mut glyphs = layout_line(font, line, scale, start_x, baseline, cells);
+        if options.snap_to_pixels {
+            glyphs = snap_glyphs(glyphs);
+        }
+        if let Some(cell_width) = cells {
+            let end = start_x + cell_width * line.chars().count() as f32;
+            cells_end = Some(cells_end.map_or(end, |e| e.max(end)));
+        }
+        all_glyphs.extend(glyphs);
+    }
+    (all_glyphs, cells_end)
+}
+
+/// rows of black glyph pixels which don't fit on the label, see `vertical_clip`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct VerticalClip {
+    /// rows cut off above the top edge
+    pub above: u32,
+    /// rows cut off below the bottom edge
+    pub below: u32,
+}
+
+impl VerticalClip {
+    pub fn is_clipped(&self) -> bool {
+        self.above > 0 || self.below > 0
+    }
+}
+
+/// how far the black pixels of `text` reach beyond the top and bottom of a `height` rows
+/// label, e.g. accents of capitals pushed over the top by a low baseline
+pub fn vertical_clip(font: &Font, text: &str, start_x: f32, width: f32, height: u32, options: &TextOptions) -> VerticalClip {
+    let (glyphs, _) = layout_text(font, text, start_x, width, height as f32, options);
+    let mut clip = VerticalClip::default();
+    for glyph in glyphs {
+        if let Some(bb) = glyph.pixel_bounding_box() {
+            // only glyphs crossing an edge are rasterized
+            if bb.min.y >= 0 && bb.max.y <= height as i32 {
+                continue;
+            }
+            glyph.draw(|_, y, v| {
+                let py = bb.min.y + y as i32;
+                if v > options.threshold {
+                    if py < 0 {
+                        clip.above = clip.above.max((-py) as u32);
+                    } else if py >= height as i32 {
+                        clip.below = clip.below.max((py - height as i32 + 1) as u32);
+                    }
+                }
+            });
+        }
+    }
+    clip
+}
+
+fn draw_text_with(img: &mut ImageBuffer<Luma<u8>, Vec<u8>>, font: &Font, text: &str, start_x: f32, options: &TextOptions, gray: bool) -> usize {
+    draw_glyphs(img, font, None, text, start_x, options, gray)
+}
+
+/// like `draw_text` (`gray` false) or `draw_text_gray`, the glyphs come from the glyph cache
+/// under the key `font_path`. positions are rounded to `SUBPIXEL_STEPS` of a pixel
+pub fn draw_text_cached(img: &mut ImageBuffer<Luma<u8>, Vec<u8>>, font: &Font, font_path: &str, text: &str, start_x: f32, options: &TextOptions, gray: bool) -> usize {
+    draw_glyphs(img, font, Some(font_path), text, start_x, options, gray)
+}
+
+fn draw_glyphs(img: &mut ImageBuffer<Luma<u8>, Vec<u8>>, font: &Font, font_path: Option<&str>, text: &str, start_x: f32, options: &TextOptions, gray: bool) -> usize {
+    let (width, height) = img.dimensions();
+    let mut used_len = 0;
+
+    let (glyphs, cells_end) = layout_text(font, text, start_x, width as f32, height as f32, options);
+
+    // draw the text
+    let mut plot = |px: i32, py: i32, v: f32| {
+        if px >= 0 && px < width as i32 && py >= 0 && py < height as i32 {
+            let pixel: &mut Luma<u8> = img.get_pixel_mut(px as u32, py as u32);
+            if gray {
+                // darken, overlapping glyphs and the background stay visible
+                pixel[0] = pixel[0].min(((1.0 - v.clamp(0.0, 1.0)) * 255.0).round() as u8);
+            }
+            if v > options.threshold {
+                if !gray {
+                    pixel[0] = 0; // black
+                }
+                if px as usize > used_len {
+                    used_len = px as usize;
+                }
+            }
+        }
+    };
+    match font_path {
+        Some(font_path) => {
+            // the lock is released before drawing, parallel batches share the cache
+            let placed: Vec<_> = {
+                let mut cache = glyph_cache();
+                glyphs.iter().filter_map(|glyph| cache.get(font_path, glyph)).collect()
+            };
+            for (bitmap, x0, y0) in placed {
+                bitmap.draw(|x, y, v| plot(x0 + bitmap.min_x + x as i32, y0 + bitmap.min_y + y as i32, v));
+            }
+        }
+        None => {
+            for glyph in glyphs {
+                if let Some(bb) = glyph.pixel_bounding_box() {
+                    glyph.draw(|x, y, v| plot(bb.min.x + x as i32, bb.min.y + y as i32, v));
+                }
+            }
+        }
+    }
+    if let Some(cells_end) = cells_end {
+        return (cells_end.ceil().max(0.0) as usize).min(width as usize);
+    }
+    used_len + 1
+}
+
+/// largest accepted width or height of a source image
+pub const MAX_SOURCE_DIMENSION: u32 = 20_000;
+
+/// bytes a decoder may allocate for a source image, a larger decode fails before it starts
+pub const MAX_SOURCE_ALLOC: u64 = 256 * 1024 * 1024;
+
+/// open an image file and reduce it right after decoding to at most `4 * height` rows,
+/// so a big photo doesn't stay in memory in full size while it is scaled and dithered.
+/// a jpeg is already reduced while decoding. files larger than `MAX_SOURCE_DIMENSION` or
+/// needing more than `MAX_SOURCE_ALLOC` bytes are refused before decoding
+pub fn load_source_image(path: &Path, height: u32) -> Result<DynamicImage, LabelError> {
+    if path.extension().is_some_and(|ext| ext.eq_ignore_ascii_case("svg")) {
+        return load_svg(path, height);
+    }
+    let max_height = height * 4;
+    let mut reader = ImageReader::open(path)?.with_guessed_format()?;
+    let reduced = match reader.format() {
+        Some(ImageFormat::Jpeg) => decode_jpeg_reduced(path, max_height)?,
+        _ => None,
+    };
+    let img = match reduced {
+        Some(img) => img,
+        None => {
+            let mut limits = Limits::default();
+            limits.max_image_width = Some(MAX_SOURCE_DIMENSION);
+            limits.max_image_height = Some(MAX_SOURCE_DIMENSION);
+            limits.max_alloc = Some(MAX_SOURCE_ALLOC);
+            reader.limits(limits);
+            reader.decode()?
+        }
+    };
+    if img.height() > max_height {
+        let (w, h) = img.dimensions();
+        eprintln!("Source image {}x{} reduced to height {}", w, h, max_height);
+        // thumbnail keeps the aspect ratio, the large decode is dropped right here
+        return Ok(img.thumbnail(u32::MAX, max_height));
+    }
+    Ok(img)
+}
+
+/// decode a jpeg at 1/2, 1/4 or 1/8 of its size if that keeps at least `min_height` rows,
+/// the decoder scales the blocks so the full size is never in memory. `None` if it can't
+/// be reduced or has a pixel format left to `image`
+fn decode_jpeg_reduced(path: &Path, min_height: u32) -> Result<Option<DynamicImage>, LabelError> {
+    let jpeg_error = |e: jpeg_decoder::Error| LabelError::Image(ImageError::Decoding(DecodingError::new(ImageFormat::Jpeg.into(), e)));
+    let mut decoder = jpeg_decoder::Decoder::new(BufReader::new(fs::File::open(path)?));
+    decoder.read_info().map_err(jpeg_error)?;
+    let Some(info) = decoder.info() else { return Ok(None) };
+    let (width, height) = (info.width as u32, info.height as u32);
+    // the limits are reported by `image`
+    if height < min_height * 2 || width > MAX_SOURCE_DIMENSION || height > MAX_SOURCE_DIMENSION || !matches!(info.pixel_format, PixelFormat::L8 | PixelFormat::RGB24) {
+        return Ok(None);
+    }
+    let min_width = (width as u64 * min_height as u64).div_ceil(height as u64) as u32;
+    let (width, height) = decoder.scale(min_width.min(u16::MAX as u32) as u16, min_height.min(u16::MAX as u32) as u16).map_err(jpeg_error)?;
+    decoder.set_max_decoding_buffer_size(MAX_SOURCE_ALLOC as usize);
+    let pixels = decoder.decode().map_err(jpeg_error)?;
+    let (width, height) = (width as u32, height as u32);
+    let img = match info.pixel_format {
+        PixelFormat::L8 => GrayImage::from_raw(width, height, pixels).map(DynamicImage::ImageLuma8),
+        _ => RgbImage::from_raw(width, height, pixels).map(DynamicImage::ImageRgb8),
+    };
+    Ok(img)
+}
+
+/// rasterize an svg file directly at `height` rows on a white background, so vector
+/// logos stay sharp instead of being scaled from a raster image
+pub fn load_svg(path: &Path, height: u32) -> Result<DynamicImage, LabelError> {
+    let data = fs::read(path)?;
+    let tree = usvg::Tree::from_data(&data, &usvg::Options::default()).map_err(|e| LabelError::InvalidInput(format!("Invalid SVG file: {}", e)))?;
+    let size = tree.size();
+    let scale = height as f32 / size.height();
+    let width = (size.width() * scale).ceil() as u32;
+    if width == 0 || width > MAX_SOURCE_DIMENSION {
+        return Err(LabelError::InvalidInput(format!("SVG width of {} pixels at height {} is not supported", width, height)));
+    }
+    let mut pixmap = tiny_skia::Pixmap::new(width, height).ok_or_else(|| LabelError::InvalidInput("SVG has no size".to_string()))?;
+    pixmap.fill(tiny_skia::Color::WHITE);
+    resvg::render(&tree, tiny_skia::Transform::from_scale(scale, scale), &mut pixmap.as_mut());
+    // the pixmap is premultiplied, but opaque because of the white background
+    let img = RgbaImage::from_raw(width, height, pixmap.take()).ok_or_else(|| LabelError::InvalidInput("SVG could not be rasterized".to_string()))?;
+    Ok(DynamicImage::ImageRgba8(img))
+}
+
+/// scale `img` to the label height, dither it and place it on a white `width` x `height` canvas.
+/// returns the canvas and the used length
+pub fn render_image(img: &DynamicImage, width: u32, height: u32, x_offset: i32, y_offset: i32) -> (ImageBuffer<Luma<u8>, Vec<u8>>, u32) {
+    let dithered = prepare_image(img, height);
+
+    // create final image with white background
+    let mut final_img = ImageBuffer::from_pixel(width, height, Luma([255u8]));
+    let used_len = place_image(&mut final_img, &dithered, x_offset, y_offset);
+    (final_img, used_len)
+}
+
+/// tonal correction of an image before it is dithered
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Levels {
+    /// gray value which becomes black, everything darker too
+    pub black: u8,
+    /// gray value which becomes white, everything brighter too
+    pub white: u8,
+    /// gamma of the midtones, above 1 brightens and below 1 darkens them
+    pub midpoint: f32,
+}
+
+impl Default for Levels {
+    fn default() -> Self {
+        Levels { black: 0, white: 255, midpoint: 1.0 }
+    }
+}
+
+impl Levels {
+    /// output value for the gray value `value`
+    pub fn map(&self, value: u8) -> u8 {
+        if self.white <= self.black {
+            // no range left, a plain threshold
+            return if value > self.black { 255 } else { 0 };
+        }
+        let t = (value as f32 - self.black as f32) / (self.white as f32 - self.black as f32);
+        let gamma = if self.midpoint > 0.0 { self.midpoint } else { 1.0 };
+        (t.clamp(0.0, 1.0).powf(1.0 / gamma) * 255.0).round() as u8
+    }
+}
+
+/// share of the darkest and of the brightest pixels ignored by `auto_levels`, so a few
+/// outliers don't keep the range from stretching
+pub const AUTO_LEVELS_CLIP: f32 = 0.005;
+
+/// levels which stretch the tones of `img` to the full range, the darkest and brightest
+/// `clip` share of the pixels is clipped. the default levels for a blank image
+pub fn auto_levels_for(img: &ImageBuffer<Luma<u8>, Vec<u8>>, clip: f32) -> Levels {
+    let histogram = luma_histogram(img);
+    let total = img.pixels().len();
+    if total == 0 {
+        return Levels::default();
+    }
+    let skip = (total as f32 * clip.clamp(0.0, 0.5)) as usize;
+    let black = histogram_limit(&histogram, 0..256, skip);
+    let white = histogram_limit(&histogram, (0..256).rev(), skip);
+    if white <= black {
+        // a single tone, stretching would turn it into a threshold
+        return Levels::default();
+    }
+    Levels { black, white, midpoint: 1.0 }
+}
+
+/// first gray value of `values` after `skip` pixels, counted from one end of the histogram
+fn histogram_limit(histogram: &[usize; 256], mut values: impl Iterator<Item = usize>, skip: usize) -> u8 {
+    let mut seen = 0;
+    values.find(|&v| {
+        seen += histogram[v];
+        seen > skip
+    }).unwrap_or(0) as u8
+}
+
+/// number of pixels of every gray value
+pub fn luma_histogram(img: &ImageBuffer<Luma<u8>, Vec<u8>>) -> [usize; 256] {
+    let mut histogram = [0usize; 256];
+    for Luma([value]) in img.pixels() {
+        histogram[*value as usize] += 1;
+    }
+    histogram
+}
+
+/// stretch the tones of `img` to the full range, see `auto_levels_for`
+pub fn auto_levels(img: &mut ImageBuffer<Luma<u8>, Vec<u8>>) {
+    let levels = auto_levels_for(img, AUTO_LEVELS_CLIP);
+    apply_levels(img, &levels);
+}
+
+/// apply `levels` to every pixel
+pub fn apply_levels(img: &mut ImageBuffer<Luma<u8>, Vec<u8>>, levels: &Levels) {
+    if *levels == Levels::default() {
+        return;
+    }
+    let table: Vec<u8> = (0..=255).map(|v| levels.map(v)).collect();
+    for pixel in img.pixels_mut() {
+        pixel[0] = table[pixel[0] as usize];
+    }
+}
+
+/// scale `img` proportionally to `height` and dither it to black and white
+pub fn prepare_image(img: &DynamicImage, height: u32) -> ImageBuffer<Luma<u8>, Vec<u8>> {
+    prepare_image_with_levels(img, height, &Levels::default())
+}
+
+/// like `prepare_image`, `levels` are applied before dithering
+pub fn prepare_image_with_levels(img: &DynamicImage, height: u32, levels: &Levels) -> ImageBuffer<Luma<u8>, Vec<u8>> {
+    let mut dithered = prepare_gray_image(img, height, levels, ResizeFilter::default());
+
+    // dithern
+    dither(&mut dithered, &BiLevel);
+    dithered
+}
+
+/// scale an image proportionally to `height` with `filter` and apply `levels`, without dithering
+pub fn prepare_gray_image(img: &DynamicImage, height: u32, levels: &Levels, filter: ResizeFilter) -> ImageBuffer<Luma<u8>, Vec<u8>> {
+    // scaler proportionally
+    let (orig_w, orig_h) = img.dimensions();
+    let scale = height as f32 / orig_h as f32;
+    let new_w = (orig_w as f32 * scale).round() as u32;
+
+    // scale image
+    let mut gray = img.resize_exact(new_w, height, filter.filter_type()).to_luma8();
+    apply_levels(&mut gray, levels);
+    gray
+}
+
+/// box an image is fitted into without distortion, see `resize_letterbox`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Letterbox {
+    pub width: u32,
+    /// gray value of the bars around the image
+    pub fill: u8,
+}
+
+/// scale `img` to fit into `width` x `height` keeping the aspect ratio and center it,
+/// the remaining area is filled with `fill`. an empty box gives an empty image
+pub fn resize_letterbox(img: &DynamicImage, width: u32, height: u32, fill: u8, filter: ResizeFilter) -> ImageBuffer<Luma<u8>, Vec<u8>> {
+    if width == 0 || height == 0 {
+        return ImageBuffer::from_pixel(width, height, Luma([fill]));
+    }
+    let (orig_w, orig_h) = img.dimensions();
+    let scale = (width as f32 / orig_w.max(1) as f32).min(height as f32 / orig_h.max(1) as f32);
+    let new_w = ((orig_w as f32 * scale).round() as u32).clamp(1, width);
+    let new_h = ((orig_h as f32 * scale).round() as u32).clamp(1, height);
+    let scaled = img.resize_exact(new_w, new_h, filter.filter_type()).to_luma8();
+    let mut boxed = ImageBuffer::from_pixel(width, height, Luma([fill]));
+    imageops::replace(&mut boxed, &scaled, (width.saturating_sub(new_w) / 2) as i64, (height.saturating_sub(new_h) / 2) as i64);
+    boxed
+}
+
+/// dither a composed grayscale label into black and white, the final stage of `compose_dithered`
+pub fn binarize(img: &mut ImageBuffer<Luma<u8>, Vec<u8>>) {
+    binarize_with(img, Dither::default());
+}
+
+/// like `binarize` with the error diffusion `method`
+pub fn binarize_with(img: &mut ImageBuffer<Luma<u8>, Vec<u8>>, method: Dither) {
+    match method {
+        Dither::FloydSteinberg => dither(img, &BiLevel),
+        Dither::Serpentine => dither_serpentine(img),
+    }
+}
+
+/// clean up a dithered black and white label: a pixel whose neighbours (8, fewer at the
+/// edges) all have the other color is flipped, stray dots and pinholes disappear while
+/// lines of one pixel stay. returns the number of flipped pixels
+pub fn despeckle(img: &mut ImageBuffer<Luma<u8>, Vec<u8>>) -> usize {
+    let source = img.clone();
+    let (width, height) = source.dimensions();
+    let black = |x: u32, y: u32| source.get_pixel(x, y)[0] < 128;
+    let mut flipped = 0;
+    for y in 0..height {
+        for x in 0..width {
+            let center = black(x, y);
+            let mut neighbours = 0;
+            let mut isolated = true;
+            for ny in y.saturating_sub(1)..=(y + 1).min(height - 1) {
+                for nx in x.saturating_sub(1)..=(x + 1).min(width - 1) {
+                    if (nx, ny) == (x, y) {
+                        continue;
+                    }
+                    neighbours += 1;
+                    if black(nx, ny) == center {
+                        isolated = false;
+                    }
+                }
+            }
+            if isolated && neighbours > 0 {
+                img.put_pixel(x, y, Luma([if center { 255 } else { 0 }]));
+                flipped += 1;
+            }
+        }
+    }
+    flipped
+}
+
+/// floyd-steinberg error diffusion, even rows from left to right and odd rows from right
+/// to left. the error of a pixel goes 7/16 ahead, 3/16 behind below, 5/16 below and 1/16 ahead below
+pub fn dither_serpentine(img: &mut ImageBuffer<Luma<u8>, Vec<u8>>) {
+    let (width, height) = img.dimensions();
+    let width = width as usize;
+    // error carried into the current and the next row, one extra cell at each end
+    let mut current = vec![0i32; width + 2];
+    let mut next = vec![0i32; width + 2];
+    for y in 0..height {
+        let forward = y % 2 == 0;
+        for step in 0..width {
+            let x = if forward { step } else { width - 1 - step };
+            let cell = x + 1;
+            let value = img.get_pixel(x as u32, y)[0] as i32 + current[cell] / 16;
+            let output = if value < 128 { 0 } else { 255 };
+            img.put_pixel(x as u32, y, Luma([output as u8]));
+            let error = value - output;
+            let (ahead, behind) = if forward { (cell + 1, cell - 1) } else { (cell - 1, cell + 1) };
+            current[ahead] += error * 7;
+            next[behind] += error * 3;
+            next[cell] += error * 5;
+            next[ahead] += error;
+        }
+        std::mem::swap(&mut current, &mut next);
+        next.fill(0);
+    }
+}
+
+/// like `place_image`, but the darker pixel of canvas and image is kept, so the image
+/// doesn't cover what is already drawn
+pub fn darken_image(canvas: &mut ImageBuffer<Luma<u8>, Vec<u8>>, img: &ImageBuffer<Luma<u8>, Vec<u8>>, x_offset: i32, y_offset: i32) -> u32 {
+    let (canvas_w, canvas_h) = canvas.dimensions();
+    let img_w = img.width();
+    for (x, y, Luma([val])) in img.enumerate_pixels() {
+        let px = x_offset as i64 + x as i64;
+        let py = y_offset as i64 + y as i64;
+        if px >= 0 && px < canvas_w as i64 && py >= 0 && py < canvas_h as i64 {
+            let pixel = canvas.get_pixel_mut(px as u32, py as u32);
+            pixel[0] = pixel[0].min(*val);
+        }
+    }
+    let x = (x_offset as i64).clamp(-(img_w as i64), canvas_w as i64);
+    (x + img_w as i64).clamp(0, canvas_w as i64) as u32
+}
+
+/// copy `img` onto `canvas` at the given offset, clipping everything outside of the canvas.
+/// returns the used length (rightmost covered column + 1)
+pub fn place_image(canvas: &mut ImageBuffer<Luma<u8>, Vec<u8>>, img: &ImageBuffer<Luma<u8>, Vec<u8>>, x_offset: i32, y_offset: i32) -> u32 {
+    let (canvas_w, canvas_h) = canvas.dimensions();
+    let (img_w, img_h) = img.dimensions();
+    // clamp the offsets so the image is at most completely off-canvas
+    let x = (x_offset as i64).clamp(-(img_w as i64), canvas_w as i64);
+    let y = (y_offset as i64).clamp(-(img_h as i64), canvas_h as i64);
+    imageops::replace(canvas, img, x, y);
+    (x + img_w as i64).clamp(0, canvas_w as i64) as u32
+}
+
+/// cut an image taller than the print head into `band_height` high strips from top to bottom.
+/// the last strip is filled up with white
+pub fn split_into_bands(img: &ImageBuffer<Luma<u8>, Vec<u8>>, band_height: u32) -> Vec<ImageBuffer<Luma<u8>, Vec<u8>>> {
+    let (width, height) = img.dimensions();
+    let band_height = band_height.max(1);
+    (0..height.div_ceil(band_height))
+        .map(|band| {
+            let mut strip = ImageBuffer::from_pixel(width, band_height, Luma([255u8]));
+            let top = band * band_height;
+            let rows = band_height.min(height - top);
+            imageops::replace(&mut strip, &imageops::crop_imm(img, 0, top, width, rows).to_image(), 0, 0);
+            strip
+        })
+        .collect()
+}
+
+/// pack the image column by column (bottom to top) into bits, 1 = black, MSB first
+pub fn get_bitmap_data(img: &ImageBuffer<Luma<u8>, Vec<u8>>, height: usize, width: usize) -> Vec<u8> { 
+    get_bitmap_data_with(img, height, width, &PackingOptions::default())
+}
+
+/// how the pixels are packed into bytes for the printer
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct PackingOptions {
+    /// start every scan line (one column of `height` pixels) on a new byte, the unused
+    /// bits of the last byte stay 0. continuous if false, the next line starts in the
+    /// middle of the byte. raster protocols like ESC/POS need the padding
+    pub pad_rows: bool,
+    /// fill every byte from the least significant bit instead of the most significant bit
+    pub lsb_first: bool,
+}
+
+/// gray value of red ink in a two-color label. it is dark enough to count as printed for
+/// trimming, and a one color printer prints it black
+pub const RED_INK: u8 = 64;
+
+/// split a two-color label into a black and a red black-and-white plane.
+/// values below `RED_INK / 2` are black, up to 127 red, the rest blank
+pub fn split_planes(img: &ImageBuffer<Luma<u8>, Vec<u8>>) -> (GrayImage, GrayImage) {
+    let plane = |printed: fn(u8) -> bool| GrayImage::from_fn(img.width(), img.height(), |x, y| Luma([if printed(img.get_pixel(x, y)[0]) { 0 } else { 255 }]));
+    (plane(|v| v < RED_INK / 2), plane(|v| (RED_INK / 2..128).contains(&v)))
+}
+
+/// pack a two-color label for the firmware: the black plane followed by the red plane,
+/// each packed like `get_bitmap_data_with`
+pub fn get_two_color_data(img: &ImageBuffer<Luma<u8>, Vec<u8>>, height: usize, width: usize, options: &PackingOptions) -> Vec<u8> {
+    let (black, red) = split_planes(img);
+    let mut data = get_bitmap_data_with(&black, height, width, options);
+    data.extend(get_bitmap_data_with(&red, height, width, options));
+    data
+}
+
+/// pack like `get_bitmap_data` with the given byte layout
+pub fn get_bitmap_data_with(img: &ImageBuffer<Luma<u8>, Vec<u8>>, height: usize, width: usize, options: &PackingOptions) -> Vec<u8> {
+    let capacity = if options.pad_rows { width * height.div_ceil(8) } else { (width * height).div_ceil(8) };
+    let mut packed: Vec<u8> = Vec::with_capacity(capacity);
+    let mut current_byte = 0u8;
+    let mut bit_pos = 0;
+    eprintln!("Image dimensions: WxH {}x{}", width, height);
+    for x in 0..width {
+        for y in 0..height {
+            let Luma([val]) = *img.get_pixel(x as u32, height as u32 - y as u32 - 1);
+            let bit = if val < 128 { 1 } else { 0 };
+            let shift = if options.lsb_first { bit_pos } else { 7 - bit_pos };
+            current_byte |= bit << shift;
+            bit_pos += 1;
+
+            if bit_pos == 8 {
+                packed.push(current_byte);
+                current_byte = 0;
+                bit_pos = 0;
+            }
+        }
+        if options.pad_rows && bit_pos > 0 {
+            packed.push(current_byte);
+            current_byte = 0;
+            bit_pos = 0;
+        }
+    }
+    // the tail of a continuous stream
+    if bit_pos > 0 {
+        packed.push(current_byte);
+    }
+    eprintln!("Bit-packed length: {} bytes", packed.len());
+    packed
+}
+
+/// rebuild the label from packed data, the inverse of `get_bitmap_data_with`. set bits
+/// become black, missing bits white. shows exactly what the printer gets
+pub fn unpack_bitmap_data(data: &[u8], height: usize, width: usize, options: &PackingOptions) -> ImageBuffer<Luma<u8>, Vec<u8>> {
+    let mut img = ImageBuffer::from_pixel(width as u32, height as u32, Luma([255u8]));
+    let line_bits = if options.pad_rows { height.div_ceil(8) * 8 } else { height };
+    for x in 0..width {
+        for y in 0..height {
+            let bit_index = x * line_bits + y;
+            let Some(byte) = data.get(bit_index / 8) else {
+                return img;
+            };
+            let bit_pos = bit_index % 8;
+            let shift = if options.lsb_first { bit_pos } else { 7 - bit_pos };
+            if byte >> shift & 1 == 1 {
+                img.put_pixel(x as u32, (height - y - 1) as u32, Luma([0u8]));
+            }
+        }
+    }
+    img
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+
+    /// a font of the system, tests which need one are skipped without it
+    pub(crate) const TEST_FONT: &str = "/usr/share/fonts/truetype/dejavu/DejaVuSans.ttf";
+
+    pub(crate) fn test_font() -> Option<Font<'static>> {
+        let font = fs::read(TEST_FONT).ok().and_then(Font::try_from_vec);
+        if font.is_none() {
+            eprintln!("{} not found, test skipped", TEST_FONT);
+        }
+        font
+    }
+
+    #[test]
+    fn an_image_partly_off_the_canvas_is_clipped() {
+        let img = ImageBuffer::from_pixel(20, 10, Luma([0u8]));
+        let mut canvas = ImageBuffer::from_pixel(50, 30, Luma([255u8]));
+        assert_eq!(place_image(&mut canvas, &img, -5, 25), 15);
+        assert_eq!(canvas.get_pixel(0, 25)[0], 0);
+        assert_eq!(canvas.get_pixel(14, 29)[0], 0);
+        assert_eq!(canvas.get_pixel(15, 25)[0], 255);
+        assert_eq!(canvas.get_pixel(0, 24)[0], 255);
+        // far outside offsets leave the canvas untouched
+        let mut canvas = ImageBuffer::from_pixel(50, 30, Luma([255u8]));
+        assert_eq!(place_image(&mut canvas, &img, i32::MIN, 0), 0);
+        assert_eq!(place_image(&mut canvas, &img, 0, i32::MAX), 20);
+        assert_eq!(place_image(&mut canvas, &img, i32::MAX, 0), 50);
+        assert!(canvas.pixels().all(|p| p[0] == 255));
+    }
+
+    #[test]
+    fn a_fixed_cell_aligns_digits_of_any_width() {
+        let Some(font) = test_font() else { return };
+        let scale = Scale::uniform(40.0);
+        let cells = cell_width(&font, "1111\n8888", scale, Pitch::Cell(30.0));
+        let ones = layout_line(&font, "1111", scale, 0.0, 40.0, cells);
+        let eights = layout_line(&font, "8888", scale, 0.0, 40.0, cells);
+        let center = |g: &PositionedGlyph| g.position().x + g.unpositioned().h_metrics().advance_width / 2.0;
+        for column in 0..4 {
+            assert!((center(&ones[column]) - (15.0 + 30.0 * column as f32)).abs() < 0.01);
+            assert!((center(&ones[column]) - center(&eights[column])).abs() < 0.01);
+        }
+        assert_eq!(cells, Some(30.0));
+    }
+
+    #[test]
+    fn widest_pitch_uses_one_cell_for_all_lines() {
+        let Some(font) = test_font() else { return };
+        let scale = Scale::uniform(40.0);
+        let w = font.glyph('W').scaled(scale).h_metrics().advance_width;
+        let cells = cell_width(&font, "il\nWW", scale, Pitch::Widest);
+        assert_eq!(cells, Some(w));
+        let center = |g: &PositionedGlyph| g.position().x + g.unpositioned().h_metrics().advance_width / 2.0;
+        let narrow = layout_line(&font, "il", scale, 0.0, 40.0, cells);
+        assert!((center(&narrow[1]) - center(&narrow[0]) - w).abs() < 0.01);
+    }
+
+    #[test]
+    fn the_size_for_lines_comes_from_the_line_metrics() {
+        let metrics = VMetrics { ascent: 0.8, descent: -0.2, line_gap: 0.0 };
+        assert!((fit_scale_to_lines(metrics, 96.0, 1, 1.0) - 96.0).abs() < 0.001);
+        assert!((fit_scale_to_lines(metrics, 96.0, 2, 1.0) - 48.0).abs() < 0.001);
+        // a wider line spacing leaves less for the lines
+        assert!((fit_scale_to_lines(metrics, 96.0, 2, 1.5) - 38.4).abs() < 0.001);
+        assert_eq!(fit_scale_to_lines(metrics, 96.0, 0, 1.0), 96.0);
+        assert_eq!(fit_scale_to_lines(VMetrics { ascent: 0.0, descent: 0.0, line_gap: 0.0 }, 96.0, 2, 1.0), 96.0);
+    }
+
+    #[test]
+    fn a_long_text_is_fitted_to_the_width_instead_of_the_lines() {
+        let Some(font) = test_font() else { return };
+        let short = fit_scale(&font, "Hi", 1000.0, 96.0, 1, 1.0);
+        assert!((short - fit_scale_to_lines(font.v_metrics(Scale::uniform(1.0)), 96.0, 1, 1.0)).abs() < 0.001);
+        let long = fit_scale(&font, "A rather long line of label text", 300.0, 96.0, 1, 1.0);
+        assert!(long < short);
+        assert!((long - fit_scale_to_width(&font, "A rather long line of label text", 300.0)).abs() < 0.001);
+    }
+
+    #[test]
+    fn a_large_source_image_is_reduced_and_an_oversized_one_refused() {
+        let dir = crate::fonts::tests::test_dir("large_source");
+        // at most 4 times the label height stays
+        let tall = dir.join("tall.png");
+        GrayImage::from_fn(40, 1000, |_, y| Luma([(y % 256) as u8])).save(&tall).unwrap();
+        let img = load_source_image(&tall, 96).unwrap();
+        assert_eq!(img.height(), 384);
+        assert!((15..=16).contains(&img.width()), "{}", img.width());
+        let small = dir.join("small.png");
+        GrayImage::new(40, 300).save(&small).unwrap();
+        assert_eq!(load_source_image(&small, 96).unwrap().dimensions(), (40, 300));
+
+        // a jpeg is decoded at a quarter of its size, then reduced the rest of the way
+        let photo = dir.join("photo.jpg");
+        GrayImage::from_fn(64, 1600, |x, _| Luma([(x * 4) as u8])).save(&photo).unwrap();
+        assert_eq!(decode_jpeg_reduced(&photo, 384).unwrap().unwrap().dimensions(), (16, 400));
+        assert_eq!(decode_jpeg_reduced(&photo, 1000).unwrap(), None);
+        assert_eq!(load_source_image(&photo, 96).unwrap().height(), 384);
+
+        // too wide, and too large to allocate although the dimensions are accepted
+        let wide = dir.join("wide.png");
+        GrayImage::new(MAX_SOURCE_DIMENSION + 1, 1).save(&wide).unwrap();
+        assert!(matches!(load_source_image(&wide, 96), Err(LabelError::Image(ImageError::Limits(_)))));
+        let side: i32 = 16_000;
+        let mut bmp = b"BM".to_vec();
+        for value in [54u32 + 3 * (side * side) as u32, 0, 54, 40] {
+            bmp.extend(value.to_le_bytes());
+        }
+        bmp.extend(side.to_le_bytes());
+        bmp.extend(side.to_le_bytes());
+        bmp.extend(1u16.to_le_bytes());
+        bmp.extend(24u16.to_le_bytes());
+        bmp.extend([0u8; 24]);
+        let huge = dir.join("huge.bmp");
+        fs::write(&huge, &bmp).unwrap();
+        assert!(matches!(load_source_image(&huge, 96), Err(LabelError::Image(ImageError::Limits(_)))));
+    }
+
+    #[test]
+    fn a_label_of_two_head_heights_splits_into_two_bands() {
+        let img = GrayImage::from_fn(40, 192, |_, y| Luma([if y < 96 { 0 } else { 255 }]));
+        let bands = split_into_bands(&img, 96);
+        assert_eq!(bands.len(), 2);
+        assert!(bands.iter().all(|band| band.dimensions() == (40, 96)));
+        assert!(bands[0].pixels().all(|p| p[0] == 0));
+        assert!(bands[1].pixels().all(|p| p[0] == 255));
+        // the rest of the last band is blank
+        let bands = split_into_bands(&GrayImage::from_pixel(40, 100, Luma([0])), 96);
+        assert_eq!(bands.len(), 2);
+        assert_eq!(bands[1].get_pixel(0, 3)[0], 0);
+        assert_eq!(bands[1].get_pixel(0, 4)[0], 255);
+    }
+
+    #[test]
+    fn levels_map_known_gray_values() {
+        let levels = Levels { black: 50, white: 200, midpoint: 1.0 };
+        let mapped: Vec<u8> = [0, 50, 125, 200, 250].into_iter().map(|v| levels.map(v)).collect();
+        assert_eq!(mapped, vec![0, 0, 128, 255, 255]);
+        // the midpoint brightens or darkens the middle, the ends stay
+        assert_eq!(Levels { midpoint: 2.0, ..levels }.map(125), 180);
+        assert_eq!(Levels { midpoint: 0.5, ..levels }.map(125), 64);
+        assert_eq!(Levels { midpoint: 2.0, ..levels }.map(200), 255);
+        // no range left is a threshold
+        let threshold = Levels { black: 100, white: 100, midpoint: 1.0 };
+        assert_eq!((threshold.map(100), threshold.map(101)), (0, 255));
+        let mut img = GrayImage::from_fn(4, 1, |x, _| Luma([[0, 50, 125, 200][x as usize]]));
+        apply_levels(&mut img, &levels);
+        assert_eq!(img.into_raw(), vec![0, 0, 128, 255]);
+    }
+
+    #[test]
+    fn the_measured_width_matches_the_rendered_length() {
+        let Some(font) = test_font() else { return };
+        let options = TextOptions { size: 60.0, ..TextOptions::default() };
+        for text in ["i", "Hello", "WAV 123", "two\nlines of text"] {
+            let (width, height, baseline) = measure_text(text, &font, Scale::uniform(60.0));
+            let mut used_len = 0;
+            create_image_with_text(2000, 400, text, TEST_FONT, &options, &mut used_len).unwrap();
+            let rendered = used_len as f32 - TEXT_START_X;
+            assert!((width - rendered).abs() <= 2.0, "{}: measured {} rendered {}", text, width, rendered);
+            assert!(height > 0.0 && baseline > 0.0 && baseline < height);
+        }
+    }
+
+    #[test]
+    fn an_svg_is_rasterized_at_the_label_height() {
+        let path = crate::fonts::tests::test_dir("svg").join("logo.svg");
+        fs::write(&path, r#"<svg xmlns="http://www.w3.org/2000/svg" width="200" height="100"><rect x="100" y="0" width="100" height="100" fill="black"/></svg>"#).unwrap();
+        let img = load_source_image(&path, 96).unwrap().to_luma8();
+        assert_eq!(img.dimensions(), (192, 96));
+        assert_eq!(img.get_pixel(40, 48)[0], 255);
+        assert_eq!(img.get_pixel(150, 48)[0], 0);
+        fs::write(&path, "<svg").unwrap();
+        assert!(matches!(load_source_image(&path, 96), Err(LabelError::InvalidInput(_))));
+    }
+
+    #[test]
+    fn scan_lines_not_a_multiple_of_8_are_padded_or_continuous() {
+        // 3 lines of 5 pixels, all black
+        let img = ImageBuffer::from_pixel(3, 5, Luma([0u8]));
+        let continuous = PackingOptions::default();
+        assert_eq!(get_bitmap_data_with(&img, 5, 3, &continuous), vec![0xff, 0xfe]);
+        let padded = PackingOptions { pad_rows: true, ..PackingOptions::default() };
+        assert_eq!(get_bitmap_data_with(&img, 5, 3, &padded), vec![0xf8, 0xf8, 0xf8]);
+        let lsb = PackingOptions { pad_rows: true, lsb_first: true };
+        assert_eq!(get_bitmap_data_with(&img, 5, 3, &lsb), vec![0x1f, 0x1f, 0x1f]);
+        // only the top pixel of the middle line, lines are packed bottom to top
+        let mut img = ImageBuffer::from_pixel(3, 5, Luma([255u8]));
+        img.put_pixel(1, 0, Luma([0u8]));
+        for options in [continuous, padded, lsb] {
+            let data = get_bitmap_data_with(&img, 5, 3, &options);
+            assert_eq!(unpack_bitmap_data(&data, 5, 3, &options), img, "{:?}", options);
+        }
+        assert_eq!(get_bitmap_data_with(&img, 5, 3, &continuous), vec![0x00, 0x40]);
+        assert_eq!(get_bitmap_data_with(&img, 5, 3, &padded), vec![0x00, 0x08, 0x00]);
+    }
+
+    #[test]
+    fn a_lower_glyph_threshold_inks_more_pixels() {
+        if test_font().is_none() {
+            return;
+        }
+        let black_pixels = |threshold: f32| {
+            let options = TextOptions { size: 60.0, threshold, ..TextOptions::default() };
+            let mut used_len = 0;
+            let img = create_image_with_text(400, 96, "Rag", TEST_FONT, &options, &mut used_len).unwrap();
+            img.pixels().filter(|pixel| pixel[0] < 128).count()
+        };
+        let (heavy, normal, light) = (black_pixels(0.2), black_pixels(0.5), black_pixels(0.8));
+        assert!(heavy > normal && normal > light, "{} {} {}", heavy, normal, light);
+        assert!(light > 0);
+    }
+
+    #[test]
+    fn snapped_glyphs_sit_on_whole_pixels() {
+        let Some(font) = test_font() else { return };
+        let glyphs = layout_line(&font, "Hello, world", Scale::uniform(13.0), 0.3, 10.4, None);
+        assert!(glyphs.iter().any(|glyph| glyph.position().x.fract() != 0.0));
+        let snapped = snap_glyphs(glyphs.clone());
+        for (glyph, snapped) in glyphs.iter().zip(&snapped) {
+            let (before, after) = (glyph.position(), snapped.position());
+            assert_eq!((after.x.fract(), after.y.fract()), (0.0, 0.0));
+            assert!((before.x - after.x).abs() <= 0.5 && (before.y - after.y).abs() <= 0.5);
+        }
+    }
+
+    #[test]
+    fn the_self_test_detects_an_empty_rendering() {
+        assert_eq!(printed_pixels(&ImageBuffer::from_pixel(40, 8, Luma([255u8]))), 0);
+        assert_eq!(printed_pixels(&ImageBuffer::from_fn(40, 8, |x, _| Luma([if x < 5 { 127 } else { 128 }]))), 40);
+        let broken = crate::fonts::tests::test_dir("self_test").join("broken.ttf");
+        fs::write(&broken, b"no font").unwrap();
+        assert!(matches!(self_test(broken.to_str().unwrap()), Err(LabelError::Font(_))));
+        if test_font().is_some() {
+            assert!(self_test(TEST_FONT).unwrap() > 0);
+        }
+    }
+
+    #[test]
+    fn vertical_clip_finds_accents_above_the_label() {
+        let Some(font) = test_font() else { return };
+        let options = TextOptions { size: 90.0, baseline: 60.0, ..TextOptions::default() };
+        let clip = vertical_clip(&font, "ÉÅÖ", TEXT_START_X, 400.0, 96, &options);
+        assert!(clip.above > 0);
+        assert_eq!(clip.below, 0);
+        assert!(!vertical_clip(&font, "ace", TEXT_START_X, 400.0, 96, &options).is_clipped());
+    }
+
+    #[test]
+    fn the_baseline_moves_descenders_onto_the_label() {
+        let Some(font) = test_font() else { return };
+        let low = TextOptions { size: 60.0, baseline: 90.0, ..TextOptions::default() };
+        let clip = vertical_clip(&font, "gjpq", TEXT_START_X, 400.0, 96, &low);
+        assert!(clip.below > 0 && clip.above == 0, "{:?}", clip);
+        let raised = TextOptions { baseline: 70.0, ..low };
+        assert_eq!(vertical_clip(&font, "gjpq", TEXT_START_X, 400.0, 96, &raised), VerticalClip::default());
+    }
+
+    #[test]
+    fn letterbox_keeps_the_aspect_ratio_and_centers() {
+        let img = DynamicImage::ImageLuma8(GrayImage::from_pixel(20, 10, Luma([0])));
+        let boxed = resize_letterbox(&img, 40, 40, 255, ResizeFilter::Nearest);
+        assert_eq!(boxed.dimensions(), (40, 40));
+        assert_eq!(boxed.get_pixel(20, 5)[0], 255);
+        assert_eq!(boxed.get_pixel(20, 15)[0], 0);
+        assert_eq!(boxed.get_pixel(20, 25)[0], 0);
+        assert_eq!(boxed.get_pixel(20, 35)[0], 255);
+    }
+
+    #[test]
+    fn a_letterbox_pads_the_sides_with_the_fill() {
+        let img = DynamicImage::ImageLuma8(GrayImage::from_pixel(10, 20, Luma([255])));
+        let boxed = resize_letterbox(&img, 40, 40, 0, ResizeFilter::Nearest);
+        assert_eq!(boxed.dimensions(), (40, 40));
+        // the image is 20 wide in the middle, both bars are filled black
+        for y in [0, 20, 39] {
+            assert!((0..10).chain(30..40).all(|x| boxed.get_pixel(x, y)[0] == 0));
+            assert!((10..30).all(|x| boxed.get_pixel(x, y)[0] == 255));
+        }
+        let gray = resize_letterbox(&img, 40, 40, 200, ResizeFilter::Nearest);
+        assert_eq!((gray.get_pixel(2, 2)[0], gray.get_pixel(37, 37)[0]), (200, 200));
+    }
+
+    #[test]
+    fn letterbox_into_an_empty_box_does_not_panic() {
+        let img = DynamicImage::ImageLuma8(GrayImage::from_pixel(20, 10, Luma([0])));
+        assert_eq!(resize_letterbox(&img, 0, 40, 255, ResizeFilter::default()).dimensions(), (0, 40));
+        assert_eq!(resize_letterbox(&img, 40, 0, 255, ResizeFilter::default()).dimensions(), (40, 0));
+    }
+
+    #[test]
+    fn the_exact_preview_matches_the_packed_bits() {
+        // gray tones on purpose, the preview shows what the threshold of the packing makes of them
+        let img = ImageBuffer::from_fn(37, 21, |x, y| Luma([((x * 7 + y * 13) % 256) as u8]));
+        let bits = ImageBuffer::from_fn(37, 21, |x, y| Luma([if img.get_pixel(x, y)[0] < 128 { 0u8 } else { 255 }]));
+        for options in [PackingOptions::default(), PackingOptions { pad_rows: true, lsb_first: true }] {
+            let packed = get_bitmap_data_with(&img, 21, 37, &options);
+            let preview = unpack_bitmap_data(&packed, 21, 37, &options);
+            assert_eq!(preview, bits);
+            assert_eq!(get_bitmap_data_with(&preview, 21, 37, &options), packed);
+        }
+        // missing data stays white
+        let short = unpack_bitmap_data(&[0xff], 21, 37, &PackingOptions::default());
+        assert_eq!(printed_pixels(&short), 8);
+    }
+
+    #[test]
+    fn auto_levels_expand_a_low_contrast_gradient() {
+        // 100..=150 from left to right, with two outliers
+        let mut img = ImageBuffer::from_fn(204, 10, |x, _| Luma([(100 + x * 50 / 203) as u8]));
+        img.put_pixel(0, 0, Luma([0]));
+        img.put_pixel(1, 0, Luma([255]));
+        let levels = auto_levels_for(&img, AUTO_LEVELS_CLIP);
+        assert_eq!((levels.black, levels.white), (100, 150));
+        apply_levels(&mut img, &levels);
+        let (min, max) = img.pixels().skip(2).fold((255, 0), |(min, max), pixel| (pixel[0].min(min), pixel[0].max(max)));
+        assert_eq!((min, max), (0, 255));
+        // without clipping the outliers keep the range
+        let mut img = ImageBuffer::from_fn(204, 10, |x, _| Luma([(100 + x * 50 / 203) as u8]));
+        img.put_pixel(0, 0, Luma([0]));
+        assert_eq!(auto_levels_for(&img, 0.0).black, 0);
+        assert_eq!(auto_levels_for(&ImageBuffer::from_pixel(4, 4, Luma([90u8])), AUTO_LEVELS_CLIP), Levels::default());
+    }
+
+    #[test]
+    fn the_line_spacing_moves_the_following_lines() {
+        let Some(font) = test_font() else { return };
+        // top and bottom row of the ink of the second line
+        let second_line = |line_spacing: f32| {
+            let options = TextOptions { size: 30.0, line_spacing, ..TextOptions::default() };
+            let mut used_len = 0;
+            let img = create_image_with_text(200, 400, "H\nH", TEST_FONT, &options, &mut used_len).unwrap();
+            let rows: Vec<u32> = (0..400).filter(|&y| (0..200).any(|x| img.get_pixel(x, y)[0] == 0)).collect();
+            let start = rows.windows(2).find(|pair| pair[1] > pair[0] + 1).unwrap()[1];
+            (rows[0], start)
+        };
+        let (first_top, normal) = second_line(1.0);
+        let (same_top, wide) = second_line(2.0);
+        assert_eq!(first_top, same_top);
+        // the distance of the lines doubles
+        let v_metrics = font.v_metrics(Scale::uniform(30.0));
+        let advance = v_metrics.ascent - v_metrics.descent + v_metrics.line_gap;
+        assert!(((wide - normal) as f32 - advance).abs() <= 1.0, "{} {} {}", normal, wide, advance);
+        // two lines of 30 px fit the label of 96 only with the normal spacing
+        let options = TextOptions { size: 30.0, baseline: 30.0, ..TextOptions::default() };
+        assert!(text_overflow(&font, "H\nH", 200.0, 96.0, &options) <= 0.0);
+        assert!(text_overflow(&font, "H\nH", 200.0, 96.0, &TextOptions { line_spacing: 2.0, ..options }) > 0.0);
+    }
+
+    #[test]
+    fn every_resize_filter_gives_another_result() {
+        // a logo with hard edges
+        let logo = DynamicImage::ImageLuma8(GrayImage::from_fn(30, 30, |x, y| Luma([if (x / 5 + y / 7) % 2 == 0 { 0 } else { 255 }])));
+        let scaled: Vec<GrayImage> = ResizeFilter::ALL.iter().map(|filter| prepare_gray_image(&logo, 96, &Levels::default(), *filter)).collect();
+        for (i, a) in scaled.iter().enumerate() {
+            assert_eq!(a.dimensions(), (96, 96));
+            for b in &scaled[i + 1..] {
+                assert_ne!(a, b);
+            }
+        }
+        // nearest adds no gray tones
+        let nearest = prepare_gray_image(&logo, 96, &Levels::default(), ResizeFilter::Nearest);
+        assert!(nearest.pixels().all(|pixel| pixel[0] == 0 || pixel[0] == 255));
+        assert_eq!(ResizeFilter::from_name("gaussian"), ResizeFilter::Gaussian);
+        assert_eq!(ResizeFilter::from_name("bicubic"), ResizeFilter::Lanczos3);
+    }
+
+    #[test]
+    fn each_overflow_policy_handles_an_over_wide_text() {
+        let Some(font) = test_font() else { return };
+        let text = "Storage box number twelve";
+        let width = 300.0;
+        let options = |overflow| TextOptions { size: 40.0, overflow, ..TextOptions::default() };
+        // a text which fits stays as it is with every policy
+        for overflow in Overflow::ALL {
+            let fitted = fit_text_to_width(&font, "Box", width, 96.0, &options(overflow)).unwrap();
+            assert_eq!((fitted.text.as_str(), fitted.applied), ("Box", None));
+        }
+        let clipped = fit_text_to_width(&font, text, width, 96.0, &options(Overflow::Clip)).unwrap();
+        assert_eq!((clipped.text.as_str(), clipped.options.size, clipped.applied), (text, 40.0, Some(Overflow::Clip)));
+        let shrunk = fit_text_to_width(&font, text, width, 96.0, &options(Overflow::Shrink)).unwrap();
+        assert!(shrunk.options.size < 40.0);
+        assert!(text_width(&font, text, shrunk.options.size, None) <= width + 0.5);
+        let wrapped = fit_text_to_width(&font, text, width, 96.0, &options(Overflow::Wrap)).unwrap();
+        assert!(wrapped.text.lines().count() > 1);
+        assert_eq!(wrapped.text.split_whitespace().collect::<Vec<_>>(), text.split_whitespace().collect::<Vec<_>>());
+        assert!(wrapped.text.lines().all(|line| text_width(&font, line, 40.0, None) <= width));
+        assert!(matches!(fit_text_to_width(&font, text, width, 96.0, &options(Overflow::Error)), Err(LabelError::InvalidInput(_))));
+        // a word wider than the label is broken between its characters
+        let long_word = fit_text_to_width(&font, "Donaudampfschifffahrt", 150.0, 96.0, &options(Overflow::Wrap)).unwrap();
+        assert_eq!(long_word.text.replace('\n', ""), "Donaudampfschifffahrt");
+        assert!(long_word.text.lines().count() > 1);
+    }
+
+    #[test]
+    fn the_histogram_counts_every_gray_value() {
+        // 10 black, 20 at 128 and 30 white pixels
+        let img = ImageBuffer::from_fn(60, 1, |x, _| Luma([match x { 0..10 => 0u8, 10..30 => 128, _ => 255 }]));
+        let histogram = luma_histogram(&img);
+        assert_eq!((histogram[0], histogram[128], histogram[255]), (10, 20, 30));
+        assert_eq!(histogram.iter().sum::<usize>(), 60);
+        assert_eq!(histogram.iter().filter(|&&count| count > 0).count(), 3);
+        assert_eq!(luma_histogram(&ImageBuffer::new(0, 0)), [0; 256]);
+    }
+
+    #[test]
+    fn the_serpentine_dither_has_no_diagonal_drift() {
+        // pixels with the color of their upper left and of their upper right neighbour
+        let diagonals = |img: &ImageBuffer<Luma<u8>, Vec<u8>>| {
+            let (mut down_right, mut down_left) = (0, 0);
+            for y in 1..img.height() {
+                for x in 1..img.width() - 1 {
+                    let color = img.get_pixel(x, y)[0];
+                    down_right += (img.get_pixel(x - 1, y - 1)[0] == color) as i32;
+                    down_left += (img.get_pixel(x + 1, y - 1)[0] == color) as i32;
+                }
+            }
+            (down_right - down_left).abs() as f32 / (down_right + down_left) as f32
+        };
+        let mut flat = ImageBuffer::from_pixel(128, 64, Luma([100u8]));
+        let mut serpentine = flat.clone();
+        binarize_with(&mut flat, Dither::FloydSteinberg);
+        binarize_with(&mut serpentine, Dither::Serpentine);
+        // the left to right scan of every row leaves diagonal streaks
+        assert!(diagonals(&flat) > 0.1, "{}", diagonals(&flat));
+        assert!(diagonals(&serpentine) < 0.05, "{}", diagonals(&serpentine));
+        // the tones of a gradient are kept
+        let mut gradient = ImageBuffer::from_fn(128, 64, |x, _| Luma([(x * 2) as u8]));
+        binarize_with(&mut gradient, Dither::Serpentine);
+        assert!(gradient.pixels().all(|pixel| pixel[0] == 0 || pixel[0] == 255));
+        for band in 0..8 {
+            let white = (band * 16..band * 16 + 16).flat_map(|x| (0..64).map(move |y| (x, y))).filter(|&(x, y)| gradient.get_pixel(x, y)[0] == 255).count();
+            let expected = (band * 32 + 15) as f32 / 255.0;
+            assert!((white as f32 / (16.0 * 64.0) - expected).abs() < 0.03, "band {}: {}", band, white);
+        }
+    }
+
+    #[test]
+    fn a_two_color_label_is_packed_as_black_and_red_plane() {
+        // 4 lines of 8 pixels: black, red, blank and half black half red
+        let img = ImageBuffer::from_fn(4, 8, |x, y| Luma([match (x, y < 4) {
+            (0, _) | (3, true) => 0u8,
+            (1, _) | (3, false) => RED_INK,
+            _ => 255,
+        }]));
+        let (black, red) = split_planes(&img);
+        assert_eq!((black.get_pixel(0, 0)[0], red.get_pixel(0, 0)[0]), (0, 255));
+        assert_eq!((black.get_pixel(1, 0)[0], red.get_pixel(1, 0)[0]), (255, 0));
+        assert_eq!((black.get_pixel(2, 0)[0], red.get_pixel(2, 0)[0]), (255, 255));
+        let data = get_two_color_data(&img, 8, 4, &PackingOptions::default());
+        // one byte per line and plane, a line is packed bottom to top
+        assert_eq!(data, vec![0xff, 0x00, 0x00, 0x0f, 0x00, 0xff, 0x00, 0xf0]);
+        assert_eq!(&data[..4], get_bitmap_data(&black, 8, 4).as_slice());
+    }
+
+    #[test]
+    fn a_nudged_font_size_stays_within_the_offered_sizes() {
+        assert_eq!(nudge_font_size(96, 1), 96 + FONT_SIZE_STEP);
+        assert_eq!(nudge_font_size(96, -3), 96 - 3 * FONT_SIZE_STEP);
+        assert_eq!(nudge_font_size(MAX_FONT_SIZE - 1, 1), MAX_FONT_SIZE);
+        assert_eq!(nudge_font_size(MIN_FONT_SIZE + 1, -1), MIN_FONT_SIZE);
+        assert_eq!(nudge_font_size(MIN_FONT_SIZE, i32::MIN), MIN_FONT_SIZE);
+        assert_eq!(nudge_font_size(MAX_FONT_SIZE, i32::MAX), MAX_FONT_SIZE);
+        // a size beyond the limits, e.g. from an old config, comes back in
+        assert_eq!(nudge_font_size(500, 0), MAX_FONT_SIZE);
+    }
+
+    #[test]
+    fn a_tall_image_is_turned_and_a_wide_one_is_not() {
+        assert_eq!(best_orientation(100, 400, 96, 2000), Orientation::Rotated);
+        assert_eq!(best_orientation(400, 100, 96, 2000), Orientation::Upright);
+        assert_eq!(best_orientation(300, 300, 96, 2000), Orientation::Upright);
+        // turned, the tall image is shown 4 times larger
+        let (upright, rotated) = orientation_scales(100, 400, 96, 2000);
+        assert_eq!((upright, rotated), (0.24, 0.96));
+        // a wide image longer than the tape is still better upright
+        assert_eq!(orientation_scales(4000, 100, 96, 1000), (0.25, 0.024));
+        assert_eq!(best_orientation(4000, 100, 96, 1000), Orientation::Upright);
+        // the choice of the user wins over auto
+        let tall = DynamicImage::ImageLuma8(GrayImage::new(100, 400));
+        let element = |orientation| crate::elements::ImageElement::new(tall.clone(), 0, 0).with_orientation(orientation);
+        assert!(element(Orientation::Auto).is_rotated(96));
+        assert!(!element(Orientation::Upright).is_rotated(96));
+        assert_eq!(element(Orientation::Auto).gray(96).dimensions(), (384, 96));
+    }
+
+    #[test]
+    fn despeckle_removes_dots_and_pinholes_but_keeps_lines() {
+        let mut img = ImageBuffer::from_pixel(12, 12, Luma([255u8]));
+        // a line of one pixel along the top
+        for x in 0..12 {
+            img.put_pixel(x, 0, Luma([0u8]));
+        }
+        // a black block with a pinhole
+        for y in 4..9 {
+            for x in 5..10 {
+                img.put_pixel(x, y, Luma([0u8]));
+            }
+        }
+        img.put_pixel(7, 6, Luma([255u8]));
+        // a dot inside and one in the corner
+        img.put_pixel(2, 5, Luma([0u8]));
+        img.put_pixel(11, 11, Luma([0u8]));
+        let original = img.clone();
+
+        assert_eq!(despeckle(&mut img), 3);
+        for (x, y, pixel) in img.enumerate_pixels() {
+            let expected = match (x, y) {
+                (7, 6) => 0,
+                (2, 5) | (11, 11) => 255,
+                _ => original.get_pixel(x, y)[0],
+            };
+            assert_eq!(pixel[0], expected, "pixel {},{}", x, y);
+        }
+        // a clean label stays as it is
+        assert_eq!(despeckle(&mut img), 0);
+        let mut single = ImageBuffer::from_pixel(1, 1, Luma([0u8]));
+        assert_eq!(despeckle(&mut single), 0);
+    }
+}
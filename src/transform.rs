@@ -0,0 +1,295 @@
+//! Transformations of the finished label bitmap before it is packed.
+
+use image::{ImageBuffer, Luma, imageops::{self, FilterType}};
+use serde::{Deserialize, Serialize};
+
+/// true if any pixel of column `x` is printed
+fn column_has_ink(img: &ImageBuffer<Luma<u8>, Vec<u8>>, x: u32) -> bool {
+    (0..img.height()).any(|y| img.get_pixel(x, y)[0] < 128)
+}
+
+/// move the content to the left edge, removing the blank leading columns.
+/// returns the shifted image (same size) and the width of the content without
+/// the blank leading and trailing columns, 0 for a blank image
+pub fn trim_blank_columns(img: &ImageBuffer<Luma<u8>, Vec<u8>>) -> (ImageBuffer<Luma<u8>, Vec<u8>>, u32) {
+    match content_columns(img) {
+        Some((first, trimmed_width)) => (shift_left(img, first), trimmed_width),
+        None => (img.clone(), 0),
+    }
+}
+
+/// first column with ink and the width up to the last one, `None` for a blank image
+pub fn content_columns(img: &ImageBuffer<Luma<u8>, Vec<u8>>) -> Option<(u32, u32)> {
+    let width = img.width();
+    let first = (0..width).find(|&x| column_has_ink(img, x))?;
+    let last = (first..width).rev().find(|&x| column_has_ink(img, x)).unwrap_or(first);
+    Some((first, last - first + 1))
+}
+
+/// move the content `columns` columns to the left, the size stays
+pub fn shift_left(img: &ImageBuffer<Luma<u8>, Vec<u8>>, columns: u32) -> ImageBuffer<Luma<u8>, Vec<u8>> {
+    let (width, height) = img.dimensions();
+    let mut shifted = ImageBuffer::from_pixel(width, height, Luma([255u8]));
+    let columns = columns.min(width);
+    imageops::replace(&mut shifted, &imageops::crop_imm(img, columns, 0, width - columns, height).to_image(), 0, 0);
+    shifted
+}
+
+/// flip the first `used_len` columns horizontally for printing on the back of a transparent
+/// label. only the used part is flipped, so the label still starts at column 0 and keeps its length
+pub fn mirror_used_columns(img: &ImageBuffer<Luma<u8>, Vec<u8>>, used_len: u32) -> ImageBuffer<Luma<u8>, Vec<u8>> {
+    let (width, height) = img.dimensions();
+    let used_len = used_len.min(width);
+    let mut mirrored = img.clone();
+    if used_len > 0 {
+        let flipped = imageops::flip_horizontal(&*imageops::crop_imm(img, 0, 0, used_len, height));
+        imageops::replace(&mut mirrored, &flipped, 0, 0);
+    }
+    mirrored
+}
+
+/// shift the content by `start` blank columns for a printer which can't print the first
+/// columns and add `end` blank columns to the used length. returns the image and the new length
+pub fn add_margins(img: &ImageBuffer<Luma<u8>, Vec<u8>>, used_len: u32, start: u32, end: u32) -> (ImageBuffer<Luma<u8>, Vec<u8>>, u32) {
+    let (width, height) = img.dimensions();
+    let mut shifted = ImageBuffer::from_pixel(width, height, Luma([255u8]));
+    imageops::replace(&mut shifted, img, start as i64, 0);
+    (shifted, used_len.saturating_add(start).saturating_add(end).min(width))
+}
+
+/// keep only the `width` x `height` region at `x`, `y` and move it to column 0, the rows stay.
+/// the region is clamped to the image. returns the image (same size) and the region width
+pub fn crop_region(img: &ImageBuffer<Luma<u8>, Vec<u8>>, x: u32, y: u32, width: u32, height: u32) -> (ImageBuffer<Luma<u8>, Vec<u8>>, u32) {
+    let (img_width, img_height) = img.dimensions();
+    let (x, y) = (x.min(img_width), y.min(img_height));
+    let (width, height) = (width.min(img_width - x), height.min(img_height - y));
+    let mut cropped = ImageBuffer::from_pixel(img_width, img_height, Luma([255u8]));
+    imageops::replace(&mut cropped, &*imageops::crop_imm(img, x, y, width, height), 0, y as i64);
+    (cropped, width)
+}
+
+/// put `columns` blank columns in front of the whole label for alignment or tear-off, the
+/// length grows by them. unlike the margins of a profile this is part of the label itself,
+/// content pushed beyond the width is lost. returns the image and the new length
+pub fn add_lead_in(img: &ImageBuffer<Luma<u8>, Vec<u8>>, used_len: u32, columns: u32) -> (ImageBuffer<Luma<u8>, Vec<u8>>, u32) {
+    add_margins(img, used_len, columns, 0)
+}
+
+/// round `used_len` up to a multiple of `multiple` for labels of equal lengths, 0 and 1
+/// keep it. the result is at most `max_len`, even if that is no multiple
+pub fn snap_length(used_len: u32, multiple: u32, max_len: u32) -> u32 {
+    if multiple <= 1 {
+        return used_len;
+    }
+    used_len.div_ceil(multiple).saturating_mul(multiple).min(max_len)
+}
+
+/// widen `img` with white columns to at least `width` columns
+pub fn pad_to_width(img: &ImageBuffer<Luma<u8>, Vec<u8>>, width: u32) -> ImageBuffer<Luma<u8>, Vec<u8>> {
+    if img.width() >= width {
+        return img.clone();
+    }
+    let mut padded = ImageBuffer::from_pixel(width, img.height(), Luma([255u8]));
+    imageops::replace(&mut padded, img, 0, 0);
+    padded
+}
+
+/// repeat the first `used_len` columns with `gap` blank columns in between until `length`
+/// columns are filled, the last repetition is cut at `length`. the result is at least
+/// `length` wide and has the length `length`, a blank content stays blank
+pub fn repeat_to_length(img: &ImageBuffer<Luma<u8>, Vec<u8>>, used_len: u32, length: u32, gap: u32) -> ImageBuffer<Luma<u8>, Vec<u8>> {
+    let (width, height) = img.dimensions();
+    let used_len = used_len.min(width);
+    let mut tiled = ImageBuffer::from_pixel(width.max(length), height, Luma([255u8]));
+    if used_len == 0 {
+        return tiled;
+    }
+    let tile = imageops::crop_imm(img, 0, 0, used_len, height).to_image();
+    let mut x = 0;
+    while x < length {
+        let part = used_len.min(length - x);
+        imageops::replace(&mut tiled, &*imageops::crop_imm(&tile, 0, 0, part, height), x as i64, 0);
+        x = x.saturating_add(used_len).saturating_add(gap);
+    }
+    tiled
+}
+
+/// stretch factors beyond these limits visibly distort the content
+const MAX_STRETCH: f32 = 1.25;
+const MIN_STRETCH: f32 = 0.8;
+
+/// scale the first `used_len` columns horizontally to exactly `length` columns, the height is
+/// kept. the result has the width of `img` (or `length` if that is wider) and is white after
+/// `length`. a strong change of the aspect ratio is logged as a warning
+pub fn scale_to_length(img: &ImageBuffer<Luma<u8>, Vec<u8>>, used_len: u32, length: u32) -> ImageBuffer<Luma<u8>, Vec<u8>> {
+    let (width, height) = img.dimensions();
+    let used_len = used_len.min(width);
+    let mut scaled = ImageBuffer::from_pixel(width.max(length), height, Luma([255u8]));
+    if used_len == 0 || length == 0 {
+        return scaled;
+    }
+    let stretch = length as f32 / used_len as f32;
+    if !(MIN_STRETCH..=MAX_STRETCH).contains(&stretch) {
+        eprintln!("Warning: label scaled from {} to {} columns, the content is distorted by a factor of {:.2}", used_len, length, stretch);
+    }
+    // nearest keeps the pixels black or white
+    let content = imageops::resize(&*imageops::crop_imm(img, 0, 0, used_len, height), length, height, FilterType::Nearest);
+    imageops::replace(&mut scaled, &content, 0, 0);
+    scaled
+}
+
+/// black rectangle for the gap sensor of die-cut labels, in pixels of the final label
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct RegistrationMark {
+    pub enabled: bool,
+    pub x: u32,
+    pub y: u32,
+    pub width: u32,
+    /// rows, 0 reaches down to the bottom edge whatever the head height of the printer
+    pub height: u32,
+}
+
+impl Default for RegistrationMark {
+    fn default() -> Self {
+        // a bar over the full height at the leading edge
+        RegistrationMark { enabled: false, x: 0, y: 0, width: 8, height: 0 }
+    }
+}
+
+/// draw `mark` clipped to the image. returns the used length, extended to the end of the mark
+pub fn draw_registration_mark(img: &mut ImageBuffer<Luma<u8>, Vec<u8>>, used_len: u32, mark: &RegistrationMark) -> u32 {
+    let (width, height) = img.dimensions();
+    let x_end = mark.x.saturating_add(mark.width).min(width);
+    let y_end = match mark.height {
+        0 => height,
+        rows => mark.y.saturating_add(rows).min(height),
+    };
+    for x in mark.x.min(width)..x_end {
+        for y in mark.y.min(height)..y_end {
+            img.put_pixel(x, y, Luma([0u8]));
+        }
+    }
+    if x_end > mark.x { used_len.max(x_end) } else { used_len }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// a white `width` x 8 image, black in the columns `from..to`
+    fn stripe(width: u32, from: u32, to: u32) -> ImageBuffer<Luma<u8>, Vec<u8>> {
+        ImageBuffer::from_fn(width, 8, |x, _| Luma([if (from..to).contains(&x) { 0 } else { 255 }]))
+    }
+
+    #[test]
+    fn blank_borders_are_trimmed() {
+        let (trimmed, width) = trim_blank_columns(&stripe(40, 10, 25));
+        assert_eq!(width, 15);
+        assert_eq!(trimmed.dimensions(), (40, 8));
+        assert_eq!(content_columns(&trimmed), Some((0, 15)));
+        // a blank label has nothing to print
+        assert_eq!(trim_blank_columns(&stripe(40, 0, 0)).1, 0);
+        assert_eq!(content_columns(&stripe(40, 0, 40)), Some((0, 40)));
+    }
+
+    #[test]
+    fn mirroring_twice_gives_the_original() {
+        let img = ImageBuffer::from_fn(40, 8, |x, y| Luma([if x < 5 || (x == 12 && y == 3) { 0 } else { 255 }]));
+        let mirrored = mirror_used_columns(&img, 20);
+        assert_eq!(mirrored.dimensions(), (40, 8));
+        // the content is flipped within the used length, the rest stays blank
+        assert_eq!(content_columns(&mirrored), Some((7, 13)));
+        assert_eq!(mirrored.get_pixel(7, 3)[0], 0);
+        assert_eq!(mirrored.get_pixel(19, 0)[0], 0);
+        assert_eq!(mirror_used_columns(&mirrored, 20), img);
+    }
+
+    #[test]
+    fn a_label_is_scaled_to_the_exact_length() {
+        let img = stripe(100, 0, 10);
+        let scaled = scale_to_length(&img, 20, 60);
+        assert_eq!(scaled.dimensions(), (100, 8));
+        assert_eq!(content_columns(&scaled), Some((0, 30)));
+        // longer than the buffer, the buffer grows
+        let scaled = scale_to_length(&img, 10, 150);
+        assert_eq!(scaled.dimensions(), (150, 8));
+        assert_eq!(content_columns(&scaled), Some((0, 150)));
+        assert_eq!(content_columns(&scale_to_length(&img, 0, 60)), None);
+    }
+
+    #[test]
+    fn the_registration_mark_is_drawn_and_clipped() {
+        let mut img = stripe(60, 0, 0);
+        let mark = RegistrationMark { enabled: true, x: 50, y: 2, width: 20, height: 3 };
+        assert_eq!(draw_registration_mark(&mut img, 30, &mark), 60);
+        let black: Vec<(u32, u32)> = img.enumerate_pixels().filter(|(_, _, p)| p[0] == 0).map(|(x, y, _)| (x, y)).collect();
+        assert_eq!(black.len(), 10 * 3);
+        assert!(black.iter().all(|&(x, y)| (50..60).contains(&x) && (2..5).contains(&y)));
+        // a mark inside the label keeps its length
+        let mut img = stripe(60, 0, 0);
+        assert_eq!(draw_registration_mark(&mut img, 30, &RegistrationMark::default()), 30);
+        assert!((0..8).all(|y| img.get_pixel(7, y)[0] == 0 && img.get_pixel(8, y)[0] == 255));
+        // the default covers the full height of a taller head as well
+        let mut tall = ImageBuffer::from_pixel(60, 576, Luma([255u8]));
+        draw_registration_mark(&mut tall, 30, &RegistrationMark::default());
+        assert!((0..576).all(|y| tall.get_pixel(0, y)[0] == 0));
+    }
+
+    #[test]
+    fn the_length_is_rounded_up_and_padded() {
+        assert_eq!(snap_length(61, 8, 1000), 64);
+        assert_eq!(snap_length(64, 8, 1000), 64);
+        assert_eq!(snap_length(61, 0, 1000), 61);
+        assert_eq!(snap_length(61, 1, 1000), 61);
+        // never longer than the label
+        assert_eq!(snap_length(995, 8, 1000), 1000);
+        assert_eq!(snap_length(u32::MAX - 1, 8, u32::MAX), u32::MAX);
+        let padded = pad_to_width(&stripe(61, 0, 61), snap_length(61, 8, 1000));
+        assert_eq!(padded.width(), 64);
+        assert_eq!(content_columns(&padded), Some((0, 61)));
+        assert_eq!(pad_to_width(&stripe(70, 0, 70), 64).width(), 70);
+    }
+
+    #[test]
+    fn a_small_buffer_is_tiled_to_the_length() {
+        // 8 used columns, black in 2..6, every 10 columns with the gap
+        let tiled = repeat_to_length(&stripe(10, 2, 6), 8, 25, 2);
+        assert_eq!(tiled.dimensions(), (25, 8));
+        let black: Vec<u32> = (0..25).filter(|&x| tiled.get_pixel(x, 3)[0] == 0).collect();
+        // the last repetition is cut at the length
+        assert_eq!(black, [2, 3, 4, 5, 12, 13, 14, 15, 22, 23, 24]);
+        // nothing used, nothing to repeat
+        assert_eq!(content_columns(&repeat_to_length(&stripe(10, 2, 6), 0, 25, 0)), None);
+        assert_eq!(repeat_to_length(&stripe(40, 2, 6), 8, 25, 0).width(), 40);
+    }
+
+    #[test]
+    fn only_the_selected_region_is_packed() {
+        // black in the columns 10..20 and 30..40, rows 0..8
+        let img = ImageBuffer::from_fn(50, 8, |x, _| Luma([if (10..20).contains(&x) || (30..40).contains(&x) { 0 } else { 255 }]));
+        let (cropped, width) = crop_region(&img, 8, 2, 14, 4);
+        assert_eq!((cropped.dimensions(), width), ((50, 8), 14));
+        let expected = ImageBuffer::from_fn(14, 8, |x, y| Luma([if (2..12).contains(&x) && (2..6).contains(&y) { 0 } else { 255 }]));
+        let packed = crate::render::get_bitmap_data(&cropped, 8, width as usize);
+        assert_eq!(packed, crate::render::get_bitmap_data(&expected, 8, 14));
+        // the second block is outside of the selection
+        assert_eq!(content_columns(&cropped), Some((2, 10)));
+        // a selection beyond the image is clamped
+        assert_eq!(crop_region(&img, 45, 0, 100, 100).1, 5);
+        assert_eq!(crop_region(&img, 80, 0, 10, 8).1, 0);
+    }
+
+    #[test]
+    fn a_lead_in_puts_blank_columns_before_the_content() {
+        let (led_in, length) = add_lead_in(&stripe(60, 0, 20), 20, 16);
+        assert_eq!(length, 36);
+        assert_eq!(content_columns(&led_in), Some((16, 20)));
+        assert!((0..16).all(|x| led_in.get_pixel(x, 0)[0] == 255));
+        // the length ends at the label, the content behind it is lost
+        let (led_in, length) = add_lead_in(&stripe(60, 0, 50), 50, 30);
+        assert_eq!(length, 60);
+        assert_eq!(content_columns(&led_in), Some((30, 30)));
+        assert_eq!(add_lead_in(&stripe(60, 0, 20), 20, 0).1, 20);
+    }
+}
@@ -0,0 +1,42 @@
+//! The library used without the GUI, like an embedding tool would.
+
+use std::path::Path;
+
+use image::{DynamicImage, GrayImage, Luma};
+use label_drawer::render::TextOptions;
+use label_drawer::{LABEL_HEIGHT, LABEL_WIDTH, create_image_with_text, get_bitmap_data, render_image};
+
+/// a font of the system, tests which need one are skipped without it
+const TEST_FONT: &str = "/usr/share/fonts/truetype/dejavu/DejaVuSans.ttf";
+
+#[test]
+fn text_is_rendered_and_packed_headless() {
+    if !Path::new(TEST_FONT).is_file() {
+        eprintln!("{} not found, test skipped", TEST_FONT);
+        return;
+    }
+    let mut used_len = 0;
+    let img = create_image_with_text(LABEL_WIDTH, LABEL_HEIGHT, "Hello", TEST_FONT, &TextOptions::default(), &mut used_len).unwrap();
+    assert_eq!(img.dimensions(), (LABEL_WIDTH as u32, LABEL_HEIGHT as u32));
+    assert!(used_len > 0 && used_len < LABEL_WIDTH);
+    assert!(img.pixels().any(|p| p[0] == 0));
+    let packed = get_bitmap_data(&img, LABEL_HEIGHT, LABEL_WIDTH);
+    assert_eq!(packed.len(), LABEL_WIDTH * LABEL_HEIGHT / 8);
+    assert!(packed.iter().any(|&byte| byte != 0));
+}
+
+#[test]
+fn a_missing_font_is_an_error() {
+    let mut used_len = 0;
+    assert!(create_image_with_text(LABEL_WIDTH, LABEL_HEIGHT, "Hello", "/nonexistent/font.ttf", &TextOptions::default(), &mut used_len).is_err());
+}
+
+#[test]
+fn an_image_is_rendered_to_the_label_height() {
+    let img = DynamicImage::ImageLuma8(GrayImage::from_pixel(50, 50, Luma([0])));
+    let (label, used_len) = render_image(&img, LABEL_WIDTH as u32, LABEL_HEIGHT as u32, 0, 0);
+    assert_eq!(label.dimensions(), (LABEL_WIDTH as u32, LABEL_HEIGHT as u32));
+    assert_eq!(used_len, LABEL_HEIGHT as u32);
+    assert_eq!(label.get_pixel(10, 10)[0], 0);
+    assert_eq!(label.get_pixel(LABEL_HEIGHT as u32 + 10, 10)[0], 255);
+}